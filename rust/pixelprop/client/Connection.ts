@@ -18,6 +18,10 @@ type UpdateData = [int, int, int, int, [string, RectArray][], ClientEvent[], Ent
 type PingDelayData = float | undefined;
 type ShootDelayData = float | undefined;
 type StaminaData = float;
+type MapInfoData = [string, int | undefined]; // Map name, seed if generated
+type AbilityCooldownData = [string, int | undefined, int | undefined]; // Ability id, cooldown if usable, charges if limited
+type LobbySettingsData = [boolean, LobbySettings]; // Is host, settings
+export type LobbySettings = [string, number, number, number, int | undefined, string[] | undefined, int | undefined]; // Map id, pre game, hiding and hunting durations, hunter count, allowed characters, rounds
 
 export type GameResultsData = [boolean, ScoreboardData, ScoreBreakdownData]; // Props win, scoreboard, score breakdown
 export type ScoreboardData = [int, boolean, string, int][]; // Entity id, is prop, username, score
@@ -29,7 +33,11 @@ enum IncomingMessageType {
     PingDelay = 2,
     ShootDelay = 3,
     Stamina = 4,
-    GameResults = 5
+    GameResults = 5,
+    MapInfo = 6,
+    AbilityCooldown = 7,
+    LobbySettings = 8,
+    ReclaimSecret = 9
 }
 
 enum OutgoingMessageType {
@@ -43,13 +51,34 @@ enum OutgoingMessageType {
     Jump = 7,
     ForcePing = 8,
     CheatCode = 9,
-    TempData = 10
+    TempData = 10,
+    Chat = 11,
+    UseAbility = 12,
+    LobbySetting = 13,
+    StartRounds = 14
+}
+
+export enum LobbySettingKind {
+    Map = 0,
+    PreGameDuration = 1,
+    HidingDuration = 2,
+    HuntingDuration = 3,
+    HunterCount = 4,
+    AllowedCharacters = 5,
+    Rounds = 6
+}
+
+export enum ChatChannel {
+    All = 0,
+    Team = 1,
+    Spectator = 2
 }
 
 export class Connection {
     // Socket components
     private socket?: WebSocket;
     private playerToken?: string;
+    private lobbyId?: string; // The lobby to join on servers that host more than one
     private get isOpen(): boolean {
         return this.socket.readyState == WebSocket.OPEN;
     }
@@ -63,6 +92,7 @@ export class Connection {
         let res = await RIVET.matchmaker.lobbies.find({ gameModes: ["default"] });
         let port = res.ports.default;
         this.playerToken = res.player.token;
+        this.lobbyId = new URLSearchParams(window.location.search).get("lobby") || undefined;
 
         // Create the server
         const protocol = port.isTls ? "wss:" : "ws:";
@@ -153,6 +183,18 @@ export class Connection {
             case IncomingMessageType.GameResults:
                 this.onGameResults(data);
                 break;
+            case IncomingMessageType.MapInfo:
+                this.onMapInfo(data);
+                break;
+            case IncomingMessageType.AbilityCooldown:
+                this.onAbilityCooldown(data);
+                break;
+            case IncomingMessageType.LobbySettings:
+                this.onLobbySettings(data);
+                break;
+            case IncomingMessageType.ReclaimSecret:
+                this.onReclaimSecret(data);
+                break;
             default:
                 console.error(`Unknown message type ${type}`);
                 break;
@@ -162,7 +204,12 @@ export class Connection {
     private onOpen(event: Event) {
         console.log("Open", event);
 
-        this.sendMessage(OutgoingMessageType.Auth, [this.playerToken]);
+        // The reclaim secret gives the player back their entity if the server restarted mid-round
+        this.sendMessage(OutgoingMessageType.Auth, [
+            this.playerToken,
+            this.lobbyId || null,
+            Storage.reclaimSecret || null
+        ]);
 
         // // Change the state
         // MainGUI.shared.setGUIState(GUIState.InitiatingGame);
@@ -191,7 +238,8 @@ export class Connection {
     public sendJoin() {
         this.sendMessage(OutgoingMessageType.Join, [
             Storage.username || generateUsername(),
-            Storage.characterId || Game.shared.storeData.characters[0].id
+            Storage.characterId || Game.shared.storeData.characters[0].id,
+            new URLSearchParams(window.location.search).get("party") || undefined
         ]);
     }
 
@@ -231,6 +279,24 @@ export class Connection {
         this.sendMessage(OutgoingMessageType.TempData, data);
     }
 
+    public sendChat(channel: ChatChannel, message: string) {
+        this.sendMessage(OutgoingMessageType.Chat, [channel, message]);
+    }
+
+    public sendUseAbility(abilityId: string) {
+        this.sendMessage(OutgoingMessageType.UseAbility, abilityId);
+    }
+
+    // Changes a setting of a custom lobby; only works for the host before the round starts.
+    public sendLobbySetting(kind: LobbySettingKind, value: any) {
+        this.sendMessage(OutgoingMessageType.LobbySetting, [kind, value]);
+    }
+
+    // Starts another set of rounds once the lobby's rounds are all played; only works for the host.
+    public sendStartRounds() {
+        this.sendMessage(OutgoingMessageType.StartRounds, undefined);
+    }
+
     /**** TEMP METHODS ****/
     public tempDumpTree() {
         this.sendTempData("dump-tree:");
@@ -306,4 +372,20 @@ export class Connection {
     private onGameResults(data: GameResultsData) {
         Game.shared.gameGUI.displayGameResults(data);
     }
+
+    private onMapInfo(data: MapInfoData) {
+        Game.shared.setMapInfo(data[0], data[1]);
+    }
+
+    private onAbilityCooldown(data: AbilityCooldownData) {
+        Game.shared.setAbilityCooldown(data[0], data[1], data[2]);
+    }
+
+    private onLobbySettings(data: LobbySettingsData) {
+        Game.shared.setLobbySettings(data[0], data[1]);
+    }
+
+    private onReclaimSecret(data: string) {
+        Storage.reclaimSecret = data;
+    }
 }
@@ -8,7 +8,7 @@ import {SpotLightPlaceholder} from "./SpotLightPlaceholder";
 
 /* Pack data */
 export type EntityId = int;
-export type EntityRotation = 0 | 1 | 2 | 3; // 0º, 90º, 180º, or 270º
+export type EntityRotation = float; // Radians
 export enum EntityPackFlags {
     Id = 0, // int
     Position = 1, // VectorArray
@@ -66,7 +66,7 @@ export class Entity {
 
     // Node
     public node: BABYLON.TransformNode; // The base node; this node is in charge of moving the translation
-    public rotNode: BABYLON.TransformNode; // A child node of `node`; this rotates with the rotation (fixed at 90º intervals)
+    public rotNode: BABYLON.TransformNode; // A child node of `node`; this rotates with the rotation
     public dirNode: BABYLON.TransformNode; // A child node of `node`; this rotates with the direction
     public labelHandle: BABYLON.AbstractMesh; // The empty mesh that the usernmae node will pin to
     private meshes: BABYLON.AbstractMesh[] = [];
@@ -170,7 +170,7 @@ export class Entity {
             this.setDir(data[EPF.Dir] as float);
 
             // Set rotation;
-            this.setRot(data[EPF.Rotation] * Math.PI / 2);
+            this.setRot(data[EPF.Rotation]);
         }
 
         // Update the state
@@ -304,7 +304,7 @@ export class Entity {
         // Update position and angle if needed
         if (this.autoPosition) {
             // Update the entity's position
-            let targetRotation = this.serverRotation * Math.PI / 2;
+            let targetRotation = this.serverRotation;
             if (Entity.smoothMovement) {
                 const time = 8 * dt;
                 this.node.position.lerp(this.serverPosition, time);
@@ -1,7 +1,7 @@
 import * as BABYLON from "babylonjs";
 import {float, int} from "./types";
 import {Entity, EntityData, EntityId, EntityRotation, EntityState, EPF} from "./Entity";
-import {Connection} from "./Connection";
+import {ChatChannel, Connection, LobbySettings} from "./Connection";
 import {InputHandler, SubscribeType} from "./InputHandler";
 import {NodeNames, StoreData, Utils} from "./Utils";
 import {Assets} from "./Assets";
@@ -15,7 +15,7 @@ import {Storage} from "./Storage";
 import {Minimap} from "./gui/game/Minimap";
 
 export enum ClientEventFlag {
-    GameState = 0, Shoot = 1, Ping = 2, PlayerDeath = 3, ScoreboardUpdate = 4
+    GameState = 0, Shoot = 1, Ping = 2, PlayerDeath = 3, ScoreboardUpdate = 4, EntityDestroyed = 5, Chat = 6
 }
 export type ClientEvent = [ClientEventFlag, any];
 
@@ -139,9 +139,20 @@ export class Game {
     private shootStart: int; // ms
     private shootDelay: int; // ms
 
+    // Abilities the player can use, in the order the server sent them
+    private abilities: { id: string, start: int, cooldown: int, charges: int | undefined }[] = [];
+
     // Stamina
     private stamina: float = 1;
 
+    // Map
+    private mapName: string = "";
+    private mapSeed: int | undefined = undefined; // Only set if the map was generated
+
+    // Custom lobby
+    public isLobbyHost: boolean = false;
+    public lobbySettings: LobbySettings | undefined = undefined; // Only set in custom lobbies
+
     // Raw input
     private mouseX: float = 0;
     private mouseY: float = 0;
@@ -526,6 +537,15 @@ export class Game {
         // Show map
         InputHandler.subscribe(["m"], () => this.gameGUI.minimap.fullMapView = InputHandler.key("m"), SubscribeType.Both);
 
+        // Abilities
+        for (let i = 1; i <= 3; i++) {
+            InputHandler.subscribe([i.toString()], () => !hasDebugModifier() && this.isPlaying && this.useAbility(i - 1));
+        }
+
+        // Chat; spectators' team chat goes to the other spectators
+        InputHandler.subscribe(["y"], () => this.promptChat(ChatChannel.All));
+        InputHandler.subscribe(["u"], () => this.promptChat(ChatChannel.Team));
+
         /* Temp Controls */
         function hasDebugModifier(): boolean { return InputHandler.key("option"); }
 
@@ -725,14 +745,19 @@ export class Game {
     }
 
     /* Input */
-    /// Rotate the player by a certain amount.
-    private rotateBy(rot: int) {
+    /// Rotate the player by a certain number of quarter turns.
+    private rotateBy(rot: float) {
         const mainPlayer = this.mainPlayer;
-        const newRotation = Math.mod(this.mainPlayer.serverRotation + rot, 4) as EntityRotation;
+        const newRotation = Math.mod(this.mainPlayer.serverRotation + rot * Math.PI / 2, Math.PI * 2) as EntityRotation;
         mainPlayer.serverRotation = newRotation;
         this.connection.sendRotate(newRotation);
     }
 
+    private promptChat(channel: ChatChannel) {
+        const message = prompt(channel == ChatChannel.Team ? "Say to team" : "Say");
+        if (message) this.connection.sendChat(channel, message);
+    }
+
     private switchObject(x: float, y: float) {
         // Pick the object
         const pickInfo = this.scene.pick(x, y, m => {
@@ -1078,6 +1103,18 @@ export class Game {
                 // Display the new scoreboard
                 this.gameGUI.scoreboard.displayData(data);
 
+                break;
+            case ClientEventFlag.EntityDestroyed:
+                // Play a sound where the entity broke; the entity itself is removed with the update
+                const breakSound = Assets.bulletHit;
+                breakSound.setPosition(Utils.arrayToVector(data[1]));
+                breakSound.play();
+
+                break;
+            case ClientEventFlag.Chat:
+                const [username, channel, message] = data;
+                console.log(`[${ChatChannel[channel]}] ${username}: ${message}`);
+
                 break;
             default:
                 console.warn("Unknown event flag", flag);
@@ -1212,6 +1249,43 @@ export class Game {
         this.stamina = stamina;
     }
 
+    public setAbilityCooldown(id: string, cooldown: int | undefined, charges: int | undefined) {
+        // Remove the ability if the player can't use it
+        const index = this.abilities.findIndex(a => a.id == id);
+        if (cooldown == undefined) {
+            if (index != -1) this.abilities.splice(index, 1);
+            return;
+        }
+
+        // Save the cooldown
+        const ability = { id, start: Date.now(), cooldown, charges };
+        if (index == -1) {
+            this.abilities.push(ability);
+        } else {
+            this.abilities[index] = ability;
+        }
+    }
+
+    private useAbility(index: int) {
+        const ability = this.abilities[index];
+        if (!ability || ability.charges === 0 || Date.now() - ability.start < ability.cooldown) return;
+        this.connection.sendUseAbility(ability.id);
+    }
+
+    public setMapInfo(name: string, seed: int | undefined) {
+        this.mapName = name;
+        this.mapSeed = seed;
+
+        if (seed != undefined) {
+            console.log(`Playing ${name} with seed ${seed}`);
+        }
+    }
+
+    public setLobbySettings(isHost: boolean, settings: LobbySettings) {
+        this.isLobbyHost = isHost;
+        this.lobbySettings = settings;
+    }
+
     /* Debug */
     private updateDebugText() {
         if (!this.debugText.isVisible) return;
@@ -1302,6 +1376,10 @@ export class Game {
             entityCount++;
         }
         text += `Entity count: ${entityCount}\n`;
+        text += `Map: ${this.mapName}${this.mapSeed != undefined ? ` (seed ${this.mapSeed})` : ""}\n`;
+        if (this.lobbySettings) {
+            text += `Custom lobby: ${JSON.stringify(this.lobbySettings)}${this.isLobbyHost ? " (host)" : ""}\n`;
+        }
         text += "\n";
 
         // Apply to text
@@ -17,6 +17,9 @@ export class Storage {
     public static get username(): string { return this.getItem("username", ""); }
     public static set username(value: string) { this.setItem("username", value); }
 
+    public static get reclaimSecret(): string { return this.getItem("reclaimSecret", ""); }
+    public static set reclaimSecret(value: string) { this.setItem("reclaimSecret", value); }
+
     public static get characterId(): string { return this.getItem("characterId", "basic"); }
     public static set characterId(value: string) { this.setItem("characterId", value); }
 
@@ -4,7 +4,7 @@ import SimplexNoise = require("simplex-noise");
 
 /* Types */
 export type VectorArray = [float, float, float];
-export type RectArray = [VectorArray, VectorArray];
+export type RectArray = [VectorArray, VectorArray, float?]; // Center, size, and yaw in radians if rotated
 
 /* Store Data */
 export interface StoreData {
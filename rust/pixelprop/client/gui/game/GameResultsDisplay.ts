@@ -22,6 +22,10 @@ export class GameResultsDisplay extends GUI.Rectangle {
             "propHealthScoring": "Prop health",
             "propPingCountScoring": "Ping count",
             "propTotalPingVolumeScoring": "Total ping volume",
+            "sleepingScoring": "Time asleep",
+            "survivedHuntScoring": "Survived the hunt",
+            "tauntScoring": "Taunts",
+            "decoyPingScoring": "Decoy pings",
             "winningTeamScoring": "Winning team",
             "losingTeamScoring": "Losing team",
             "firstScoring": "First place",
@@ -54,7 +58,7 @@ export class GameResultsDisplay extends GUI.Rectangle {
                 continue;
             }
 
-            const text = new GUI.TextBlock("Label", TEMPTranslationLookup[breakdown[0]].toUpperCase());
+            const text = new GUI.TextBlock("Label", (TEMPTranslationLookup[breakdown[0]] || breakdown[0]).toUpperCase());
             if (breakdown[1]) { text.text += " x " + breakdown[1]; }
             text.width = 0.8;
             text.fontSize = 15;
@@ -141,12 +141,15 @@ export class Minimap extends BABYLON.GUI.Ellipse {
         let maxY = Number.NEGATIVE_INFINITY;
         for (let body of rects) {
             for (let rect of body[1]) {
-                const [center, size] = rect;
+                const [center, size, yaw = 0] = rect;
 
-                let lowerX = center[0] - size[0] / 2;
-                let upperX = center[0] + size[0] / 2;
-                let lowerY = center[1] - size[1] / 2;
-                let upperY = center[1] + size[1] / 2;
+                // Find the extent of the rotated rect
+                const halfWidth = (size[0] * Math.abs(Math.cos(yaw)) + size[1] * Math.abs(Math.sin(yaw))) / 2;
+                const halfHeight = (size[0] * Math.abs(Math.sin(yaw)) + size[1] * Math.abs(Math.cos(yaw))) / 2;
+                let lowerX = center[0] - halfWidth;
+                let upperX = center[0] + halfWidth;
+                let lowerY = center[1] - halfHeight;
+                let upperY = center[1] + halfHeight;
 
                 if (lowerX < minX) minX = lowerX;
                 if (upperX > maxX) maxX = upperX;
@@ -167,7 +170,7 @@ export class Minimap extends BABYLON.GUI.Ellipse {
 
             // Render the rectangles
             for (let rect of body[1]) {
-                const [center, size] = rect;
+                const [center, size, yaw = 0] = rect;
 
                 // Determine the height of the top of the object and calculate the shade from the height; we use an inverse
                 // curve on the height in order to be able to show depth for really tall items vs normally tall items; see
@@ -175,13 +178,17 @@ export class Minimap extends BABYLON.GUI.Ellipse {
                 const height = center[2] + size[2] / 2;
                 context.globalAlpha = 1 - Math.pow(height + 1, -0.5);
 
-                // Draw the rect
+                // Draw the rect around its center so it can be rotated
+                context.save();
+                context.translate((center[0] + offsetX) * scale, (center[1] + offsetY) * scale);
+                context.rotate(yaw);
                 context.fillRect(
-                    (center[0] - size[0] / 2 + offsetX) * scale,
-                    (center[1] - size[1] / 2 + offsetY) * scale,
+                    -size[0] / 2 * scale,
+                    -size[1] / 2 * scale,
                     size[0] * scale,
                     size[1] * scale
                 );
+                context.restore();
             }
         }
 
@@ -33,4 +33,7 @@ for (let object of objects) {
     if (object.rotation != undefined) {
         console.log(`  rotation: ${object.rotation}`);
     }
+    if (object.yaw != undefined) {
+        console.log(`  yaw: ${object.yaw}`);
+    }
 }
@@ -0,0 +1,137 @@
+use crate::entities::EntityId;
+use crate::game_config::{AbilityConfig, GAME_CONFIG};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/*** Errors ***/
+/// Reasons an ability can't be used.
+#[derive(Debug, PartialEq)]
+pub enum AbilityError {
+    Cooldown,
+    NoCharges,
+}
+
+/*** Ability State ***/
+/// Cooldown and charges for one of a player's abilities.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AbilityState {
+    /// The time (in milliseconds) at which the ability can be used again
+    ready_time: u64,
+
+    /// Number of times the ability was used this round
+    uses: u64,
+}
+
+/// Tracks the cooldowns and charges of a player's abilities. This is reset whenever the player
+/// switches teams.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AbilitySet {
+    states: HashMap<String, AbilityState>,
+}
+
+impl AbilitySet {
+    pub fn new() -> AbilitySet {
+        AbilitySet {
+            states: HashMap::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.states.clear();
+    }
+
+    /// Uses the ability if it's off cooldown and has charges left.
+    pub fn try_use(&mut self, ability: &AbilityConfig, now: u64) -> Result<(), AbilityError> {
+        if self.cooldown(ability, now) > 0 {
+            return Err(AbilityError::Cooldown);
+        }
+        if self.charges_left(ability) == Some(0) {
+            return Err(AbilityError::NoCharges);
+        }
+
+        let state = self
+            .states
+            .entry(ability.id.clone())
+            .or_insert(AbilityState {
+                ready_time: 0,
+                uses: 0,
+            });
+        state.ready_time = now + (ability.cooldown * 1000.) as u64;
+        state.uses += 1;
+
+        Ok(())
+    }
+
+    /// Milliseconds until the ability can be used again.
+    pub fn cooldown(&self, ability: &AbilityConfig, now: u64) -> u64 {
+        self.states
+            .get(&ability.id)
+            .map_or(0, |state| state.ready_time.saturating_sub(now))
+    }
+
+    /// Number of uses left this round; `None` if the ability can be used any number of times.
+    pub fn charges_left(&self, ability: &AbilityConfig) -> Option<u64> {
+        let uses = self.states.get(&ability.id).map_or(0, |state| state.uses);
+        ability.charges.map(|charges| charges.saturating_sub(uses))
+    }
+}
+
+/// Finds an ability in the config.
+pub fn ability_with_id(id: &str) -> Option<&'static AbilityConfig> {
+    GAME_CONFIG.abilities.iter().find(|a| a.id == id)
+}
+
+/*** Decoys ***/
+/// A fake prop spawned by a prop player. Decoys ping along with their owner until they expire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Decoy {
+    pub entity_id: EntityId,
+
+    /// The player that spawned the decoy
+    pub owner_id: EntityId,
+
+    /// The time (in milliseconds) at which the decoy is removed
+    pub expire_time: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game_config::{AbilityEffect, AbilityTeam};
+
+    fn ability(cooldown: f64, charges: Option<u64>) -> AbilityConfig {
+        AbilityConfig {
+            id: "test".to_string(),
+            team: AbilityTeam::Prop,
+            cooldown,
+            charges,
+            score_event: None,
+            effect: AbilityEffect::Ping,
+        }
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let ability = ability(2., None);
+        let mut set = AbilitySet::new();
+        assert_eq!(set.try_use(&ability, 1000), Ok(()));
+        assert_eq!(set.cooldown(&ability, 2000), 1000);
+        assert_eq!(set.try_use(&ability, 2000), Err(AbilityError::Cooldown));
+        assert_eq!(set.try_use(&ability, 3000), Ok(()));
+        assert_eq!(set.charges_left(&ability), None);
+    }
+
+    #[test]
+    fn test_charges() {
+        let ability = ability(0., Some(2));
+        let mut set = AbilitySet::new();
+        assert_eq!(set.try_use(&ability, 0), Ok(()));
+        assert_eq!(set.try_use(&ability, 0), Ok(()));
+        assert_eq!(set.charges_left(&ability), Some(0));
+        assert_eq!(set.try_use(&ability, 0), Err(AbilityError::NoCharges));
+
+        // Switching teams gives the charges back
+        set.reset();
+        assert_eq!(set.charges_left(&ability), Some(2));
+    }
+}
@@ -0,0 +1,411 @@
+use crate::entities::{Entity, EntityHandle, EntityId, EntityKind, MoveMessage, PlayerState};
+use crate::game::GameState;
+use crate::game_config::{BotConfig, CharacterConfig, GAME_CONFIG};
+use crate::game_world::GameWorld;
+use crate::network::ClientMessage;
+use crate::utils::{time_milliseconds, FloatType, Ray, Rect, Vector};
+use rand::{self, Rng};
+use std::mem;
+use std::sync::mpsc::Sender;
+
+/// Controls a player that doesn't have a client. Bots play through the same messages as a
+/// client would send, so the game treats them like any other player.
+pub struct Bot {
+    /// Sends messages to the bot's client handle
+    tx_message: Sender<ClientMessage>,
+
+    /// The name the bot joined with
+    username: String,
+
+    /// The time (in milliseconds) at which the bot will make its next decision
+    next_think_time: u64,
+
+    /// Where the bot is walking to
+    target: Option<Vector>,
+
+    /// Where the bot was at the last decision, so it knows if it's stuck
+    last_position: Vector,
+
+    /// Number of decisions in a row the bot hasn't moved while walking
+    stuck_count: u64,
+
+    /* Props */
+    /// The entity the prop bot is disguised as and hiding next to
+    cover_id: Option<EntityId>,
+
+    /* Hunters */
+    /// Pings heard since the last decision
+    heard_pings: Vec<Vector>,
+
+    /// The last ping the hunter is chasing
+    suspect: Option<Vector>,
+
+    /// Shots fired since the hunter started looking around the suspect
+    suspect_shots: u64,
+}
+
+impl Bot {
+    /// Number of decisions without moving before the bot gives up on its target.
+    const MAX_STUCK_COUNT: u64 = 4;
+
+    /// How close the bot has to be to its target to stop walking.
+    const ARRIVE_DISTANCE: FloatType = 8.;
+
+    pub fn new(tx_message: Sender<ClientMessage>, username: String) -> Bot {
+        Bot {
+            tx_message,
+            username,
+            next_think_time: 0,
+            target: None,
+            last_position: Vector::zero(),
+            stuck_count: 0,
+            cover_id: None,
+            heard_pings: Vec::new(),
+            suspect: None,
+            suspect_shots: 0,
+        }
+    }
+
+    pub fn username(&self) -> &String {
+        &self.username
+    }
+
+    /// Joins the game with a random character from the ones the lobby allows.
+    pub fn join(&self, characters: &[&CharacterConfig]) {
+        let character_id = rand::thread_rng()
+            .choose(characters)
+            .map(|c| c.id.clone())
+            .unwrap_or_default();
+        self.send(ClientMessage::Join {
+            username: self.username.clone(),
+            character_id,
+            party: None,
+        });
+    }
+
+    /// Removes the bot from the game.
+    pub fn leave(&self) {
+        self.send(ClientMessage::Leave);
+    }
+
+    /// Decides what the bot should do next. `pings` holds the ping events from this update.
+    pub fn update(
+        &mut self,
+        world: &GameWorld,
+        player: &EntityHandle,
+        state: &GameState,
+        pings: &[Vector],
+    ) {
+        let config = &GAME_CONFIG.bots;
+
+        // Remember the pings until the next decision
+        self.heard_pings.extend_from_slice(pings);
+
+        // Only make a decision every so often
+        let now = time_milliseconds();
+        if now < self.next_think_time {
+            return;
+        }
+        let jitter = rand::thread_rng().gen_range(0.5, 1.5);
+        self.next_think_time = now + (config.think_delay * jitter * 1000.) as u64;
+
+        // Read the player
+        let (player_id, position, is_prop) = {
+            let entity = player.borrow();
+            let is_prop = match *entity.kind() {
+                EntityKind::Player(ref player) => match *player.state() {
+                    PlayerState::Prop(_) => true,
+                    PlayerState::Hunter(_) => false,
+                },
+                EntityKind::Prop(_) => return,
+            };
+            let position = entity.body().get_pos().clone();
+            (*entity.id(), position, is_prop)
+        };
+
+        // Forget the last role's plans
+        if is_prop {
+            self.suspect = None;
+        } else {
+            self.cover_id = None;
+        }
+
+        // Check if the bot is stuck on something
+        if self.target.is_some() && Bot::flat_distance(&position, &self.last_position) < 1. {
+            self.stuck_count += 1;
+            if self.stuck_count >= Bot::MAX_STUCK_COUNT {
+                self.target = None;
+                self.stuck_count = 0;
+            } else {
+                self.send(ClientMessage::Jump);
+            }
+        } else {
+            self.stuck_count = 0;
+        }
+        self.last_position = position.clone();
+
+        let pings = mem::take(&mut self.heard_pings);
+        if is_prop {
+            self.update_prop(config, world, &position, state);
+        } else {
+            self.update_hunter(config, world, player_id, &position, state, &pings);
+        }
+    }
+
+    /// Props disguise themselves as a prop nearby, walk next to it and hold still.
+    fn update_prop(
+        &mut self,
+        config: &BotConfig,
+        world: &GameWorld,
+        position: &Vector,
+        state: &GameState,
+    ) {
+        match *state {
+            GameState::Hiding => {
+                // Find cover if the bot doesn't have any or if its cover was destroyed
+                let has_cover = self
+                    .cover_id
+                    .and_then(|id| world.entity_with_id(&id))
+                    .is_some();
+                if !has_cover {
+                    self.find_cover(config, world, position);
+                }
+
+                self.walk_to_target(position, false);
+            }
+            GameState::Hunting => {
+                // Holding still makes it harder to spot the bot
+                self.target = None;
+                self.walk_to_target(position, false);
+            }
+            GameState::PreGame => self.patrol(config, position),
+        }
+    }
+
+    /// Picks a random prop in range to disguise as and a spot right next to it to hide in.
+    fn find_cover(&mut self, config: &BotConfig, world: &GameWorld, position: &Vector) {
+        let area = Rect::new(
+            Vector::zero(),
+            Vector::new(config.cover_range * 2., config.cover_range * 2., 99999.),
+        );
+        let candidates = world.query_rect_all(&area, position, true, false, |e| {
+            if let EntityKind::Prop(_) = *e.kind() {
+                e.kind().inner().is_selectable()
+            } else {
+                false
+            }
+        });
+        let cover = match rand::thread_rng().choose(&candidates) {
+            Some(cover) => cover.borrow(),
+            None => return,
+        };
+
+        // Disguise as the cover
+        self.cover_id = Some(*cover.id());
+        self.send(ClientMessage::Select {
+            entity_id: *cover.id(),
+        });
+
+        // Hide on the side of the cover closest to the bot
+        let body = cover.body();
+        let cover_position = body.get_pos();
+        let radius = body
+            .bounding_rect()
+            .as_ref()
+            .map_or(0., |rect| FloatType::max(rect.size.x, rect.size.y));
+        let mut offset = position.clone();
+        offset.add(cover_position, -1.);
+        offset.z = 0.;
+        let distance = offset.magnitude();
+        let mut target = cover_position.clone();
+        if distance > 0. {
+            target.add(&offset, radius / distance);
+        }
+        self.target = Some(target);
+    }
+
+    /// Hunters chase pings, shoot props near them and patrol when there's nothing to chase.
+    fn update_hunter(
+        &mut self,
+        config: &BotConfig,
+        world: &GameWorld,
+        player_id: EntityId,
+        position: &Vector,
+        state: &GameState,
+        pings: &[Vector],
+    ) {
+        match *state {
+            GameState::Hiding => {
+                // Wait at the spawn for the props to hide
+                self.target = None;
+                self.walk_to_target(position, false);
+                return;
+            }
+            GameState::PreGame => {
+                self.patrol(config, position);
+                return;
+            }
+            GameState::Hunting => {}
+        }
+
+        // Chase the closest ping in range
+        let closest_ping = pings
+            .iter()
+            .filter(|p| Bot::flat_distance(p, position) <= config.ping_range)
+            .min_by(|a, b| {
+                Bot::flat_distance(a, position)
+                    .partial_cmp(&Bot::flat_distance(b, position))
+                    .unwrap()
+            });
+        if let Some(ping) = closest_ping {
+            self.suspect = Some(ping.clone());
+            self.suspect_shots = 0;
+            self.target = Some(ping.clone());
+        }
+
+        if let Some(suspect) = self.suspect.clone() {
+            // Shoot at anything suspicious around the ping once close enough
+            let distance = Bot::flat_distance(position, &suspect);
+            let can_shoot =
+                distance <= config.shoot_range && self.suspect_shots < config.max_shots_per_suspect;
+            let target = if can_shoot {
+                Bot::find_suspicious_prop(config, world, player_id, position, &suspect)
+            } else {
+                None
+            };
+            if let Some(target) = target {
+                self.shoot(position, &target);
+                self.suspect_shots += 1;
+                self.target = None;
+                self.walk_to_target(position, false);
+                return;
+            }
+
+            // Keep chasing the ping until there's nothing left to find around it
+            if self.suspect_shots >= config.max_shots_per_suspect
+                || distance <= config.suspect_range
+            {
+                self.suspect = None;
+            } else {
+                self.target = Some(suspect);
+                self.walk_to_target(position, true);
+                return;
+            }
+        }
+
+        self.patrol(config, position);
+    }
+
+    /// Finds the closest prop-like entity around the suspect that the hunter can see.
+    fn find_suspicious_prop(
+        config: &BotConfig,
+        world: &GameWorld,
+        player_id: EntityId,
+        position: &Vector,
+        suspect: &Vector,
+    ) -> Option<Vector> {
+        let area = Rect::new(
+            Vector::zero(),
+            Vector::new(config.suspect_range * 2., config.suspect_range * 2., 99999.),
+        );
+        let mut candidates = world
+            .query_rect_all(&area, suspect, true, false, Bot::looks_like_prop)
+            .into_iter()
+            .map(|e| {
+                let e = e.borrow();
+                let position = e.body().get_pos().clone();
+                (*e.id(), position)
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| {
+            a.1.distance(suspect)
+                .partial_cmp(&b.1.distance(suspect))
+                .unwrap()
+        });
+
+        // Look for a prop that isn't blocked by anything
+        let mut origin = position.clone();
+        origin.z += GAME_CONFIG.player.shoot_pos.z;
+        candidates.into_iter().find_map(|(id, target)| {
+            let mut dir = target.clone();
+            dir.add(&origin, -1.);
+            let ray = Ray::new(origin.clone(), dir, config.shoot_range);
+            match world.cast_ray(&ray, |e| *e.id() != player_id) {
+                Some((hit, _)) if *hit.borrow().id() == id => Some(target),
+                _ => None,
+            }
+        })
+    }
+
+    /// If the entity looks like a prop to a hunter; this includes players disguised as props.
+    fn looks_like_prop(entity: &Entity) -> bool {
+        match *entity.kind() {
+            EntityKind::Prop(_) => entity.kind().inner().is_selectable(),
+            EntityKind::Player(ref player) => match *player.state() {
+                PlayerState::Prop(_) => true,
+                PlayerState::Hunter(_) => false,
+            },
+        }
+    }
+
+    /// Faces and shoots at a point.
+    fn shoot(&self, position: &Vector, target: &Vector) {
+        let dir = FloatType::atan2(target.y - position.y, target.x - position.x);
+        self.send(ClientMessage::FaceDir { dir });
+        self.send(ClientMessage::Shoot {
+            target: target.clone(),
+        });
+    }
+
+    /// Wanders to random spots around the bot.
+    fn patrol(&mut self, config: &BotConfig, position: &Vector) {
+        if self.target.is_none() {
+            let mut rng = rand::thread_rng();
+            let limit = GAME_CONFIG.map_size / 2.;
+            let x = position.x + rng.gen_range(-config.patrol_range, config.patrol_range);
+            let y = position.y + rng.gen_range(-config.patrol_range, config.patrol_range);
+            self.target = Some(Vector::new(
+                x.max(-limit).min(limit),
+                y.max(-limit).min(limit),
+                position.z,
+            ));
+        }
+
+        self.walk_to_target(position, false);
+    }
+
+    /// Walks toward the target or stops if there is no target or it's been reached.
+    fn walk_to_target(&mut self, position: &Vector, sprinting: bool) {
+        let move_dir = match self.target {
+            Some(ref target) if Bot::flat_distance(target, position) > Bot::ARRIVE_DISTANCE => {
+                Some(FloatType::atan2(
+                    target.y - position.y,
+                    target.x - position.x,
+                ))
+            }
+            _ => {
+                self.target = None;
+                None
+            }
+        };
+
+        if let Some(dir) = move_dir {
+            self.send(ClientMessage::FaceDir { dir });
+        }
+        self.send(ClientMessage::MoveDir(MoveMessage {
+            timestamp: time_milliseconds(),
+            move_dir,
+            sprinting,
+        }));
+    }
+
+    /// Distance between two points on the ground, since bots can't choose how high they are.
+    fn flat_distance(a: &Vector, b: &Vector) -> FloatType {
+        ((a.x - b.x).powi(2) + (a.y - b.y).powi(2)).sqrt()
+    }
+
+    fn send(&self, message: ClientMessage) {
+        // The handle only goes away after the bot leaves, so there's nothing to do on an error
+        self.tx_message.send(message).ok();
+    }
+}
@@ -0,0 +1,185 @@
+use crate::game_config::ChatConfig;
+use crate::network::ClientTeam;
+use std::collections::VecDeque;
+
+/*** Channels ***/
+/// Who a chat message is meant for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChatChannel {
+    All,
+    Team,
+    Spectator,
+}
+
+impl ChatChannel {
+    pub fn from_raw(raw: u64) -> Option<ChatChannel> {
+        match raw {
+            0 => Some(ChatChannel::All),
+            1 => Some(ChatChannel::Team),
+            2 => Some(ChatChannel::Spectator),
+            _ => None,
+        }
+    }
+
+    pub fn raw(&self) -> u8 {
+        match *self {
+            ChatChannel::All => 0,
+            ChatChannel::Team => 1,
+            ChatChannel::Spectator => 2,
+        }
+    }
+}
+
+/// The teams that can read a message. While a round is running, props and spectators can't talk
+/// to the hunters so they can't give away where the props are hiding.
+pub fn chat_audience(
+    channel: ChatChannel,
+    sender: ClientTeam,
+    round_active: bool,
+) -> Vec<ClientTeam> {
+    match channel {
+        ChatChannel::All if !round_active => {
+            vec![ClientTeam::Hunter, ClientTeam::Prop, ClientTeam::Spectator]
+        }
+        ChatChannel::All => match sender {
+            ClientTeam::Hunter => vec![ClientTeam::Hunter, ClientTeam::Prop, ClientTeam::Spectator],
+            ClientTeam::Prop => vec![ClientTeam::Prop, ClientTeam::Spectator],
+            ClientTeam::Spectator => vec![ClientTeam::Spectator],
+        },
+        ChatChannel::Team => vec![sender],
+        ChatChannel::Spectator => vec![ClientTeam::Spectator],
+    }
+}
+
+/*** Moderation ***/
+/// Limits how many messages a client can send in a window of time.
+#[derive(Debug)]
+pub struct ChatRateLimiter {
+    /// Times (in milliseconds) of the messages sent in the current window
+    sent: VecDeque<u64>,
+}
+
+impl ChatRateLimiter {
+    pub fn new() -> ChatRateLimiter {
+        ChatRateLimiter {
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Records a message sent at `now` if the client is under the limit.
+    pub fn try_send(&mut self, now: u64, config: &ChatConfig) -> bool {
+        // Forget the messages outside of the window
+        let window = (config.rate_window * 1000.) as u64;
+        while let Some(&time) = self.sent.front() {
+            if time + window > now {
+                break;
+            }
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= config.rate_limit {
+            return false;
+        }
+        self.sent.push_back(now);
+        true
+    }
+}
+
+/// Cleans up a message before it's sent to the other players. Returns `None` if there is nothing
+/// left to send.
+pub fn clean_message(message: &str, config: &ChatConfig) -> Option<String> {
+    // Remove control characters and limit the length
+    let message = message
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(config.max_length)
+        .collect::<String>();
+    let message = message.trim();
+    if message.is_empty() {
+        return None;
+    }
+
+    Some(filter_words(message, &config.filtered_words))
+}
+
+/// Replaces the filtered words with asterisks. Only whole words are matched, so words that
+/// happen to contain a filtered word are left alone.
+fn filter_words(message: &str, filtered_words: &[String]) -> String {
+    let mut result = String::with_capacity(message.len());
+    let mut word = String::new();
+    for c in message.chars().chain(std::iter::once(' ')) {
+        if c.is_alphanumeric() {
+            word.push(c);
+            continue;
+        }
+
+        // Finish the current word
+        let is_filtered = filtered_words
+            .iter()
+            .any(|filtered| filtered.to_lowercase() == word.to_lowercase());
+        if is_filtered {
+            result.extend(word.chars().map(|_| '*'));
+        } else {
+            result.push_str(&word);
+        }
+        word.clear();
+        result.push(c);
+    }
+    result.pop(); // Remove the extra space
+
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn config() -> ChatConfig {
+        ChatConfig {
+            max_length: 20,
+            rate_limit: 2,
+            rate_window: 1.,
+            filtered_words: vec!["darn".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_clean_message() {
+        let config = config();
+        assert_eq!(
+            clean_message("Darn it, darnation", &config),
+            Some("**** it, darnation".to_string())
+        );
+        assert_eq!(clean_message("  hi\n  ", &config), Some("hi".to_string()));
+        assert_eq!(clean_message(" \t ", &config), None);
+        assert_eq!(
+            clean_message("this message is far too long", &config),
+            Some("this message is far".to_string())
+        );
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let config = config();
+        let mut limiter = ChatRateLimiter::new();
+        assert!(limiter.try_send(0, &config));
+        assert!(limiter.try_send(100, &config));
+        assert!(!limiter.try_send(500, &config));
+        assert!(limiter.try_send(1000, &config));
+        assert!(!limiter.try_send(1050, &config));
+    }
+
+    #[test]
+    fn test_props_dont_leak() {
+        for &channel in [ChatChannel::All, ChatChannel::Team, ChatChannel::Spectator].iter() {
+            let audience = chat_audience(channel, ClientTeam::Prop, true);
+            assert!(!audience.contains(&ClientTeam::Hunter));
+            let audience = chat_audience(channel, ClientTeam::Spectator, true);
+            assert!(!audience.contains(&ClientTeam::Hunter));
+        }
+
+        // Everyone can talk before the round starts
+        let audience = chat_audience(ChatChannel::All, ClientTeam::Prop, false);
+        assert!(audience.contains(&ClientTeam::Hunter));
+    }
+}
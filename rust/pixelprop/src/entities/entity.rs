@@ -1,11 +1,13 @@
 use crate::entities::{Player, Prop};
 use crate::game_config::PrefabConfig;
 use crate::game_config::PrefabConfigHandle;
+use crate::game_config::GAME_CONFIG;
 use crate::incremental_value::{IncrementalValue, IncrementalValueDiff};
+use crate::snapshot::{EntityKindSnapshot, EntitySnapshot, SnapshotError};
 use crate::utils::SerializableInitMut;
 use crate::utils::{
-    pack_incremental_value, pack_value, Counter, FloatType, PackData, PackFlag, PackFlagRaw, Rect,
-    Serializable, SerializableInit, Vector,
+    normalize_angle, pack_incremental_value, pack_value, Counter, FloatType, PackData, PackFlag,
+    PackFlagRaw, Rect, Serializable, SerializableInit, Vector,
 };
 use rmpv::Value;
 use std::cell::{Ref, RefCell, RefMut};
@@ -125,8 +127,8 @@ pub struct EntityBody {
     /// The velocity of the body
     velocity: IncrementalValueDiff<Vector, FloatType>,
 
-    /// Which way the entity is facing; 0 = 0º, 1 = 90º, 2 = 180º, 3 = 270º
-    rotation: IncrementalValue<u8>,
+    /// Which way the entity is facing in radians; this is always between 0 and 2π
+    rotation: IncrementalValue<FloatType>,
 
     /// The collision rectangles for the object
     rects: IncrementalValue<Vec<Rect>>,
@@ -152,7 +154,7 @@ impl EntityBody {
         is_static: bool,
         position: Vector,
         velocity: Vector,
-        rotation: u8,
+        rotation: FloatType,
         rects: Vec<Rect>,
     ) -> EntityBody {
         measure!("New entity body");
@@ -164,7 +166,7 @@ impl EntityBody {
             sleepable_updates: 0,
             position: IncrementalValueDiff::new(position, 0.1),
             velocity: IncrementalValueDiff::new(velocity, 0.1),
-            rotation: IncrementalValue::new(rotation),
+            rotation: IncrementalValue::new(normalize_angle(rotation)),
             rotated_rects: Vec::new(),
             bounding_rect: None,
             rects: IncrementalValue::new(rects),
@@ -213,11 +215,11 @@ impl EntityBody {
         self.velocity.set(vel)
     }
 
-    pub fn get_rotation(&self) -> &u8 {
+    pub fn get_rotation(&self) -> &FloatType {
         self.rotation.get()
     }
-    pub fn set_rotation(&mut self, rot: u8) {
-        self.rotation.set(rot);
+    pub fn set_rotation(&mut self, rot: FloatType) {
+        self.rotation.set(normalize_angle(rot));
         self.recalculate_rect_data(); // Calculate new bounding box
     }
 
@@ -258,7 +260,7 @@ impl EntityBody {
     /// Calculates the rotated rects.
     fn calculate_rotated_rects(&mut self) {
         // Calculate the new rotated rects and save them
-        let rotation = self.get_rotation();
+        let rotation = *self.get_rotation();
         self.rotated_rects = self
             .rects
             .get()
@@ -307,8 +309,9 @@ impl EntityBody {
                 }
             }
 
-            // Create the new bounding rect
-            let mut bounding_rect = Rect::new(
+            // Create the new bounding rect; this is built from the rotated rects, so it's already
+            // rotated and stays axis aligned
+            let bounding_rect = Rect::new(
                 Vector::new(
                     (min.x + max.x) / 2.,
                     (min.y + max.y) / 2.,
@@ -317,9 +320,6 @@ impl EntityBody {
                 Vector::new(max.x - min.x, max.y - min.y, max.z - min.z),
             );
 
-            // Rotate it
-            bounding_rect.rotate(self.get_rotation());
-
             // Save it
             self.bounding_rect = Some(bounding_rect);
         }
@@ -378,6 +378,12 @@ impl EntityBody {
             return;
         }
 
+        // Rotated rects need to be pushed along their own axes
+        if rect_a.is_rotated() || rect_b.is_rotated() {
+            EntityBody::collide_oriented_rects(body_a, body_b, index_a, index_b);
+            return;
+        }
+
         measure_verbose!("Collide rects (after intersects)");
 
         // TODO: Generalize this code to reuse more code
@@ -499,6 +505,60 @@ impl EntityBody {
         }
     }
 
+    /// Performs a collision between two child rectangles where at least one of them is rotated.
+    /// Works like `collide_rects`, but pushes the bodies apart along the axis with the least
+    /// overlap from the separating axis test.
+    fn collide_oriented_rects(
+        body_a: &mut EntityBody,
+        body_b: &mut EntityBody,
+        index_a: usize,
+        index_b: usize,
+    ) {
+        measure_verbose!("Collide oriented rects");
+
+        // Get the rectangles
+        let rect_a = &body_a.rotated_rects()[index_a];
+        let rect_b = &body_b.rotated_rects()[index_b];
+
+        // Find the axis to push the rects apart along
+        let (axis, overlap) =
+            match rect_a.min_overlap(rect_b, body_a.get_pos(), body_b.get_pos()) {
+                Some(result) => result,
+                None => return,
+            };
+
+        // TEMP: Declare masses based on if the object is static
+        let mass_a: FloatType = if body_a.is_static {
+            f64::MAX
+        } else {
+            body_a.mass()
+        };
+        let mass_b: FloatType = if body_b.is_static {
+            f64::MAX
+        } else {
+            body_b.mass()
+        };
+
+        // Find how much to weight the push-away
+        let weight_a = rect_a.projected_radius(&axis) / mass_a;
+        let weight_b = rect_b.projected_radius(&axis) / mass_b;
+        let weight_total = weight_a + weight_b;
+
+        // Find how much to push each item
+        let push_a = weight_a / weight_total * overlap;
+        let push_b = weight_b / weight_total * overlap;
+
+        // Remove velocity along the axis
+        let vel_a = body_a.get_vel().dot(&axis);
+        body_a.get_vel_mut().add(&axis, -vel_a);
+        let vel_b = body_b.get_vel().dot(&axis);
+        body_b.get_vel_mut().add(&axis, -vel_b);
+
+        // Move the items; the axis points from a to b
+        body_a.get_pos_mut().add(&axis, -push_a);
+        body_b.get_pos_mut().add(&axis, push_b);
+    }
+
     pub fn is_changed(&self) -> bool {
         self.is_sleeping.is_changed()
             || self.position.is_changed()
@@ -581,6 +641,11 @@ pub struct Entity {
 
 impl Entity {
     pub fn new(kind: EntityKind) -> Entity {
+        let id = ENTITY_COUNTER.lock().unwrap().tick();
+        Entity::with_id(id, kind)
+    }
+
+    fn with_id(id: EntityId, kind: EntityKind) -> Entity {
         // Save the prefab
         let prefab = kind.inner().get_prefab();
 
@@ -591,7 +656,7 @@ impl Entity {
 
         // Create the entity
         let mut entity = Entity {
-            id: ENTITY_COUNTER.lock().unwrap().tick(),
+            id,
             is_new: true,
             kind: RefCell::new(kind),
             body: RefCell::new(entity_body),
@@ -682,6 +747,74 @@ impl Entity {
     }
 }
 
+/* Snapshots */
+impl Entity {
+    /// Recreates an entity saved with `snapshot`, keeping its id.
+    pub fn from_snapshot(snapshot: EntitySnapshot) -> Result<Entity, SnapshotError> {
+        let kind = match snapshot.kind {
+            EntityKindSnapshot::Player(player) => {
+                EntityKind::Player(Player::from_snapshot(*player)?)
+            }
+            EntityKindSnapshot::Prop { object, prefab_id } => {
+                let prefab = GAME_CONFIG
+                    .prefab_with_id(&prefab_id)
+                    .ok_or(SnapshotError::MissingPrefab(prefab_id))?;
+                EntityKind::Prop(Prop::from_prefab(object, prefab.clone()))
+            }
+        };
+
+        // Make sure new entities don't reuse the id
+        ENTITY_COUNTER.lock().unwrap().skip_past(snapshot.id);
+
+        let mut entity = Entity::with_id(snapshot.id, kind);
+        entity.set_asset(snapshot.asset);
+        entity.set_dir(snapshot.dir);
+        entity.set_uses_dir(snapshot.uses_dir);
+        entity.set_health(snapshot.health);
+        entity.set_label(snapshot.label);
+        {
+            let mut body = entity.body_mut();
+            body.set_rects_raw(snapshot.rects);
+            body.set_pos(snapshot.position);
+            body.set_vel(snapshot.velocity);
+            body.set_rotation(snapshot.rotation);
+            if snapshot.is_sleeping {
+                body.sleep();
+            } else {
+                body.awaken();
+            }
+        }
+
+        Ok(entity)
+    }
+
+    pub fn snapshot(&self) -> EntitySnapshot {
+        let body = self.body();
+        EntitySnapshot {
+            id: self.id,
+            kind: match *self.kind() {
+                EntityKind::Player(ref player) => {
+                    EntityKindSnapshot::Player(Box::new(player.snapshot()))
+                }
+                EntityKind::Prop(ref prop) => EntityKindSnapshot::Prop {
+                    object: prop.object.clone(),
+                    prefab_id: prop.prefab.id.clone(),
+                },
+            },
+            asset: self.asset.get().clone(),
+            rects: body.rects_raw().clone(),
+            position: body.get_pos().clone(),
+            velocity: body.get_vel().clone(),
+            rotation: *body.get_rotation(),
+            is_sleeping: *body.is_sleeping(),
+            dir: *self.dir.get(),
+            uses_dir: *self.uses_dir.get(),
+            health: *self.health.get(),
+            label: self.label.get().clone(),
+        }
+    }
+}
+
 impl Entity {
     pub fn physics_will_update(&mut self, dt: f64) {
         // Call `physics_will_update` on the inner kind
@@ -1,17 +1,24 @@
 extern crate rmpv;
 
+use crate::abilities::AbilitySet;
 use crate::entities::{EntityBody, EntityKindInner};
 use crate::game::Game;
 use crate::game_config::PrefabConfig;
 use crate::game_config::PrefabConfigHandle;
 use crate::game_config::GAME_CONFIG;
+use crate::game_config::{PlacementTierConfig, ScoreDisplay, ScoreRuleConfig};
 use crate::incremental_value::IncrementalValue;
+use crate::snapshot::{PlayerSnapshot, PlayerStateSnapshot, SnapshotError};
+use crate::stats_store::PlayerStats;
+use crate::teams::TeamHistory;
 use crate::utils::time_milliseconds;
 use crate::utils::Serializable;
 use crate::utils::{FloatType, Rect, Vector};
 use rand::{self, Rng};
 use rmpv::Value;
+use serde::{Deserialize, Serialize};
 use std::cell::RefMut;
+use std::collections::HashMap;
 use std::sync::Arc;
 
 /// Move message
@@ -43,6 +50,9 @@ pub struct PropState {
 
     /// The time at which the next map ping will be forced.
     pub next_ping_time: u64,
+
+    /// The prop the player is disguised as; `None` if they haven't picked one
+    pub disguise: Option<PrefabConfigHandle>,
 }
 
 impl PropState {
@@ -50,6 +60,7 @@ impl PropState {
         PropState {
             last_ping_time: 0,
             next_ping_time: 0,
+            disguise: None,
         }
     }
 }
@@ -76,6 +87,15 @@ pub struct Player {
     /// Data for the player's score
     score_counter: ScoreCounter,
 
+    /// Cooldowns and charges for the player's abilities
+    abilities: AbilitySet,
+
+    /// The player's past rounds, used to pick the teams
+    team_history: TeamHistory,
+
+    /// Players with the same party are put on the same team
+    party: Option<String>,
+
     /// Display name for the player
     username: String,
 
@@ -106,10 +126,18 @@ pub struct Player {
 }
 
 impl Player {
-    pub fn new(username: String, character_id: String, is_admin: bool) -> Player {
+    pub fn new(
+        username: String,
+        character_id: String,
+        party: Option<String>,
+        is_admin: bool,
+    ) -> Player {
         Player {
             is_admin,
             score_counter: ScoreCounter::new(),
+            abilities: AbilitySet::new(),
+            team_history: TeamHistory::new(),
+            party,
             username,
             character_id,
             state: PlayerState::default_state(),
@@ -122,6 +150,70 @@ impl Player {
         }
     }
 
+    /// Recreates a player saved with `snapshot`.
+    pub fn from_snapshot(snapshot: PlayerSnapshot) -> Result<Player, SnapshotError> {
+        let mut player = Player::new(
+            snapshot.username,
+            snapshot.character_id,
+            snapshot.party,
+            snapshot.is_admin,
+        );
+        player.state = match snapshot.state {
+            PlayerStateSnapshot::Hunter { last_shoot_time } => {
+                PlayerState::Hunter(HunterState { last_shoot_time })
+            }
+            PlayerStateSnapshot::Prop {
+                last_ping_time,
+                next_ping_time,
+                disguise,
+            } => PlayerState::Prop(PropState {
+                last_ping_time,
+                next_ping_time,
+                disguise: match disguise {
+                    Some(id) => Some(
+                        GAME_CONFIG
+                            .prefab_with_id(&id)
+                            .ok_or(SnapshotError::MissingPrefab(id))?
+                            .clone(),
+                    ),
+                    None => None,
+                },
+            }),
+        };
+        player.score_counter = snapshot.score_counter;
+        player.abilities = snapshot.abilities;
+        player.team_history = snapshot.team_history;
+        player.stamina.set(snapshot.stamina);
+        player.spawn_pos = snapshot.spawn_pos;
+
+        Ok(player)
+    }
+
+    /// Saves everything about the player except their input, which is sent again by the client.
+    pub fn snapshot(&self) -> PlayerSnapshot {
+        PlayerSnapshot {
+            username: self.username.clone(),
+            character_id: self.character_id.clone(),
+            party: self.party.clone(),
+            is_admin: self.is_admin,
+            state: match self.state {
+                PlayerState::Hunter(ref state) => PlayerStateSnapshot::Hunter {
+                    last_shoot_time: state.last_shoot_time,
+                },
+                PlayerState::Prop(ref state) => PlayerStateSnapshot::Prop {
+                    last_ping_time: state.last_ping_time,
+                    next_ping_time: state.next_ping_time,
+                    disguise: state.disguise.as_ref().map(|prefab| prefab.id.clone()),
+                },
+            },
+            score_counter: self.score_counter.clone(),
+            abilities: self.abilities.clone(),
+            team_history: self.team_history.clone(),
+            stamina: *self.stamina.get(),
+            spawn_pos: self.spawn_pos.clone(),
+        }
+    }
+
     pub fn set_admin(&mut self, admin: bool) {
         self.is_admin = admin;
     }
@@ -136,6 +228,31 @@ impl Player {
     pub fn score_counter_mut(&mut self) -> &mut ScoreCounter {
         &mut self.score_counter
     }
+    pub fn reset_score_counter(&mut self) {
+        self.score_counter = ScoreCounter::new();
+    }
+
+    pub fn abilities(&self) -> &AbilitySet {
+        &self.abilities
+    }
+    pub fn abilities_mut(&mut self) -> &mut AbilitySet {
+        &mut self.abilities
+    }
+
+    pub fn character_id(&self) -> &String {
+        &self.character_id
+    }
+
+    pub fn team_history(&self) -> &TeamHistory {
+        &self.team_history
+    }
+    pub fn team_history_mut(&mut self) -> &mut TeamHistory {
+        &mut self.team_history
+    }
+
+    pub fn party(&self) -> &Option<String> {
+        &self.party
+    }
 
     pub fn state(&self) -> &PlayerState {
         &self.state
@@ -145,6 +262,7 @@ impl Player {
     }
     pub fn set_state(&mut self, state: PlayerState) {
         self.state = state;
+        self.abilities.reset();
     }
 
     pub fn move_dir(&self) -> &Option<f64> {
@@ -282,7 +400,7 @@ impl EntityKindInner for Player {
             false,
             Vector::zero(),
             Vector::zero(),
-            0,
+            0.,
             vec![], // The body will be created automatically based on the prefab
         )
     }
@@ -317,180 +435,246 @@ impl EntityKindInner for Player {
 }
 
 /* Score counter */
-#[derive(Debug)]
-pub struct ScoreCounter {
-    /* Hunters */
-    killed_players: u64,
-    hit_shots: u64,
-    missed_shots: u64,
+/// How often and how much of a score event happened.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ScoreTally {
+    pub count: u64,
+    pub amount: f64,
+    pub seconds: f64,
+}
 
-    /* Props */
-    health: f64,
-    pings: u64,
-    total_ping_volume: f64,
-    sleeping_time: f64,
+/// Counts the score events for a player. The points for each event come from the scoring rules
+/// in the config, so gameplay code only has to say what happened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScoreCounter {
+    tallies: HashMap<String, ScoreTally>,
 }
 
 impl ScoreCounter {
+    /* Event names */
+    pub const KILLED_PLAYER: &'static str = "killedPlayer";
+    pub const HIT_SHOT: &'static str = "hitShot";
+    pub const MISSED_SHOT: &'static str = "missedShot";
+    pub const HIT_RATIO: &'static str = "hitRatio"; // Calculated from the shots
+    pub const PROP_HEALTH: &'static str = "propHealth";
+    pub const PING: &'static str = "ping";
+    pub const SLEEPING: &'static str = "sleeping";
+    pub const SURVIVED_HUNT: &'static str = "survivedHunt";
+    pub const DECOY_PING: &'static str = "decoyPing";
+
     fn new() -> ScoreCounter {
         ScoreCounter {
-            killed_players: 0,
-            hit_shots: 0,
-            missed_shots: 0,
-
-            health: 0.0,
-            pings: 0,
-            total_ping_volume: 0.0,
-            sleeping_time: 0.,
+            tallies: HashMap::new(),
+        }
+    }
+
+    /* Events */
+    /// Counts an event that happened once.
+    pub fn event(&mut self, event: &str) {
+        self.tally_mut(event).count += 1;
+    }
+
+    /// Counts an event that happened once with an amount, like the volume of a ping.
+    pub fn event_amount(&mut self, event: &str, amount: f64) {
+        let tally = self.tally_mut(event);
+        tally.count += 1;
+        tally.amount += amount;
+    }
+
+    /// Replaces the amount of an event that is a measurement, like the prop's health.
+    pub fn set_amount(&mut self, event: &str, amount: f64) {
+        self.tally_mut(event).amount = amount;
+    }
+
+    /// Adds to how long an ongoing event has lasted.
+    pub fn event_time(&mut self, event: &str, dt: f64) {
+        self.tally_mut(event).seconds += dt;
+    }
+
+    fn tally_mut(&mut self, event: &str) -> &mut ScoreTally {
+        self.tallies.entry(event.to_string()).or_default()
+    }
+
+    /// The tally for an event, which is empty if the event never happened.
+    pub fn tally(&self, event: &str) -> ScoreTally {
+        if event == ScoreCounter::HIT_RATIO {
+            let hit = self.tally(ScoreCounter::HIT_SHOT).count;
+            let missed = self.tally(ScoreCounter::MISSED_SHOT).count;
+            let amount = if missed > 0 {
+                hit as f64 / (hit + missed) as f64
+            } else {
+                0.
+            };
+            return ScoreTally {
+                count: hit,
+                amount,
+                seconds: 0.,
+            };
         }
+
+        self.tallies.get(event).cloned().unwrap_or_default()
     }
 
     /* Hunters */
     pub fn killed_player(&mut self) {
-        self.killed_players += 1;
+        self.event(ScoreCounter::KILLED_PLAYER);
     }
 
     pub fn shot(&mut self, hit: bool) {
         if hit {
-            self.hit_shots += 1;
+            self.event(ScoreCounter::HIT_SHOT);
         } else {
-            self.missed_shots += 1;
+            self.event(ScoreCounter::MISSED_SHOT);
         }
     }
 
     /* Props */
     pub fn update_prop_health(&mut self, health: f64) {
-        self.health = health;
+        self.set_amount(ScoreCounter::PROP_HEALTH, health);
     }
 
     pub fn pinged(&mut self, volume: f64) {
-        self.pings += 1;
-        self.total_ping_volume += volume.powf(1. / 3.); // Use cubed root to make it less extreme
+        self.event_amount(ScoreCounter::PING, volume.powf(1. / 3.)); // Use cubed root to make it less extreme
     }
 
     pub fn sleeping(&mut self, dt: f64) {
-        self.sleeping_time += dt;
+        self.event_time(ScoreCounter::SLEEPING, dt);
+    }
+
+    /* Stats */
+    pub fn stats(&self) -> PlayerStats {
+        let pings = self.tally(ScoreCounter::PING);
+        PlayerStats {
+            killed_players: self.tally(ScoreCounter::KILLED_PLAYER).count,
+            hit_shots: self.tally(ScoreCounter::HIT_SHOT).count,
+            missed_shots: self.tally(ScoreCounter::MISSED_SHOT).count,
+            pings: pings.count,
+            total_ping_volume: pings.amount,
+            sleeping_time: self.tally(ScoreCounter::SLEEPING).seconds,
+        }
     }
 
     /* Calculations */
-    fn killed_players(&self) -> u64 {
-        self.killed_players * GAME_CONFIG.scoring.killed_player
-    }
-    fn hit_shots(&self) -> u64 {
-        self.hit_shots * GAME_CONFIG.scoring.hit_shot
-    }
-    fn percent_hit(&self) -> u64 {
-        if self.missed_shots > 0 {
-            ((self.hit_shots as f64 / (self.hit_shots as f64 + self.missed_shots as f64))
-                * GAME_CONFIG.scoring.percent_hit_scale as f64) as u64
-        } else {
-            0
+    /// Points from a rule for the event's tally.
+    fn rule_score(rule: &ScoreRuleConfig, tally: &ScoreTally) -> u64 {
+        let score = (tally.count * rule.per_event) as f64
+            + tally.amount * rule.per_unit
+            + tally.seconds * rule.per_second;
+        let score = score.max(0.) as u64;
+        match rule.cap {
+            Some(cap) => u64::min(score, cap),
+            None => score,
         }
     }
-    fn health(&self) -> u64 {
-        (self.health * GAME_CONFIG.scoring.prop_health as f64) as u64
-    }
-    fn pings(&self) -> u64 {
-        self.pings * GAME_CONFIG.scoring.ping
-    }
-    fn volume_pings(&self) -> u64 {
-        self.total_ping_volume as u64 * GAME_CONFIG.scoring.ping_per_unit
-    }
-    fn sleeping_time(&self) -> u64 {
-        (self.sleeping_time * GAME_CONFIG.scoring.sleeping_per_second) as u64
+
+    /// The value shown next to a rule's label in the score breakdown.
+    fn rule_display(rule: &ScoreRuleConfig, tally: &ScoreTally) -> String {
+        match rule.display {
+            ScoreDisplay::None => "".to_string(),
+            ScoreDisplay::Count => tally.count.to_string(),
+            ScoreDisplay::Amount => (tally.amount as u64).to_string(),
+            ScoreDisplay::Percent => format!("{:.1}%", tally.amount * 100.),
+            ScoreDisplay::Seconds => format!("{:.0}s", tally.seconds),
+        }
     }
+
+    /// The label and bonus for a placement from the placement tiers.
     pub fn placement(placement: Option<u64>) -> (String, u64) {
-        match placement {
-            Some(0) => ("firstScoring".to_string(), GAME_CONFIG.scoring.first),
-            Some(1) => ("secondScoring".to_string(), GAME_CONFIG.scoring.second),
-            Some(2) => ("thirdScoring".to_string(), GAME_CONFIG.scoring.third),
-            _ => ("didNotPlaceScoring".to_string(), 0),
+        ScoreCounter::placement_with_tiers(&GAME_CONFIG.scoring.placements, placement)
+    }
+
+    fn placement_with_tiers(
+        tiers: &[PlacementTierConfig],
+        placement: Option<u64>,
+    ) -> (String, u64) {
+        if let Some(placement) = placement {
+            let mut last_place = 0;
+            for tier in tiers.iter() {
+                last_place += tier.places;
+                if placement < last_place {
+                    return (tier.label.clone(), tier.points);
+                }
+            }
         }
+
+        ("didNotPlaceScoring".to_string(), 0)
     }
-    pub fn calculate(&self, placement: Option<u64>, winning_team: Option<bool>) -> u64 {
-        let mut score = self.killed_players() + self.hit_shots() + self.percent_hit(); // Hunter
-        score += self.health() + self.pings() + self.volume_pings() + self.sleeping_time(); // Prop
-        score += if winning_team.unwrap_or_else(|| false) {
+
+    fn winning_team_score(winning_team: bool) -> u64 {
+        if winning_team {
             GAME_CONFIG.scoring.winning_team
         } else {
             0
-        }; // Team
+        }
+    }
+
+    pub fn calculate(&self, placement: Option<u64>, winning_team: Option<bool>) -> u64 {
+        let mut score = self.rules_score(&GAME_CONFIG.scoring.rules); // Events
+        score += ScoreCounter::winning_team_score(winning_team.unwrap_or(false)); // Team
         score += ScoreCounter::placement(placement).1; // Placement
         score
     }
 
-    pub fn score_breakdown(&self, placement: Option<u64>, winning_team: bool) -> Value {
-        let (placement_label, placement_score) = ScoreCounter::placement(placement);
-        let percent_hit = if self.missed_shots > 0 {
-            self.hit_shots as f64 / self.missed_shots as f64
+    fn rules_score(&self, rules: &[Vec<ScoreRuleConfig>]) -> u64 {
+        rules
+            .iter()
+            .flatten()
+            .map(|rule| ScoreCounter::rule_score(rule, &self.tally(&rule.event)))
+            .sum()
+    }
+
+    /// Rows of the score breakdown as (label, value, score); `None` is spacing between sections.
+    pub fn score_breakdown_items(
+        &self,
+        placement: Option<u64>,
+        winning_team: bool,
+    ) -> Vec<Option<(String, String, u64)>> {
+        let mut items = Vec::new();
+
+        // Add a section for each group of rules
+        for section in GAME_CONFIG.scoring.rules.iter() {
+            for rule in section.iter() {
+                let tally = self.tally(&rule.event);
+                items.push(Some((
+                    rule.label.clone(),
+                    ScoreCounter::rule_display(rule, &tally),
+                    ScoreCounter::rule_score(rule, &tally),
+                )));
+            }
+            items.push(None); // Spacing
+        }
+
+        // Winning team
+        let team_label = if winning_team {
+            "winningTeamScoring"
         } else {
-            0.
+            "losingTeamScoring"
         };
+        items.push(Some((
+            team_label.to_string(),
+            "".to_string(),
+            ScoreCounter::winning_team_score(winning_team),
+        )));
+        items.push(None); // Spacing
+
+        // Placement
+        let (placement_label, placement_score) = ScoreCounter::placement(placement);
+        items.push(Some((placement_label, "".to_string(), placement_score)));
+        items.push(None); // Spacing
 
-        // Create the breakdown of the score
-        let mut breakdown = vec![
-            // Hunter:
-            Some((
-                "killedPlayersScoring".to_string(),
-                self.killed_players.to_string(),
-                self.killed_players(),
-            )),
-            Some((
-                "hitShotsScoring".to_string(),
-                self.hit_shots.to_string(),
-                self.hit_shots(),
-            )),
-            Some((
-                "percentHitScoring".to_string(),
-                format!("{:.1}%", percent_hit * 100.),
-                self.percent_hit(),
-            )),
-            None, // Spacing
-            // Prop:
-            Some((
-                "propHealthScoring".to_string(),
-                format!("{:.1}%", self.health * 100.),
-                self.health(),
-            )),
-            Some((
-                "propPingCountScoring".to_string(),
-                self.pings.to_string(),
-                self.pings(),
-            )),
-            Some((
-                "propTotalPingVolumeScoring".to_string(),
-                (self.total_ping_volume as u64).to_string(),
-                self.volume_pings(),
-            )),
-            None, // Spacing
-            // Winning team:
-            Some((
-                if winning_team {
-                    "winningTeamScoring".to_string()
-                } else {
-                    "losingTeamScoring".to_string()
-                },
-                "".to_string(),
-                if winning_team {
-                    GAME_CONFIG.scoring.winning_team
-                } else {
-                    0
-                },
-            )),
-            None, // Spacing
-            // Placement
-            Some((placement_label, "".to_string(), placement_score)),
-            None, // Spacing
-            // Total:
-            Some((
-                "totalScoring".to_string(),
-                "".to_string(),
-                self.calculate(placement, Some(winning_team)),
-            )),
-        ];
+        // Total
+        items.push(Some((
+            "totalScoring".to_string(),
+            "".to_string(),
+            self.calculate(placement, Some(winning_team)),
+        )));
 
+        items
+    }
+
+    pub fn score_breakdown(&self, placement: Option<u64>, winning_team: bool) -> Value {
         // Convert into RMPV value
-        breakdown
+        self.score_breakdown_items(placement, winning_team)
             .into_iter()
             .map(|b| {
                 b.map_or(Value::Nil, |b| {
@@ -501,3 +685,78 @@ impl ScoreCounter {
             .into()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rule(per_event: u64, per_unit: f64, per_second: f64, cap: Option<u64>) -> ScoreRuleConfig {
+        ScoreRuleConfig {
+            event: "test".to_string(),
+            label: "testScoring".to_string(),
+            display: ScoreDisplay::Count,
+            per_event,
+            per_unit,
+            per_second,
+            cap,
+        }
+    }
+
+    #[test]
+    fn test_rule_score() {
+        let mut counter = ScoreCounter::new();
+        counter.event_amount("test", 2.5);
+        counter.event_amount("test", 1.5);
+        counter.event_time("test", 10.);
+        let tally = counter.tally("test");
+        assert_eq!(tally.count, 2);
+
+        assert_eq!(
+            ScoreCounter::rule_score(&rule(10, 0., 0., None), &tally),
+            20
+        );
+        assert_eq!(ScoreCounter::rule_score(&rule(0, 2., 0., None), &tally), 8);
+        assert_eq!(ScoreCounter::rule_score(&rule(0, 0., 0.5, None), &tally), 5);
+        assert_eq!(
+            ScoreCounter::rule_score(&rule(10, 2., 0.5, Some(30)), &tally),
+            30
+        );
+
+        // Events without a rule don't give points
+        assert_eq!(counter.rules_score(&[vec![rule(10, 0., 0., None)]]), 20);
+        counter.event("unknown");
+        assert_eq!(counter.rules_score(&[vec![rule(10, 0., 0., None)]]), 20);
+    }
+
+    #[test]
+    fn test_hit_ratio() {
+        let mut counter = ScoreCounter::new();
+        counter.shot(true);
+        counter.shot(true);
+        counter.shot(true);
+        counter.shot(false);
+        assert_eq!(counter.tally(ScoreCounter::HIT_RATIO).amount, 0.75);
+    }
+
+    #[test]
+    fn test_placement_tiers() {
+        let tiers = vec![
+            PlacementTierConfig {
+                label: "firstScoring".to_string(),
+                points: 200,
+                places: 1,
+            },
+            PlacementTierConfig {
+                label: "topThreeScoring".to_string(),
+                points: 50,
+                places: 2,
+            },
+        ];
+        let points = |placement| ScoreCounter::placement_with_tiers(&tiers, placement).1;
+        assert_eq!(points(Some(0)), 200);
+        assert_eq!(points(Some(1)), 50);
+        assert_eq!(points(Some(2)), 50);
+        assert_eq!(points(Some(3)), 0);
+        assert_eq!(points(None), 0);
+    }
+}
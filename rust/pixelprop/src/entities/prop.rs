@@ -39,7 +39,7 @@ impl EntityKindInner for Prop {
             },
             self.object.position.clone(),
             Vector::zero(),
-            self.object.rotation,
+            self.object.angle(),
             self.prefab.rects.clone()
         )
     }
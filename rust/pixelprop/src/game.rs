@@ -1,5 +1,8 @@
 extern crate core;
 
+use crate::abilities::{ability_with_id, Decoy};
+use crate::bots::Bot;
+use crate::chat::{chat_audience, clean_message};
 use crate::entities::ScoreCounter;
 use crate::entities::{
     Entity, EntityBody, EntityHandle, EntityId, EntityKind, EntityKindInner, HunterState,
@@ -10,32 +13,47 @@ use crate::game_config::ObjectConfigHandle;
 use crate::game_config::PrefabConfig;
 use crate::game_config::PrefabConfigHandle;
 use crate::game_config::{
-    GameConfig, GroupConfig, MapConfig, MapObjectKind, ObjectConfig, PrefabKind, GAME_CONFIG,
+    AbilityEffect, AbilityTeam, GameConfig, GroupConfig, LobbyConfig, MapConfig, MapObjectKind,
+    ObjectConfig, PrefabKind, GAME_CONFIG,
 };
 use crate::game_world::GameWorld;
+use crate::lobbies::LobbySettings;
+use crate::map_generator::MapGenerator;
+use crate::network::ChatEvent;
 use crate::network::ClientEventData;
 use crate::network::ClientHandleData;
 use crate::network::ClientMessage;
+use crate::network::EntityDestroyedEvent;
 use crate::network::GameStateEvent;
 use crate::network::PingEvent;
 use crate::network::PlayerDeathEvent;
 use crate::network::ScoreboardUpdateEvent;
 use crate::network::ShootEvent;
 use crate::network::{ClientHandle, ClientId, SocketSender};
+use crate::snapshot::{ClientSnapshot, GameSnapshot, SnapshotError, SNAPSHOT_VERSION};
+use crate::stats_store::{
+    open_stats_store, MatchRecord, NullStatsStore, PlayerMatchRecord, StatsStore,
+};
+use crate::supervisor::{
+    panic_message, take_panic_phase, take_suspect, Recovery, Supervisor, Suspect,
+};
+use crate::teams::{team_assigner, TeamCandidate};
 use crate::utils::random_sample;
 use crate::utils::Ray;
-use crate::utils::{time_milliseconds, Counter, FloatType, Rect, Serializable, Vector};
+use crate::utils::{
+    quarter_turns, time_milliseconds, Counter, FloatType, Rect, Serializable, Vector,
+};
 #[cfg(profile)]
 use flame;
 use rand;
 use rand::Rng;
 use rmpv::Value;
+use std::any::Any;
 use std::cell::{Ref, RefCell, RefMut};
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::f64;
 use std::io::Write;
-use std::mem;
 use std::panic;
 use std::rc::Rc;
 use std::sync::mpsc::{channel, Receiver, Sender};
@@ -59,7 +77,7 @@ pub enum GameError {
 }
 
 /*** Game ***/
-#[derive(Clone)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub enum GameState {
     PreGame,
     Hiding,
@@ -78,6 +96,21 @@ impl Serializable for GameState {
 }
 
 pub struct Game {
+    /// The lobby this game is hosting
+    lobby: LobbyConfig,
+
+    /// The rules for this lobby
+    settings: LobbySettings,
+
+    /// The map in the world; a map picked by the host is played once the map is rebuilt
+    map_id: String,
+
+    /// The client that can change the settings of a custom lobby
+    host: Option<ClientId>,
+
+    /// Number of rounds started since the host last changed the settings
+    rounds_played: u64,
+
     /// The state of the game
     state: GameState,
 
@@ -113,16 +146,56 @@ pub struct Game {
 
     /// If the minimap changed since the last update
     minimap_changed: bool,
+
+    /// The seed the current map was generated with; `None` if the map isn't generated
+    map_seed: Option<u64>,
+
+    /// The map that was generated for this round so it can be saved
+    generated_map: Option<MapConfig>,
+
+    /// Seed to use the next time the map is generated
+    next_map_seed: Option<u64>,
+
+    /// Where match history and lifetime stats are saved
+    stats_store: Box<dyn StatsStore>,
+
+    /// The time (in milliseconds) at which the current round started
+    round_start_time: u64,
+
+    /// Bots that fill the game when there aren't enough players, by their client's id
+    bots: HashMap<ClientId, Bot>,
+
+    /// Decoys spawned by the props' abilities
+    decoys: Vec<Decoy>,
+
+    /// Players restored from a snapshot that are waiting for their client to reconnect, by the
+    /// reclaim secret their client was issued
+    reclaimable_players: HashMap<String, EntityId>,
+
+    /// Recovers the game when an update panics
+    supervisor: Supervisor,
 }
 
 impl panic::UnwindSafe for Game {}
 
 impl Game {
     pub const UPDATE_DELAY: u64 = 66; // 15/sec
-
-    pub fn new(rx_client_handle: Receiver<ClientHandleData>) -> Game {
+    pub const PRE_GAME_DURATION: f64 = 10.;
+    pub const HIDING_DURATION: f64 = 15.;
+    pub const HUNTING_DURATION: f64 = 5. * 60.;
+    pub const MAX_SPAWN_ATTEMPTS: usize = 20;
+    pub const DECOY_GAP: FloatType = 4.; // Space between a prop player and their decoy
+    pub const DECOY_DIRECTIONS: usize = 8; // Sides of a prop player a decoy can spawn on
+    pub const RECLAIM_SECRET_LENGTH: usize = 32;
+
+    pub fn new(rx_client_handle: Receiver<ClientHandleData>, lobby: LobbyConfig) -> Game {
         // Create a Game object
         let mut game = Game {
+            settings: LobbySettings::new(&lobby.overrides),
+            map_id: String::new(),
+            lobby,
+            host: None,
+            rounds_played: 0,
             state: GameState::PreGame,
             state_timer: 0.,
             world: GameWorld::new(),
@@ -135,6 +208,21 @@ impl Game {
             update_time: 0,
             cached_minimap: Value::Array(Vec::new()),
             minimap_changed: false,
+            map_seed: None,
+            generated_map: None,
+            next_map_seed: None,
+            stats_store: open_stats_store(&GAME_CONFIG.stats_store).unwrap_or_else(|err| {
+                println!(
+                    "Failed to open stats store, stats will not be saved: {}",
+                    err
+                );
+                Box::new(NullStatsStore)
+            }),
+            round_start_time: 0,
+            bots: HashMap::new(),
+            decoys: Vec::new(),
+            reclaimable_players: HashMap::new(),
+            supervisor: Supervisor::new(),
         };
 
         // Build the map
@@ -143,6 +231,14 @@ impl Game {
         // Set the state
         game.set_pre_game_state();
 
+        // Pick up where another process left off
+        if let Some(path) = game.lobby.snapshot.clone() {
+            match GameSnapshot::load(&path).and_then(|snapshot| game.restore(snapshot)) {
+                Ok(_) => println!("Restored lobby {} from {}", game.lobby.id, path),
+                Err(err) => println!("Failed to restore snapshot {}: {}", path, err),
+            }
+        }
+
         game
     }
 
@@ -162,20 +258,9 @@ impl Game {
 
             // Perform the update
             self.update_time = time_milliseconds();
-            //            let update_panic_result = panic::catch_unwind(|| {
-            // Execute the update
-            match self.update() {
-                Ok(_) => {}
-                Err(err) => println!("Game update error: {:?}", err),
-            }
-            //            });
+            self.supervised_update();
             let update_end = time_milliseconds();
 
-            // Print the error if the update_panicked
-            //            if let Err(err) = update_panic_result {
-            //                println!("Update panicked with error: {:?}\nAttempting recovery.", err);
-            //            }
-
             // Calculate the update duration
             let update_duration = if update_end > self.update_time {
                 update_end - self.update_time
@@ -264,13 +349,26 @@ impl Game {
             println!("World update error: {:?}", err);
         }
 
+        /* Update the decoys */
+        self.update_decoys();
+
+        /* Update the bots */
+        self.update_bots();
+
         /* Send client updates */
         // Send the updates if it's been long enough
         {
             measure!("Send updates");
 
             // Send the updates
-            for (_, client) in self.clients.borrow_mut().iter_mut() {
+            for (id, client) in self.clients.borrow_mut().iter_mut() {
+                // Bots read the world directly
+                if client.is_bot() {
+                    continue;
+                }
+
+                let _suspect = Suspect::Client(*id).watch();
+
                 client.build_update_message(
                     &self.state,
                     self.state_timer,
@@ -320,10 +418,19 @@ impl Game {
         // Handle messages from connections
         let mut disconnections = Vec::new();
         let mut entities_to_spawn = Vec::new();
+        let mut decoys_to_spawn = Vec::new(); // (owner id, object, lifetime)
         let mut entities_to_destroy = Vec::new();
         let mut client_event_buffer = Vec::<Box<ClientEventData>>::new();
         let mut temp_force_switch_state = None;
+        let mut mutes = Vec::new(); // (client id, is muted)
+        let mut list_clients = false;
+        let mut setting_changes = Vec::new();
+        let mut start_rounds = false;
+        let mut snapshot_to_save = None;
+        let mut snapshot_to_load = None;
         for (id, client) in self.clients.borrow_mut().iter_mut() {
+            let _suspect = Suspect::Client(*id).watch();
+
             // Remove the player's ID if it doesn't exist
             if let Some(id) = client.player_id {
                 client.spectating_id = None; // Remove spectating if has player
@@ -349,6 +456,7 @@ impl Game {
                     ClientMessage::Leave { .. } => {}
                     ClientMessage::CheatCode(_) => {}
                     ClientMessage::TempData { .. } => {}
+                    ClientMessage::Chat { .. } => {}
 
                     // All other messages require the player
                     _ => {
@@ -361,23 +469,69 @@ impl Game {
 
                 // Decide what to do with the message
                 match message {
-                    ClientMessage::Auth { rivet_player_token } => {
+                    ClientMessage::Auth {
+                        rivet_player_token,
+                        reclaim_secret,
+                        ..
+                    } => {
                         if client.rivet_player_token.is_none() {
                             println!("Player authenticating");
 
                             // TODO: This is blocking the game loop
-                            match crate::rivet::player_connected(rivet_player_token.clone()) {
-                                Ok(_) => {
+                            match crate::rivet::player_connected(
+                                &self.lobby.token_var,
+                                rivet_player_token.clone(),
+                            ) {
+                                Ok(rivet_identity_id) => {
                                     println!("Player connected");
 
+                                    // Give the player back their entity if they were in the
+                                    // snapshot the game was restored from. Rivet player tokens
+                                    // only work in the lobby that issued them, so players are
+                                    // matched by the secret the server gave their client.
+                                    let reclaimable_players = &mut self.reclaimable_players;
+                                    let reclaimed = reclaim_secret.and_then(|secret| {
+                                        let player_id = reclaimable_players.remove(&secret)?;
+                                        Some((secret, player_id))
+                                    });
+                                    let secret = match reclaimed {
+                                        Some((secret, player_id)) => {
+                                            if let Some(entity) =
+                                                self.world.entity_with_id(&player_id)
+                                            {
+                                                if let EntityKind::Player(ref player) =
+                                                    *entity.borrow().kind()
+                                                {
+                                                    client.username =
+                                                        Some(player.username().clone());
+                                                }
+                                                client.player_id = Some(player_id);
+                                            }
+                                            secret
+                                        }
+                                        None => Game::new_reclaim_secret(),
+                                    };
+
                                     client.rivet_player_token = Some(rivet_player_token);
+                                    client.rivet_identity_id = rivet_identity_id;
 
                                     // Send init config
                                     client.send_init();
+                                    client.send_reclaim_secret(&secret);
+                                    client.reclaim_secret = Some(secret);
+                                    client.send_map_info(&self.map_config().name, self.map_seed);
+                                    if self.lobby.custom {
+                                        client.send_lobby_settings(
+                                            self.host == Some(*id),
+                                            self.settings.serialize(),
+                                        );
+                                    }
                                 }
                                 Err(err) => {
                                     println!("Failed to connect player: {:?}", err);
-                                    client.socket_out().close(CloseCode::Normal);
+                                    if let Some(out) = client.socket_out() {
+                                        out.close(CloseCode::Normal).ok();
+                                    }
                                 }
                             }
                         }
@@ -385,6 +539,7 @@ impl Game {
                     ClientMessage::Join {
                         username,
                         character_id,
+                        party,
                     } => {
                         measure!("Join message");
 
@@ -392,6 +547,7 @@ impl Game {
                         if client.player_id != None {
                             continue;
                         }
+                        client.username = Some(username.clone());
 
                         match self.state {
                             GameState::PreGame => { /* Let the player join */ }
@@ -402,9 +558,9 @@ impl Game {
                         }
 
                         // Validate the character id
-                        if !GAME_CONFIG
-                            .store
-                            .characters
+                        if !self
+                            .settings
+                            .characters()
                             .iter()
                             .any(|p| p.id == character_id)
                         {
@@ -416,6 +572,7 @@ impl Game {
                         let mut player = Entity::new(EntityKind::Player(Player::new(
                             username.clone(),
                             character_id,
+                            party,
                             client.is_admin,
                         )));
                         player.set_label(Some(username));
@@ -428,7 +585,7 @@ impl Game {
                         let player_handle = self.world.insert_entity(player)?;
 
                         // Set spawn position
-                        self.move_player_to_spawn(player_handle.borrow_mut());
+                        self.move_player_to_spawn(&player_handle);
 
                         // Set the state (so it sends initial data like the shoot delay)
                         self.set_player_state(client, PlayerState::default_state());
@@ -513,13 +670,10 @@ impl Game {
                         {
                             let mut hit_entity = hit_entity.borrow_mut();
 
-                            // Find the entity that was hit and destroy it if it's a normal prop; if
-                            // this returns `None`, then do no damage
+                            // Find the entity that was hit and damage it if it's a prop or a
+                            // destructible fixture; if this returns `None`, then do no damage
                             let prefab_health = match *hit_entity.kind() {
-                                EntityKind::Prop(ref prop) => match prop.prefab.kind {
-                                    PrefabKind::Prop { .. } => Some(prop.get_prefab().health),
-                                    _ => None,
-                                },
+                                EntityKind::Prop(ref prop) => prop.prefab.damageable_health(),
                                 EntityKind::Player(ref player) => {
                                     match *player.state() {
                                         PlayerState::Prop(_) => {
@@ -527,6 +681,7 @@ impl Game {
                                             match self.state {
                                                 GameState::PreGame => None,
                                                 GameState::Hiding => None,
+                                                _ if self.in_hunting_grace_period() => None,
                                                 _ => Some(player.get_prefab().health),
                                             }
                                         }
@@ -649,27 +804,136 @@ impl Game {
                         let entity = self.player_entity(client)?;
                         let kind = &mut *entity.kind_mut();
                         if let &mut EntityKind::Player(ref mut player) = kind {
-                            if let PlayerState::Prop(ref mut prop_state) = *player.state_mut() {
+                            if let PlayerState::Prop(ref prop_state) = *player.state() {
                                 // Make sure it's been long enough so the player can force ping
                                 if (time_milliseconds() - prop_state.last_ping_time) as f64 / 1000.
-                                    > GAME_CONFIG.player.ping_delay_min
+                                    <= GAME_CONFIG.player.ping_delay_min
                                 {
-                                    // Send the ping
-                                    let did_ping = self.ping_prop(
-                                        client,
-                                        &mut client_event_buffer,
-                                        prop_state,
-                                        entity.body(),
-                                    );
-
-                                    // Increase score
-                                    if did_ping {
-                                        player.score_counter_mut().pinged(entity.body().volume());
-                                    }
-                                } else {
                                     continue;
                                 }
                             }
+                            self.ping_prop(client, &mut client_event_buffer, player, &entity);
+                        }
+                    }
+                    ClientMessage::LobbySetting(setting) => {
+                        measure!("Lobby setting message");
+
+                        // Only the host can change the settings, and only before the round starts
+                        if !self.lobby.custom || self.host != Some(*id) {
+                            println!("Only the host can change the lobby settings");
+                            continue;
+                        }
+                        match self.state {
+                            GameState::PreGame => setting_changes.push(setting),
+                            _ => continue,
+                        }
+                    }
+                    ClientMessage::StartRounds => {
+                        measure!("Start rounds message");
+
+                        // Lets the host start another set of rounds once they're all played
+                        if !self.lobby.custom || self.host != Some(*id) {
+                            println!("Only the host can start the rounds");
+                            continue;
+                        }
+                        match self.state {
+                            GameState::PreGame => start_rounds = true,
+                            _ => continue,
+                        }
+                    }
+                    ClientMessage::UseAbility { ability_id } => {
+                        measure!("Use ability");
+
+                        // Abilities can only be used while hunting
+                        match self.state {
+                            GameState::Hunting => {}
+                            _ => continue,
+                        }
+
+                        let ability = match ability_with_id(&ability_id) {
+                            Some(ability) => ability,
+                            None => {
+                                println!("Invalid ability id: {}", ability_id);
+                                continue;
+                            }
+                        };
+
+                        let entity = self.player_entity(client)?;
+                        let mut kind = entity.kind_mut();
+                        let player = match *kind {
+                            EntityKind::Player(ref mut player) => player,
+                            EntityKind::Prop(_) => continue,
+                        };
+                        let team = match *player.state() {
+                            PlayerState::Hunter(_) => AbilityTeam::Hunter,
+                            PlayerState::Prop(_) => AbilityTeam::Prop,
+                        };
+                        if team != ability.team {
+                            continue;
+                        }
+
+                        // Make sure the effect can be applied before using a charge
+                        let decoy = match ability.effect {
+                            AbilityEffect::Decoy { lifetime } => {
+                                let disguise = match *player.state() {
+                                    PlayerState::Prop(ref prop_state) => {
+                                        prop_state.disguise.clone()
+                                    }
+                                    PlayerState::Hunter(_) => continue,
+                                };
+                                let position = match self.find_decoy_position(&entity) {
+                                    Some(position) => position,
+                                    None => continue,
+                                };
+                                let prefab = disguise.unwrap_or_else(|| player.get_prefab());
+                                let (rotation, yaw) = ObjectConfig::rotation_from_angle(
+                                    *entity.body().get_rotation(),
+                                );
+                                let object = ObjectConfigHandle::new(ObjectConfig {
+                                    prefab_id: prefab.id.clone(),
+                                    position,
+                                    rotation,
+                                    yaw,
+                                    spawn_chance: 1.,
+                                });
+                                Some((object, lifetime))
+                            }
+                            AbilityEffect::Ping => match *player.state() {
+                                PlayerState::Prop(_) => None,
+                                PlayerState::Hunter(_) => continue,
+                            },
+                            AbilityEffect::Scan { .. } => None,
+                        };
+
+                        // Use a charge of the ability
+                        let now = time_milliseconds();
+                        if player.abilities_mut().try_use(ability, now).is_err() {
+                            continue;
+                        }
+                        client.send_ability_cooldown(
+                            &ability.id,
+                            Some(player.abilities().cooldown(ability, now)),
+                            player.abilities().charges_left(ability),
+                        );
+                        if let Some(ref score_event) = ability.score_event {
+                            player.score_counter_mut().event(score_event);
+                        }
+
+                        // Apply the effect
+                        match ability.effect {
+                            AbilityEffect::Scan { radius } => {
+                                let position = entity.body().get_pos().clone();
+                                drop(kind); // The scan reads every player, including this one
+                                self.scan_props(&position, radius, &mut client_event_buffer);
+                            }
+                            AbilityEffect::Decoy { .. } => {
+                                if let Some((object, lifetime)) = decoy {
+                                    decoys_to_spawn.push((*entity.id(), object, lifetime));
+                                }
+                            }
+                            AbilityEffect::Ping => {
+                                self.ping_prop(client, &mut client_event_buffer, player, &entity);
+                            }
                         }
                     }
                     ClientMessage::CheatCode(code) => {
@@ -725,19 +989,21 @@ impl Game {
                                 // Read the data
                                 let asset = player_entity.asset().clone();
                                 let position = body.get_pos().clone();
-                                let rotation = body.get_rotation().clone();
+                                let (rotation, yaw) =
+                                    ObjectConfig::rotation_from_angle(*body.get_rotation());
 
                                 // Move the player up
                                 body.get_pos_mut().z += 100.;
 
                                 // Print out the object definition
-                                println!("- prefabId: {asset}\n  position: {position}\n  rotation: {rotation}", asset = asset, position = position, rotation = rotation);
+                                println!("- prefabId: {asset}\n  position: {position}\n  rotation: {rotation}\n  yaw: {yaw}", asset = asset, position = position, rotation = rotation, yaw = yaw);
 
                                 // Spawn an object
                                 entities_to_spawn.push(ObjectConfigHandle::new(ObjectConfig {
                                     prefab_id: asset,
                                     position,
                                     rotation,
+                                    yaw,
                                     spawn_chance: 1.,
                                 }));
                             }
@@ -765,6 +1031,7 @@ impl Game {
                                                     base_pos.z + 0.,
                                                 ),
                                                 rotation: 0,
+                                                yaw: 0.,
                                                 spawn_chance: 1.,
                                             },
                                         ));
@@ -775,6 +1042,60 @@ impl Game {
                                 }
                             }
 
+                            // Set the seed for the next generated map
+                            "map-seed" => match msg_body.parse::<u64>() {
+                                Ok(seed) => self.next_map_seed = Some(seed),
+                                Err(_) => println!("Invalid map seed {}", msg_body),
+                            },
+
+                            // Save the generated map so it can be added to the config
+                            "save-map" => self.save_generated_map(msg_body),
+
+                            // Export the world so it can be edited in Tiled
+                            "export-tiled" => match Game::admin_file_path(msg_body, "map.tmj") {
+                                Ok(path) => {
+                                    match crate::tiled::export_world_to_file(&self.world, &path) {
+                                        Ok(_) => println!("Exported map to {}", path),
+                                        Err(err) => println!("Failed to export map: {}", err),
+                                    }
+                                }
+                                Err(err) => println!("Failed to export map: {}", err),
+                            },
+
+                            // Print the top players' lifetime stats
+                            "leaderboard" => {
+                                let limit = msg_body.parse::<usize>().unwrap_or(10);
+                                match self.stats_store.leaderboard(limit) {
+                                    Ok(leaderboard) => {
+                                        for (i, stats) in leaderboard.iter().enumerate() {
+                                            println!("{}. {:?}", i + 1, stats);
+                                        }
+                                    }
+                                    Err(err) => println!("Failed to load leaderboard: {}", err),
+                                }
+                            }
+
+                            // Print a player's lifetime stats by their Rivet identity
+                            "player-stats" => match self.stats_store.lifetime_stats(msg_body) {
+                                Ok(Some(stats)) => println!("{:?}", stats),
+                                Ok(None) => println!("No stats for {}", msg_body),
+                                Err(err) => println!("Failed to load player stats: {}", err),
+                            },
+
+                            // Save or restore the whole game
+                            "save-snapshot" => snapshot_to_save = Some(msg_body.to_string()),
+                            "load-snapshot" => snapshot_to_load = Some(msg_body.to_string()),
+
+                            // Mute or unmute a player's chat by their client id, since
+                            // usernames aren't unique
+                            "mute" | "unmute" => match msg_body.parse::<ClientId>() {
+                                Ok(client_id) => mutes.push((client_id, msg_type == "mute")),
+                                Err(_) => println!("Invalid client id {}", msg_body),
+                            },
+
+                            // Print the client ids to mute by
+                            "clients" => list_clients = true,
+
                             "round-position" => {
                                 // Rounds the player's position to the nearest 0.5
                                 let mut player_entity = self.player_entity_mut(client)?;
@@ -789,6 +1110,43 @@ impl Game {
                             t @ _ => println!("Invalid temp message type {}", t),
                         }
                     }
+                    ClientMessage::Chat { channel, message } => {
+                        measure!("Chat message");
+
+                        // Only players that joined can chat
+                        let username = match client.username {
+                            Some(ref username) => username.clone(),
+                            None => continue,
+                        };
+                        if client.is_muted {
+                            continue;
+                        }
+
+                        // Check the rate limit and clean up the message
+                        let config = &GAME_CONFIG.chat;
+                        if !client.chat_limiter.try_send(time_milliseconds(), config) {
+                            println!("Chat rate limit hit by {}", username);
+                            continue;
+                        }
+                        let message = match clean_message(&message, config) {
+                            Some(message) => message,
+                            None => continue,
+                        };
+
+                        // Send the message to the teams that can read it
+                        let round_active = match self.state {
+                            GameState::PreGame => false,
+                            GameState::Hiding | GameState::Hunting => true,
+                        };
+                        let audience =
+                            chat_audience(channel, client.team(&self.world), round_active);
+                        client_event_buffer.push(Box::new(ChatEvent {
+                            username,
+                            channel,
+                            message,
+                            audience,
+                        }));
+                    }
                     ClientMessage::Leave => {
                         measure!("Leave message");
 
@@ -796,7 +1154,10 @@ impl Game {
 
                         if let Some(rivet_player_token) = &client.rivet_player_token {
                             // TODO: This is blocking the game loop
-                            match crate::rivet::player_disconnected(rivet_player_token.clone()) {
+                            match crate::rivet::player_disconnected(
+                                &self.lobby.token_var,
+                                rivet_player_token.clone(),
+                            ) {
                                 Ok(_) => {
                                     println!("Player disconnected");
                                 }
@@ -832,7 +1193,7 @@ impl Game {
                 if let EntityKind::Player(ref player) = *kind {
                     if let PlayerState::Hunter(_) = *player.state() {
                         match self.state {
-                            GameState::Hiding => {
+                            GameState::Hiding if self.map_config().spawn_protection.pin_hunters => {
                                 let mut entity_body = entity.body_mut();
                                 entity_body.get_pos_mut().copy_from(player.spawn_pos());
                                 entity_body.get_vel_mut().set(0., 0., 0.);
@@ -856,26 +1217,15 @@ impl Game {
                 let entity = self.player_entity(client)?;
                 let kind = &mut *entity.kind_mut();
                 if let &mut EntityKind::Player(ref mut player) = kind {
-                    match player.state_mut() {
-                        &mut PlayerState::Prop(ref mut prop_state) => {
-                            // Determine if needs ping
-                            let time = time_milliseconds();
-                            if time >= prop_state.next_ping_time {
-                                // Ping
-                                let did_ping = self.ping_prop(
-                                    client,
-                                    &mut client_event_buffer,
-                                    prop_state,
-                                    entity.body(),
-                                );
-
-                                // Increase score
-                                if did_ping {
-                                    player.score_counter_mut().pinged(entity.body().volume());
-                                }
-                            }
+                    // Determine if needs ping
+                    let needs_ping = match *player.state() {
+                        PlayerState::Prop(ref prop_state) => {
+                            time_milliseconds() >= prop_state.next_ping_time
                         }
-                        _ => { /* Do nothing */ }
+                        PlayerState::Hunter(_) => false,
+                    };
+                    if needs_ping {
+                        self.ping_prop(client, &mut client_event_buffer, player, &entity);
                     }
                 } else {
                     println!("Missing player kind.");
@@ -905,6 +1255,59 @@ impl Game {
             }
         }
 
+        // Apply the mutes
+        for (client_id, is_muted) in mutes.into_iter() {
+            match self.clients.borrow_mut().get_mut(&client_id) {
+                Some(client) => client.is_muted = is_muted,
+                None => println!("No client {} to mute", client_id),
+            }
+        }
+        if list_clients {
+            for (id, client) in self.clients.borrow().iter() {
+                println!(
+                    "Client {}: {:?} (muted: {})",
+                    id, client.username, client.is_muted
+                );
+            }
+        }
+
+        // Save or restore snapshots
+        if let Some(file_name) = snapshot_to_save {
+            match Game::admin_file_path(&file_name, "snapshot.json") {
+                Ok(path) => match self.snapshot().save(&path) {
+                    Ok(_) => println!("Saved snapshot to {}", path),
+                    Err(err) => println!("Failed to save snapshot: {}", err),
+                },
+                Err(err) => println!("Failed to save snapshot: {}", err),
+            }
+        }
+        if let Some(file_name) = snapshot_to_load {
+            match Game::admin_file_path(&file_name, "snapshot.json") {
+                Ok(path) => {
+                    match GameSnapshot::load(&path).and_then(|snapshot| self.restore(snapshot)) {
+                        Ok(_) => println!("Restored snapshot from {}", path),
+                        Err(err) => println!("Failed to restore snapshot: {}", err),
+                    }
+                }
+                Err(err) => println!("Failed to restore snapshot: {}", err),
+            }
+        }
+
+        // Apply the host's settings
+        if !setting_changes.is_empty() {
+            for setting in setting_changes.into_iter() {
+                match self.settings.apply(setting, &GAME_CONFIG.custom_lobbies) {
+                    Ok(_) => self.rounds_played = 0,
+                    Err(err) => println!("Invalid lobby setting: {:?}", err),
+                }
+            }
+            self.broadcast_lobby_settings();
+        }
+        if start_rounds {
+            self.rounds_played = 0;
+            self.state_timer = 0.;
+        }
+
         // Temp switch the state
         if let Some(new_state) = temp_force_switch_state {
             match new_state {
@@ -973,10 +1376,19 @@ impl Game {
 
         // Commit changes that occurred while processing the updates
         for object in entities_to_spawn.into_iter() {
-            self.spawn_object(&object, &Vector::zero(), 0, false);
+            self.spawn_object(&object, &Vector::zero(), 0., false);
+        }
+        for (owner_id, object, lifetime) in decoys_to_spawn.into_iter() {
+            self.spawn_decoy(owner_id, &object, lifetime);
         }
+        let mut destroyed_fixture = false;
         for id in entities_to_destroy.into_iter() {
-            self.world.remove_entity_with_id(&id)?;
+            let entity = self.world.remove_entity_with_id(&id)?;
+            destroyed_fixture |= self.break_entity(&entity, &mut client_event_buffer);
+        }
+        if destroyed_fixture {
+            self.cached_minimap = self.generate_minimap();
+            self.minimap_changed = true;
         }
         for event in client_event_buffer.into_iter() {
             self.broadcast_event(event);
@@ -1003,9 +1415,64 @@ impl Game {
             }
         }
 
+        // Pick a new host if needed
+        if self.lobby.custom {
+            self.update_host();
+        }
+
         Ok(())
     }
 
+    /// Spawns the debris for an entity that was destroyed and lets the clients know. Returns
+    /// true if the entity was a fixture, since the minimap needs to be updated.
+    fn break_entity(
+        &mut self,
+        entity: &EntityHandle,
+        client_event_buffer: &mut Vec<Box<dyn ClientEventData>>,
+    ) -> bool {
+        // Read the entity
+        let (entity_id, prefab, position, rotation) = {
+            let entity = entity.borrow();
+            let prefab = match *entity.kind() {
+                EntityKind::Prop(ref prop) => prop.prefab.clone(),
+                EntityKind::Player(_) => return false,
+            };
+            let body = entity.body();
+            (
+                *entity.id(),
+                prefab,
+                body.get_pos().clone(),
+                *body.get_rotation(),
+            )
+        };
+
+        // Spawn the debris around the entity
+        for debris in prefab.debris.iter() {
+            let mut debris_position = debris.position.clone();
+            debris_position.rotate_z(rotation);
+            debris_position.add(&position, 1.);
+            let object = ObjectConfigHandle::new(ObjectConfig {
+                prefab_id: debris.prefab_id.clone(),
+                position: debris_position,
+                rotation: debris.rotation,
+                yaw: debris.yaw,
+                spawn_chance: debris.spawn_chance,
+            });
+            self.spawn_object(&object, &Vector::zero(), rotation, true);
+        }
+
+        // Broadcast the destruction
+        client_event_buffer.push(Box::new(EntityDestroyedEvent {
+            entity_id,
+            position,
+        }));
+
+        match prefab.kind {
+            PrefabKind::Fixture => true,
+            PrefabKind::Prop => false,
+        }
+    }
+
     /// Sets the player's state to a hunter.
     fn set_player_to_hunter(&self, client: &ClientHandle) -> Result<(), GameError> {
         self.set_player_state(client, PlayerState::Hunter(HunterState::new()))
@@ -1065,6 +1532,9 @@ impl Game {
         entity.apply_prefab(prefab);
         entity.set_uses_dir(uses_dir);
 
+        // Tell the client which abilities it has now
+        self.send_ability_cooldowns(client, &entity);
+
         Ok(())
     }
 
@@ -1083,27 +1553,44 @@ impl Game {
         random_sample(valid_entities).map(|e| e.borrow().id().clone())
     }
 
-    /// Broadcasts a ping from the prop's location. Returns if the ping went through.
+    /// Broadcasts a ping from the prop's location and from its decoys, then scores the pings.
+    /// Does nothing if the player isn't a prop or the round isn't in the hunting phase.
     fn ping_prop(
         &self,
         client: &ClientHandle,
         client_event_buffer: &mut Vec<Box<ClientEventData>>,
-        prop_state: &mut PropState,
-        body: Ref<EntityBody>,
-    ) -> bool {
+        player: &mut Player,
+        entity: &Entity,
+    ) {
         // Don't do anything if not hunting
         match self.state {
             GameState::Hunting => {}
-            _ => return false,
+            _ => return,
         }
 
+        let prop_state = match *player.state_mut() {
+            PlayerState::Prop(ref mut prop_state) => prop_state,
+            PlayerState::Hunter(_) => return,
+        };
         let time = time_milliseconds();
+        let body = entity.body();
 
         // Broadcast the ping
         client_event_buffer.push(Box::new(PingEvent {
             point: body.get_pos().clone(),
         }));
 
+        // Ping the decoys too
+        let mut decoy_pings = 0;
+        for decoy in self.decoys.iter().filter(|d| d.owner_id == *entity.id()) {
+            if let Some(decoy_entity) = self.world.entity_with_id(&decoy.entity_id) {
+                client_event_buffer.push(Box::new(PingEvent {
+                    point: decoy_entity.borrow().body().get_pos().clone(),
+                }));
+                decoy_pings += 1;
+            }
+        }
+
         // Update last ping time
         prop_state.last_ping_time = time;
 
@@ -1111,7 +1598,12 @@ impl Game {
         let volume = body.volume();
         self.set_ping_time(client, prop_state, volume, false);
 
-        true
+        // Increase score
+        let score_counter = player.score_counter_mut();
+        score_counter.pinged(volume);
+        for _ in 0..decoy_pings {
+            score_counter.event(ScoreCounter::DECOY_PING);
+        }
     }
 
     /// Sets the player's prop
@@ -1119,7 +1611,7 @@ impl Game {
         &self,
         client: &ClientHandle,
         prefab: PrefabConfigHandle,
-        rotation: u8,
+        rotation: FloatType,
     ) -> Result<(), GameError> {
         measure!("Set player prop");
 
@@ -1134,7 +1626,7 @@ impl Game {
         };
 
         // Apply the entity prefab to the player
-        entity.apply_prefab(prefab);
+        entity.apply_prefab(prefab.clone());
         entity.body_mut().set_rotation(rotation);
 
         // Set a new ping if the calculated ping is sooner than the current ping; this way, a player
@@ -1142,6 +1634,8 @@ impl Game {
         // large object
         if let EntityKind::Player(ref mut player) = *entity.kind_mut() {
             if let PlayerState::Prop(ref mut prop_state) = *player.state_mut() {
+                prop_state.disguise = Some(prefab);
+
                 let body = entity.body();
                 let volume = body.volume();
                 self.set_ping_time(client, prop_state, volume, true);
@@ -1178,6 +1672,586 @@ impl Game {
     }
 }
 
+/*** Abilities ***/
+impl Game {
+    /// Sends the cooldown of every ability to the client; abilities for the other team are sent
+    /// without a cooldown so the client hides them.
+    fn send_ability_cooldowns(&self, client: &ClientHandle, entity: &Entity) {
+        if let EntityKind::Player(ref player) = *entity.kind() {
+            let team = match *player.state() {
+                PlayerState::Hunter(_) => AbilityTeam::Hunter,
+                PlayerState::Prop(_) => AbilityTeam::Prop,
+            };
+            let now = time_milliseconds();
+            for ability in GAME_CONFIG.abilities.iter() {
+                if ability.team == team {
+                    client.send_ability_cooldown(
+                        &ability.id,
+                        Some(player.abilities().cooldown(ability, now)),
+                        player.abilities().charges_left(ability),
+                    );
+                } else {
+                    client.send_ability_cooldown(&ability.id, None, None);
+                }
+            }
+        }
+    }
+
+    /// Pings every prop player and decoy within the radius of a point.
+    fn scan_props(
+        &self,
+        position: &Vector,
+        radius: FloatType,
+        client_event_buffer: &mut Vec<Box<dyn ClientEventData>>,
+    ) {
+        let area = Rect::new(
+            Vector::zero(),
+            Vector::new(radius * 2., radius * 2., 99999.),
+        );
+        let found = self
+            .world
+            .query_rect_all(&area, position, true, false, |e| {
+                let in_radius = e.body().get_pos().distance(position) <= radius;
+                let is_prop = match *e.kind() {
+                    EntityKind::Player(ref player) => match *player.state() {
+                        PlayerState::Prop(_) => true,
+                        PlayerState::Hunter(_) => false,
+                    },
+                    EntityKind::Prop(_) => self.decoys.iter().any(|d| d.entity_id == *e.id()),
+                };
+                in_radius && is_prop
+            });
+        for entity in found.into_iter() {
+            client_event_buffer.push(Box::new(PingEvent {
+                point: entity.borrow().body().get_pos().clone(),
+            }));
+        }
+    }
+
+    /// Finds a spot next to a prop player where a decoy the size of the player doesn't overlap
+    /// anything, so the decoy doesn't give away the player's position.
+    fn find_decoy_position(&self, entity: &Entity) -> Option<Vector> {
+        let body = entity.body();
+        let rect = body.bounding_rect().as_ref()?;
+        let distance = FloatType::max(rect.size.x, rect.size.y) + Game::DECOY_GAP;
+
+        // Try each side of the player, starting from a random one
+        let start = rand::thread_rng().gen_range(0, Game::DECOY_DIRECTIONS);
+        for i in 0..Game::DECOY_DIRECTIONS {
+            let angle = ((start + i) % Game::DECOY_DIRECTIONS) as FloatType * 2. * f64::consts::PI
+                / Game::DECOY_DIRECTIONS as FloatType;
+            let mut position = body.get_pos().clone();
+            position.x += angle.cos() * distance;
+            position.y += angle.sin() * distance;
+
+            let is_blocked = self
+                .world
+                .query_rect(rect, &position, false, false, |e| e.id() != entity.id())
+                .is_some();
+            if !is_blocked {
+                return Some(position);
+            }
+        }
+
+        None
+    }
+
+    /// Spawns a decoy for a prop player.
+    fn spawn_decoy(
+        &mut self,
+        owner_id: EntityId,
+        object: &ObjectConfigHandle,
+        lifetime: FloatType,
+    ) {
+        if let Some(entity_id) = self.spawn_object(object, &Vector::zero(), 0., false) {
+            self.decoys.push(Decoy {
+                entity_id,
+                owner_id,
+                expire_time: time_milliseconds() + (lifetime * 1000.) as u64,
+            });
+        }
+    }
+
+    /// Removes the decoys that expired, were destroyed or whose owner is gone.
+    fn update_decoys(&mut self) {
+        let now = time_milliseconds();
+        let world = &mut self.world;
+        self.decoys.retain(|decoy| {
+            let alive = world.entity_with_id(&decoy.entity_id).is_some();
+            let has_owner = world.entity_with_id(&decoy.owner_id).is_some();
+            if alive && has_owner && now < decoy.expire_time {
+                return true;
+            }
+
+            // Remove the decoy's entity if it's still around
+            if alive {
+                world.remove_entity_with_id(&decoy.entity_id).ok();
+            }
+            false
+        });
+    }
+}
+
+/*** Snapshots ***/
+impl Game {
+    /// Saves the state of the game so it can be restored later, possibly by another process.
+    pub fn snapshot(&self) -> GameSnapshot {
+        measure!("Snapshot");
+
+        let clients = self
+            .clients
+            .borrow()
+            .iter()
+            .map(|(id, client)| ClientSnapshot {
+                id: *id,
+                is_bot: client.is_bot(),
+                is_admin: client.is_admin,
+                is_muted: client.is_muted,
+                username: client.username.clone(),
+                rivet_identity_id: client.rivet_identity_id.clone(),
+                reclaim_secret: client.reclaim_secret.clone(),
+                player_id: client.player_id,
+                spectating_id: client.spectating_id,
+            })
+            .collect();
+
+        GameSnapshot {
+            version: SNAPSHOT_VERSION,
+            lobby_id: self.lobby.id.clone(),
+            taken_at: time_milliseconds(),
+            state: self.state.clone(),
+            state_timer: self.state_timer,
+            round_elapsed: self.update_time.saturating_sub(self.round_start_time),
+            rounds_played: self.rounds_played,
+            settings: self.settings.clone(),
+            map_id: Some(self.map_id.clone()),
+            map_seed: self.map_seed,
+            next_map_seed: self.next_map_seed,
+            entities: self
+                .world
+                .entities()
+                .iter()
+                .map(|entity| entity.borrow().snapshot())
+                .collect(),
+            clients,
+            decoys: self.decoys.clone(),
+        }
+    }
+
+    /// Issues a secret a client can reconnect with to take their player back after a restore.
+    fn new_reclaim_secret() -> String {
+        let mut rng = rand::OsRng::new().expect("open the OS random number generator");
+        rng.gen_ascii_chars()
+            .take(Game::RECLAIM_SECRET_LENGTH)
+            .collect()
+    }
+
+    /// Replaces the game with a snapshot. Clients that are still connected keep their players;
+    /// the other players get their entity back when they reconnect with their reclaim secret,
+    /// which works in a new process too.
+    pub fn restore(&mut self, snapshot: GameSnapshot) -> Result<(), SnapshotError> {
+        measure!("Restore");
+
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(snapshot.version));
+        }
+        let map_id = snapshot
+            .map_id
+            .clone()
+            .unwrap_or_else(|| snapshot.settings.map_id.clone());
+        for id in [&map_id, &snapshot.settings.map_id].iter() {
+            if !GAME_CONFIG.maps.contains_key(*id) {
+                return Err(SnapshotError::MissingMap((*id).clone()));
+            }
+        }
+
+        // Recreate the entities first so a bad snapshot leaves the game as it was
+        let entities = snapshot
+            .entities
+            .into_iter()
+            .map(Entity::from_snapshot)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // Replace the world
+        let old_ids = self
+            .world
+            .entities()
+            .iter()
+            .map(|entity| *entity.borrow().id())
+            .collect::<Vec<_>>();
+        for id in old_ids.iter() {
+            self.world.remove_entity_with_id(id).ok();
+        }
+        for entity in entities.into_iter() {
+            if let Err(err) = self.world.insert_entity(entity) {
+                println!("Failed to insert restored entity: {:?}", err);
+            }
+        }
+
+        // Remove the bots; the ones in the snapshot are recreated below
+        for (client_id, _) in self.bots.drain() {
+            self.clients.borrow_mut().remove(&client_id);
+        }
+
+        // Match the saved clients with the connected clients
+        self.reclaimable_players.clear();
+        let mut restored_clients = HashSet::new();
+        let mut restored_players = HashSet::new();
+        for saved in snapshot.clients.into_iter() {
+            if let Some(player_id) = saved.player_id {
+                restored_players.insert(player_id);
+            }
+
+            // Recreate the bots
+            if saved.is_bot {
+                let username = saved
+                    .username
+                    .clone()
+                    .unwrap_or_else(|| self.unused_bot_name());
+                let (mut client, tx_message) = ClientHandle::new_bot();
+                client.username = saved.username;
+                client.player_id = saved.player_id;
+                let client_id = self.client_id.tick();
+                self.clients.borrow_mut().insert(client_id, client);
+                self.bots.insert(client_id, Bot::new(tx_message, username));
+                restored_clients.insert(client_id);
+                continue;
+            }
+
+            // Clients that are still connected when restoring within the same process
+            let mut clients = self.clients.borrow_mut();
+            let connected = clients.get_mut(&saved.id).filter(|client| {
+                client.reclaim_secret.is_some() && client.reclaim_secret == saved.reclaim_secret
+            });
+            if let Some(client) = connected {
+                client.player_id = saved.player_id;
+                client.spectating_id = saved.spectating_id;
+                client.is_muted = saved.is_muted;
+                restored_clients.insert(saved.id);
+            } else if let (Some(secret), Some(player_id)) = (saved.reclaim_secret, saved.player_id)
+            {
+                self.reclaimable_players.insert(secret, player_id);
+            } else if let Some(player_id) = saved.player_id {
+                // There's no way to give this player back to their client
+                restored_players.remove(&player_id);
+            }
+        }
+
+        // The other clients' entity ids don't mean anything anymore
+        for (id, client) in self.clients.borrow_mut().iter_mut() {
+            if !restored_clients.contains(id) {
+                client.player_id = None;
+                client.spectating_id = None;
+            }
+        }
+
+        // Remove the players that no client can control
+        let orphans = self
+            .world
+            .entities()
+            .iter()
+            .filter_map(|entity| {
+                let entity = entity.borrow();
+                let is_orphan = match *entity.kind() {
+                    EntityKind::Player(_) => !restored_players.contains(entity.id()),
+                    EntityKind::Prop(_) => false,
+                };
+                if is_orphan {
+                    Some(*entity.id())
+                } else {
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+        for id in orphans.iter() {
+            self.world.remove_entity_with_id(id).ok();
+        }
+
+        // Restore the round
+        self.state = snapshot.state;
+        self.state_timer = snapshot.state_timer;
+        self.round_start_time = time_milliseconds().saturating_sub(snapshot.round_elapsed);
+        self.rounds_played = snapshot.rounds_played;
+        self.map_id = map_id;
+        self.settings = snapshot.settings;
+        self.map_seed = snapshot.map_seed;
+        self.next_map_seed = snapshot.next_map_seed;
+        self.generated_map = None;
+        self.decoys = snapshot.decoys;
+        self.host = None;
+        self.cached_minimap = self.generate_minimap();
+        self.minimap_changed = true;
+
+        // Let the connected clients know
+        for (_, client) in self.clients.borrow().iter() {
+            client.send_map_info(&self.map_config().name, self.map_seed);
+            if let Ok(entity) = self.player_entity(client) {
+                self.send_ability_cooldowns(client, &entity);
+            }
+        }
+        if self.lobby.custom {
+            self.update_host();
+            self.broadcast_lobby_settings();
+        }
+
+        Ok(())
+    }
+}
+
+/*** Supervision ***/
+impl Game {
+    /// Runs an update and recovers the game if it panics, so one bad client or entity doesn't
+    /// stop the game for everyone.
+    fn supervised_update(&mut self) {
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| self.update()));
+        match result {
+            Ok(result) => {
+                if let Err(err) = result {
+                    println!("Game update error: {:?}", err);
+                }
+
+                // Keep a snapshot to fall back on
+                if self.supervisor.update_succeeded() && self.world.is_consistent() {
+                    let snapshot = self.snapshot();
+                    self.supervisor.save(snapshot);
+                }
+            }
+            Err(payload) => self.recover(payload),
+        }
+    }
+
+    /// Removes whatever the update was working on when it panicked. If the world was left
+    /// broken or the game keeps panicking, goes back to the last good snapshot.
+    fn recover(&mut self, payload: Box<dyn Any + Send>) {
+        let phase = take_panic_phase().unwrap_or_else(|| "unknown phase".to_string());
+        let suspect = take_suspect();
+        println!(
+            "Lobby {} update {} panicked in {} ({:?}): {}",
+            self.lobby.id,
+            self.update_index.current(),
+            phase,
+            suspect,
+            panic_message(&*payload)
+        );
+
+        // Events from the update may refer to things that don't exist anymore
+        self.client_events.clear();
+
+        if let Some(suspect) = suspect {
+            self.quarantine(suspect);
+        }
+
+        // Fix the quad tree so entities can be removed when restoring
+        let world_consistent = self.world.is_consistent();
+        if !world_consistent {
+            self.world.rebuild_tree();
+        }
+
+        match self.supervisor.update_panicked(suspect, world_consistent) {
+            Recovery::Quarantine => {}
+            Recovery::Restore => {
+                let snapshot = self
+                    .supervisor
+                    .last_good()
+                    .cloned()
+                    .expect("snapshot to restore");
+                let taken_at = snapshot.taken_at;
+                match self.restore(snapshot) {
+                    Ok(_) => {
+                        println!(
+                            "Restored lobby {} to the snapshot from {}",
+                            self.lobby.id, taken_at
+                        );
+
+                        // The snapshot may have the entity that panicked
+                        if let Some(Suspect::Entity(id)) = suspect {
+                            self.world.remove_entity_with_id(&id).ok();
+                        }
+                    }
+                    Err(err) => {
+                        println!("Failed to restore snapshot: {}", err);
+                        self.reset_round();
+                    }
+                }
+            }
+            Recovery::Reset => self.reset_round(),
+        }
+    }
+
+    /// Removes a client or entity that made the update panic.
+    fn quarantine(&mut self, suspect: Suspect) {
+        println!("Quarantining {:?}", suspect);
+
+        match suspect {
+            Suspect::Client(client_id) => {
+                let client = match self.clients.borrow_mut().remove(&client_id) {
+                    Some(client) => client,
+                    None => return,
+                };
+                self.bots.remove(&client_id);
+
+                // Remove the player
+                if let Some(player_id) = client.player_id {
+                    self.world.remove_entity_with_id(&player_id).ok();
+                }
+
+                // Let Rivet know the player left
+                if let Some(ref rivet_player_token) = client.rivet_player_token {
+                    if let Err(err) = crate::rivet::player_disconnected(
+                        &self.lobby.token_var,
+                        rivet_player_token.clone(),
+                    ) {
+                        println!("Failed to disconnect quarantined player: {:?}", err);
+                    }
+                }
+
+                // Kick the client
+                if let Some(out) = client.socket_out() {
+                    out.close(CloseCode::Error).ok();
+                }
+
+                if self.lobby.custom {
+                    self.update_host();
+                }
+            }
+            Suspect::Entity(entity_id) => {
+                if let Err(err) = self.world.remove_entity_with_id(&entity_id) {
+                    println!("Failed to quarantine entity {}: {:?}", entity_id, err);
+                }
+                self.decoys.retain(|decoy| decoy.entity_id != entity_id);
+            }
+        }
+    }
+
+    /// Starts over with a new round when the game can't be restored. Players keep their
+    /// entities.
+    fn reset_round(&mut self) {
+        println!("Resetting lobby {}", self.lobby.id);
+
+        self.decoys.clear();
+        if let Err(err) = self.init_map() {
+            println!("Failed to rebuild map: {:?}", err);
+        }
+        self.set_pre_game_state();
+    }
+}
+
+/*** Custom Lobbies ***/
+impl Game {
+    /// Makes the player that has been in the game the longest the host if the lobby doesn't
+    /// have one. Bots can't be the host.
+    fn update_host(&mut self) {
+        let new_host = {
+            let clients = self.clients.borrow();
+            if let Some(ref host) = self.host {
+                if clients.contains_key(host) {
+                    return;
+                }
+            }
+            clients
+                .iter()
+                .filter(|(_, c)| !c.is_bot() && c.player_id.is_some())
+                .map(|(id, _)| *id)
+                .min()
+        };
+        if new_host != self.host {
+            self.host = new_host;
+            self.broadcast_lobby_settings();
+        }
+    }
+
+    /// Sends the settings to everyone in the lobby.
+    fn broadcast_lobby_settings(&self) {
+        let settings = self.settings.serialize();
+        for (id, client) in self.clients.borrow().iter() {
+            client.send_lobby_settings(self.host == Some(*id), settings.clone());
+        }
+    }
+}
+
+/*** Bots ***/
+impl Game {
+    /// Adds or removes bots to fill the game and lets the bots make their moves.
+    fn update_bots(&mut self) {
+        measure!("Update bots");
+
+        // Players can only join before the round starts
+        if let GameState::PreGame = self.state {
+            self.fill_with_bots();
+        }
+
+        // Find the pings the bots can hear
+        let pings = self
+            .client_events
+            .iter()
+            .filter_map(|e| e.ping_point().cloned())
+            .collect::<Vec<_>>();
+
+        // Update the bots
+        let clients = self.clients.borrow();
+        let world = &self.world;
+        for (client_id, bot) in self.bots.iter_mut() {
+            let _suspect = Suspect::Client(*client_id).watch();
+            let player = clients
+                .get(client_id)
+                .and_then(|c| c.player_id)
+                .and_then(|id| world.entity_with_id(&id));
+            if let Some(player) = player {
+                bot.update(world, player, &self.state, &pings);
+            }
+        }
+    }
+
+    /// Adds bots until there are enough players for a round or removes bots when players join.
+    /// There are no bots when there are no players.
+    fn fill_with_bots(&mut self) {
+        let players = self
+            .clients
+            .borrow()
+            .values()
+            .filter(|c| !c.is_bot() && c.player_id.is_some())
+            .count();
+        let bot_count = if players > 0 {
+            self.settings.min_players.saturating_sub(players)
+        } else {
+            0
+        };
+
+        // Remove extra bots
+        while self.bots.len() > bot_count {
+            let client_id = *self.bots.keys().next().unwrap();
+            if let Some(bot) = self.bots.remove(&client_id) {
+                bot.leave();
+            }
+        }
+
+        // Add missing bots
+        while self.bots.len() < bot_count {
+            let username = self.unused_bot_name();
+            let (client, tx_message) = ClientHandle::new_bot();
+            let client_id = self.client_id.tick();
+            self.clients.borrow_mut().insert(client_id, client);
+
+            let bot = Bot::new(tx_message, username);
+            bot.join(&self.settings.characters());
+            self.bots.insert(client_id, bot);
+        }
+    }
+
+    /// Picks a name from the config that no other bot is using.
+    fn unused_bot_name(&self) -> String {
+        let names = &GAME_CONFIG.bots.names;
+        let unused = names
+            .iter()
+            .filter(|name| !self.bots.values().any(|b| b.username() == *name))
+            .collect::<Vec<_>>();
+        match rand::thread_rng().choose(&unused) {
+            Some(name) => (*name).clone(),
+            None => format!("Bot {}", self.bots.len() + 1),
+        }
+    }
+}
+
 /*** State Management ***/
 impl Game {
     fn state_timer_finished(&mut self) {
@@ -1193,6 +2267,11 @@ impl Game {
 
         // We use the old map so players can go around and do whatever
 
+        // Players that didn't reconnect after the game was restored have left
+        for (_, player_id) in self.reclaimable_players.drain() {
+            self.world.remove_entity_with_id(&player_id).ok();
+        }
+
         // Update the state of the clients
         for (_, client) in self.clients.borrow_mut().iter_mut() {
             // Change player to hunters
@@ -1208,7 +2287,7 @@ impl Game {
         }
 
         // Update the state
-        self.state_timer = 10.;
+        self.state_timer = self.settings.pre_game_duration;
         self.state = GameState::PreGame;
         self.broadcast_new_state();
     }
@@ -1217,14 +2296,12 @@ impl Game {
         //        println!("Switching to hiding state.");
 
         // Store the players in the game
-        let mut players_in_game = self
+        let players_in_game = self
             .clients
             .borrow()
             .iter()
             .filter_map(|(c_id, c)| c.player_id.map(|p_id| (c_id.clone(), p_id.clone())))
             .collect::<Vec<_>>();
-        let mut rng = rand::thread_rng();
-        rng.shuffle(&mut players_in_game);
 
         // Check if there's enough players
         let player_count = players_in_game.len();
@@ -1233,14 +2310,40 @@ impl Game {
             return;
         }
 
+        // Wait for the host if all the rounds were played
+        if let Some(rounds) = self.settings.rounds {
+            if self.rounds_played >= rounds {
+                self.state_timer = self.settings.pre_game_duration;
+                return;
+            }
+        }
+        self.rounds_played += 1;
+
         // Rebuild the map; this will no destroy the players
         self.init_map();
 
+        // Pick the hunters
+        let candidates = players_in_game
+            .iter()
+            .filter_map(|&(client_id, player_id)| {
+                let entity = self.world.entity_with_id(&player_id)?.borrow();
+                let kind = entity.kind();
+                match *kind {
+                    EntityKind::Player(ref player) => Some(TeamCandidate {
+                        client_id,
+                        history: player.team_history().clone(),
+                        party: player.party().clone(),
+                    }),
+                    EntityKind::Prop(_) => None,
+                }
+            })
+            .collect::<Vec<_>>();
+        let hunters = team_assigner(&GAME_CONFIG.teams)
+            .assign_hunters(&candidates, self.settings.hunter_count(player_count));
+
         // Assign the player states
-        for (i, &(client_id, player_id)) in players_in_game.iter().enumerate() {
-            // Decide if hunter; they're a hunter if it's one of the first two players or in the
-            // bottom 20%
-            let is_hunter = i < 2 || i <= (player_count as f64 * 0.2) as usize;
+        for &(client_id, _) in players_in_game.iter() {
+            let is_hunter = hunters.contains(&client_id);
 
             // Update the state
             let state = if is_hunter {
@@ -1250,11 +2353,15 @@ impl Game {
             };
             self.set_player_state(self.clients.borrow().get(&client_id).unwrap(), state);
 
-            // Reset the health
+            // Reset the health and score
             if let Ok(ref mut player) =
                 self.player_entity_mut(self.clients.borrow().get(&client_id).unwrap())
             {
                 player.set_health(1.);
+                if let EntityKind::Player(ref mut player) = *player.kind_mut() {
+                    player.reset_score_counter();
+                    player.team_history_mut().assigned(is_hunter);
+                }
             }
         }
 
@@ -1262,7 +2369,8 @@ impl Game {
         self.move_players_to_spawn();
 
         // Update the state
-        self.state_timer = 15.;
+        self.round_start_time = self.update_time;
+        self.state_timer = self.settings.hiding_duration;
         self.state = GameState::Hiding;
         self.broadcast_new_state();
     }
@@ -1271,7 +2379,7 @@ impl Game {
         //        println!("Switching to hunting state.");
 
         // Update the state
-        self.state_timer = 5. * 60.;
+        self.state_timer = self.settings.hunting_duration;
         self.state = GameState::Hunting;
         self.broadcast_new_state();
     }
@@ -1279,9 +2387,25 @@ impl Game {
     fn finish_game(&mut self, props_win: bool) {
         //        println!("Finishing game.");
 
+        // Reward the props that made it to the end
+        if props_win {
+            for (_, client) in self.clients.borrow().iter() {
+                if let Ok(entity) = self.player_entity_mut(client) {
+                    if let EntityKind::Player(ref mut player) = *entity.kind_mut() {
+                        if let PlayerState::Prop(_) = *player.state() {
+                            player
+                                .score_counter_mut()
+                                .event(ScoreCounter::SURVIVED_HUNT);
+                        }
+                    }
+                }
+            }
+        }
+
         // Send the score breakdowns to the clients
         let scoreboard = self.calculate_scoreboard(Some(props_win));
         let scoreboard_value = self.serialize_scoreboard(scoreboard.clone());
+        let mut player_records = Vec::new();
         for (_, client) in self.clients.borrow().iter() {
             if let Some(player_id) = client.player_id {
                 // Find a score that matches the client's player id
@@ -1298,9 +2422,25 @@ impl Game {
                                 PlayerState::Prop(_) => true,
                                 PlayerState::Hunter(_) => false,
                             };
-                            player
-                                .score_counter()
-                                .score_breakdown(Some(i as u64), is_prop == props_win)
+                            let score_counter = player.score_counter();
+
+                            // Save the player's results for the match record
+                            player_records.push(PlayerMatchRecord {
+                                identity_id: client.rivet_identity_id.clone(),
+                                username: player.username().clone(),
+                                character_id: player.character_id().clone(),
+                                is_prop,
+                                placement: i as u64,
+                                score: score.score,
+                                stats: score_counter.stats(),
+                                score_breakdown: score_counter
+                                    .score_breakdown_items(Some(i as u64), is_prop == props_win)
+                                    .into_iter()
+                                    .flatten()
+                                    .collect(),
+                            });
+
+                            score_counter.score_breakdown(Some(i as u64), is_prop == props_win)
                         } else {
                             println!("Missing player for score breakdown.");
                             break;
@@ -1310,20 +2450,39 @@ impl Game {
                         break;
                     };
 
+                    // Remember the score for picking the teams
+                    if let Ok(ref entity) = self.player_entity(client) {
+                        if let EntityKind::Player(ref mut player) = *entity.kind_mut() {
+                            player.team_history_mut().record_score(score.score);
+                        }
+                    }
+
                     // Send the results
                     client.send_game_results(props_win, scoreboard_value.clone(), breakdown);
                 }
             }
         }
 
+        // Save the match
+        player_records.sort_by_key(|p| p.placement);
+        let record = MatchRecord {
+            map: self.map_config().name.clone(),
+            map_seed: self.map_seed,
+            started_at: self.round_start_time,
+            duration: self.update_time.saturating_sub(self.round_start_time) as f64 / 1000.,
+            props_win,
+            players: player_records,
+        };
+        if let Err(err) = self.stats_store.record_match(&record) {
+            println!("Failed to save match record: {}", err);
+        }
+
         // Update the state
         self.set_pre_game_state();
     }
 
-    /// Places all the players on a square around the center of the map.
+    /// Places all the players in their team's spawn zones.
     fn move_players_to_spawn(&mut self) {
-        let mut rng = rand::thread_rng();
-
         // Position the players on the spawn
         for (_, client) in self
             .clients
@@ -1331,41 +2490,103 @@ impl Game {
             .iter()
             .filter(|&(_, c)| c.player_id.is_some())
         {
-            if let Ok(player) = self.player_entity_mut(client) {
-                self.move_player_to_spawn(player);
+            let player_handle = client
+                .player_id
+                .and_then(|id| self.world.entity_with_id(&id))
+                .cloned();
+            if let Some(player_handle) = player_handle {
+                self.move_player_to_spawn(&player_handle);
             } else {
                 println!("Failed to find player to move to spawn.");
             }
         }
     }
 
-    /// Places the player on a square around the ceneter
-    fn move_player_to_spawn(&self, mut player: RefMut<Entity>) {
-        let mut rng = rand::thread_rng();
-        let square_size = 75.;
-
-        // Determine random x and y on the edge of a square
-        let mut x = rng.gen_range(-square_size, square_size);
-        let mut y = if rng.gen_weighted_bool(2) {
-            square_size
-        } else {
-            -square_size
+    /// Places the player in one of their team's spawn zones.
+    fn move_player_to_spawn(&self, player_handle: &EntityHandle) {
+        // Find the player's team and size
+        let (is_hunter, rect) = {
+            let player = player_handle.borrow();
+            let is_hunter = match *player.kind() {
+                EntityKind::Player(ref player) => match *player.state() {
+                    PlayerState::Hunter(_) => true,
+                    PlayerState::Prop(_) => false,
+                },
+                _ => false,
+            };
+            let rect = player.body().bounding_rect().clone();
+            (is_hunter, rect)
         };
-        if rng.gen_weighted_bool(2) {
-            mem::swap(&mut x, &mut y);
-        }
+
+        // Find the position
+        let position = self.find_spawn_position(is_hunter, rect.as_ref());
 
         // Position the player there
-        let mut player_body = player.body_mut();
-        let player_pos = player_body.get_pos_mut();
-        player_pos.set(x, y, 1.);
+        let entity = player_handle.borrow();
+        entity.body_mut().set_pos(position.clone());
 
         // Save the position
-        if let EntityKind::Player(ref mut player) = *player.kind_mut() {
-            player.set_spawn_pos(player_pos);
+        let mut kind = entity.kind_mut();
+        if let EntityKind::Player(ref mut player) = *kind {
+            player.set_spawn_pos(&position);
         }
     }
 
+    /// Picks a random position in the team's spawn zones that isn't inside of a fixture.
+    fn find_spawn_position(&self, is_hunter: bool, rect: Option<&Rect>) -> Vector {
+        let mut rng = rand::thread_rng();
+
+        let zones = self.map_config().spawn_zones_for_team(is_hunter);
+        let total_weight = zones.iter().map(|z| z.weight).sum::<FloatType>();
+        if zones.is_empty() || total_weight <= 0. {
+            println!("No spawn zones for the map");
+            return Vector::new(0., 0., 1.);
+        }
+
+        let mut position = Vector::new(0., 0., 1.);
+        for _ in 0..Game::MAX_SPAWN_ATTEMPTS {
+            // Pick a zone based on the weights
+            let mut pick = rng.gen_range(0., total_weight);
+            let zone = zones
+                .iter()
+                .find(|z| {
+                    pick -= z.weight;
+                    pick < 0.
+                })
+                .unwrap_or(&zones[zones.len() - 1]);
+
+            // Pick a position at the bottom of the zone
+            let area = &zone.area;
+            position = Vector::new(
+                area.center.x + rng.gen_range(-0.5, 0.5) * area.size.x,
+                area.center.y + rng.gen_range(-0.5, 0.5) * area.size.y,
+                area.z_lower_extent(0.),
+            );
+
+            // Make sure the player doesn't spawn inside of a fixture
+            let is_blocked = rect.is_some_and(|rect| {
+                self.world
+                    .query_rect(rect, &position, false, false, |e| {
+                        matches!(*e.kind(), EntityKind::Prop(ref prop)
+                            if matches!(prop.prefab.kind, PrefabKind::Fixture))
+                    })
+                    .is_some()
+            });
+            if !is_blocked {
+                return position;
+            }
+        }
+
+        println!("Could not find a free spawn position");
+        position
+    }
+
+    /// If props are still protected at the start of the hunt.
+    fn in_hunting_grace_period(&self) -> bool {
+        let elapsed = self.settings.hunting_duration - self.state_timer;
+        elapsed < self.map_config().spawn_protection.hunting_grace_period
+    }
+
     fn broadcast_new_state(&mut self) {
         // Don't do anything, since the state will be sent with each update now
 
@@ -1405,12 +2626,39 @@ impl Game {
             self.world.remove_entity_with_id(&id);
         }
 
+        // Switch to the map the host picked
+        self.map_id = self.settings.map_id.clone();
+
         // Add entities from config
-        let map = GAME_CONFIG
-            .maps
-            .get("map-a")
-            .expect("Could not get map config");
-        self.spawn_object_collection(&map.objects, Vector::zero());
+        let map = self.map_config();
+        if let Some(ref generator) = map.generator {
+            // Pick the seed; a seed set by an admin overrides the configured seed
+            let seed = self
+                .next_map_seed
+                .take()
+                .or(generator.seed)
+                .unwrap_or_else(|| rng.gen());
+            println!("Generating map {} with seed {}", map.name, seed);
+
+            // Generate and spawn the map, keeping the spawns clear
+            let mut map_generator = MapGenerator::new(generator, seed);
+            for zone in map.spawn_zones.iter() {
+                map_generator.reserve_area(zone.area.clone());
+            }
+            let generated_map = map_generator.generate(map);
+            self.spawn_object_collection(&generated_map.objects, Vector::zero());
+            self.map_seed = Some(seed);
+            self.generated_map = Some(generated_map);
+        } else {
+            self.spawn_object_collection(&map.objects, Vector::zero());
+            self.map_seed = None;
+            self.generated_map = None;
+        }
+
+        // Tell the clients about the map
+        for (_, client) in self.clients.borrow().iter() {
+            client.send_map_info(&map.name, self.map_seed);
+        }
 
         // Generate the minimap
         self.cached_minimap = self.generate_minimap();
@@ -1419,6 +2667,63 @@ impl Game {
         Ok(())
     }
 
+    /// The config for the map being played.
+    fn map_config(&self) -> &'static MapConfig {
+        GAME_CONFIG
+            .maps
+            .get(&self.map_id)
+            .expect("Could not get map config")
+    }
+
+    /// Resolves a file named in an admin command to a path in `adminFileDir`, or the default name
+    /// if it's empty. Only bare file names are accepted, so admins can't read or write anywhere
+    /// else on the server.
+    fn admin_file_path(file_name: &str, default: &str) -> Result<String, String> {
+        let file_name = if file_name.is_empty() {
+            default
+        } else {
+            file_name
+        };
+        if file_name == "." || file_name.contains("..") || file_name.contains(&['/', '\\'][..]) {
+            return Err(format!("invalid file name {:?}", file_name));
+        }
+
+        let dir = &GAME_CONFIG.admin_file_dir;
+        std::fs::create_dir_all(dir).map_err(|err| format!("can't create {}: {}", dir, err))?;
+        Ok(format!("{}/{}", dir, file_name))
+    }
+
+    /// Writes the generated map to a YAML file so it can be added to the game config.
+    fn save_generated_map(&self, file_name: &str) {
+        measure!("Save map");
+
+        let (map, seed) = match (&self.generated_map, self.map_seed) {
+            (Some(map), Some(seed)) => (map, seed),
+            _ => {
+                println!("No generated map to save");
+                return;
+            }
+        };
+
+        // Default the file name to the seed
+        let path = match Game::admin_file_path(file_name, &format!("map-{}.yaml", seed)) {
+            Ok(path) => path,
+            Err(err) => {
+                println!("Failed to save map: {}", err);
+                return;
+            }
+        };
+
+        // Write the map
+        match serde_yaml::to_string(map) {
+            Ok(yaml) => match std::fs::write(&path, yaml) {
+                Ok(_) => println!("Saved map with seed {} to {}", seed, path),
+                Err(err) => println!("Failed to write map to {}: {:?}", path, err),
+            },
+            Err(err) => println!("Failed to serialize map: {:?}", err),
+        }
+    }
+
     /// Initializes a collection of objects
     fn spawn_object_collection(&mut self, objects: &Vec<MapObjectKind>, offset: Vector) {
         measure!("Spawn collection");
@@ -1426,7 +2731,7 @@ impl Game {
         for object in objects.iter() {
             match *object {
                 MapObjectKind::Object(ref object) => {
-                    self.spawn_object(object, &offset, 0, true);
+                    self.spawn_object(object, &offset, 0., true);
                 }
                 MapObjectKind::Group(ref group) => {
                     let mut collection_offset = offset.clone();
@@ -1436,24 +2741,28 @@ impl Game {
                 MapObjectKind::Building(ref building) => {
                     self.generate_building(building);
                 }
+                MapObjectKind::Tiled(ref tiled) => {
+                    self.spawn_object_collection(&tiled.objects, offset.clone());
+                }
             }
         }
     }
 
+    /// Spawns an object and returns the id of its entity, if it spawned.
     fn spawn_object(
         &mut self,
         object: &ObjectConfigHandle,
         offset: &Vector,
-        offset_rotation: u8,
+        offset_rotation: FloatType,
         use_spawn_chance: bool,
-    ) {
+    ) -> Option<EntityId> {
         measure!("Spawn object");
 
         // Determine spawn chance
         let mut rng = rand::thread_rng();
         if use_spawn_chance {
             if rng.gen_range(0., 1.) > object.spawn_chance {
-                return;
+                return None;
             }
         }
 
@@ -1470,14 +2779,16 @@ impl Game {
         let entity = Entity::new(prop);
 
         // Offset the entity
-        let new_rot = (entity.body().get_rotation() + offset_rotation) % 4;
+        let new_rot = entity.body().get_rotation() + offset_rotation;
         entity.body_mut().get_pos_mut().add(offset, 1.);
         entity.body_mut().set_rotation(new_rot);
 
         // Add it to the world
+        let entity_id = *entity.id();
         self.world
             .insert_entity(entity)
             .expect("Failed to insert object into world");
+        Some(entity_id)
     }
 
     fn generate_building(&mut self, building: &BuildingConfig) {
@@ -1544,7 +2855,7 @@ impl Game {
                 }
 
                 // Spawn the object
-                self.spawn_object(object, &object_offset, rotation, false);
+                self.spawn_object(object, &object_offset, quarter_turns(rotation), false);
             }
         }
     }
@@ -1613,7 +2924,7 @@ impl Game {
                     for rect in prop.prefab.minimap_rects.iter() {
                         // Rotate the rect so it appears properly on the map
                         let mut rect = rect.clone();
-                        rect.rotate(body.get_rotation());
+                        rect.rotate(*body.get_rotation());
 
                         // Serialize the rect
                         prop_rects.push(rect.serialize_with_offset(entity.body().get_pos()));
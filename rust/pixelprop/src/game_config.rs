@@ -1,8 +1,9 @@
-use crate::utils::{FloatType, Rect, Vector};
+use crate::utils::{normalize_angle, quarter_turns, FloatType, Rect, Vector, QUARTER_TURN};
 use serde::de::{Deserializer, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, Serializer};
 use serde::{Deserialize, Serialize};
 use serde_yaml;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
@@ -19,7 +20,8 @@ lazy_static::lazy_static! {
 //        println!("Config file:\n{}", contents);
 
         // Parse the config
-        let config = serde_yaml::from_str::<GameConfig>(&contents).unwrap();
+        let config = GameConfig::parse(&contents)
+            .unwrap_or_else(|err| panic!("Invalid game config: {}", err));
 //        println!("Config data: {:?}", config);
 
         config
@@ -38,11 +40,42 @@ pub struct GameConfig {
     pub store: Arc<StoreConfig>,
     pub player: PlayerConfig,
     pub scoring: ScoringConfig,
+    #[serde(default)]
+    pub abilities: Vec<AbilityConfig>,
+    pub bots: BotConfig,
+    pub chat: ChatConfig,
+    pub teams: TeamConfig,
+    #[serde(default = "default_stats_store")]
+    pub stats_store: StatsStoreConfig, // Where to save match history and lifetime stats
+    #[serde(default = "default_admin_file_dir")]
+    pub admin_file_dir: String, // Where admin commands save and load maps and snapshots
+    #[serde(default = "default_lobbies")]
+    pub lobbies: Vec<LobbyConfig>, // Games hosted by this server, each with their own world
+    pub custom_lobbies: CustomLobbyConfig,
     pub prefabs: Vec<PrefabConfigHandle>,
+
+    #[serde(default = "default_map_id")]
+    pub map_id: String, // The map from `maps` to play on
     pub maps: HashMap<String, MapConfig>,
 }
 
 impl GameConfig {
+    /// Parses the config, then imports the Tiled maps and checks the generators. The imports run
+    /// after parsing so their errors aren't hidden by the untagged map objects.
+    pub fn parse(yaml: &str) -> Result<GameConfig, String> {
+        let mut config = serde_yaml::from_str::<GameConfig>(yaml).map_err(|err| err.to_string())?;
+        for (map_id, map) in config.maps.iter_mut() {
+            import_tiled_maps(&mut map.objects)
+                .map_err(|err| format!("invalid map {}: {}", map_id, err))?;
+            if let Some(ref generator) = map.generator {
+                generator
+                    .check()
+                    .map_err(|err| format!("invalid generator for map {}: {}", map_id, err))?;
+            }
+        }
+        Ok(config)
+    }
+
     pub fn prefab_with_id(&self, id: &String) -> Option<&PrefabConfigHandle> {
         // Find and return the prefab
         for prefab in self.prefabs.iter() {
@@ -92,18 +125,161 @@ pub struct PlayerConfig {
 #[serde(rename_all = "camelCase")]
 pub struct ScoringConfig {
     pub winning_team: u64,
-    pub first: u64,
-    pub second: u64,
-    pub third: u64,
+    pub placements: Vec<PlacementTierConfig>, // Bonuses for the top players, best first
+    pub rules: Vec<Vec<ScoreRuleConfig>>, // Each list of rules is a section of the score breakdown
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PlacementTierConfig {
+    pub label: String,
+    pub points: u64,
+    #[serde(default = "default_places")]
+    pub places: u64, // Number of places that get this tier
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScoreRuleConfig {
+    pub event: String, // The score event the rule counts
+    pub label: String, // Shown in the score breakdown
+    #[serde(default = "default_score_display")]
+    pub display: ScoreDisplay,
+
+    #[serde(default)]
+    pub per_event: u64, // Points each time the event happens
+    #[serde(default)]
+    pub per_unit: f64, // Points for each unit of the event's amount
+    #[serde(default)]
+    pub per_second: f64, // Points for each second the event lasts
+    #[serde(default)]
+    pub cap: Option<u64>, // Most points the rule can give
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ScoreDisplay {
+    None,    // Only show the points
+    Count,   // Number of times the event happened
+    Amount,  // Total amount of the event
+    Percent, // Amount as a percent
+    Seconds, // Time the event lasted
+}
+
+/* Abilities */
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AbilityConfig {
+    pub id: String,
+    pub team: AbilityTeam,           // The team that can use the ability
+    pub cooldown: FloatType,         // Seconds between uses
+    pub charges: Option<u64>,        // Uses per round; unlimited if not set
+    pub score_event: Option<String>, // Score event given to the player on each use
+    pub effect: AbilityEffect,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum AbilityTeam {
+    Hunter,
+    Prop,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "kind")]
+pub enum AbilityEffect {
+    Scan { radius: FloatType }, // Pings every prop and decoy around the hunter
+    Decoy { lifetime: FloatType }, // Spawns a copy of the prop that pings with it for `lifetime` seconds
+    Ping,                          // Pings the prop right away
+}
+
+/* Teams */
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TeamConfig {
+    pub strategy: TeamStrategy,
+    pub skill_history: usize, // Number of rounds used to rate a player's skill
+    pub balance_slack: usize, // How many players past the ones due to hunt can be picked to balance skill
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum TeamStrategy {
+    Random,   // Hunters are picked at random
+    Rotation, // Players who haven't hunted in the longest go first
+    Balanced, // Rotation, but picks hunters with an average skill
+}
+
+/* Bots */
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BotConfig {
+    pub min_players: usize, // Bots fill the game up to this many players; 0 disables bots
+    pub names: Vec<String>,
+    pub think_delay: FloatType,   // Seconds between each bot decision
+    pub cover_range: FloatType,   // How far prop bots look for a prop to hide as
+    pub patrol_range: FloatType,  // How far hunter bots walk before picking a new spot
+    pub ping_range: FloatType,    // How far away a ping can be for hunter bots to chase it
+    pub suspect_range: FloatType, // How far from a ping hunter bots look for props to shoot
+    pub shoot_range: FloatType,
+    pub max_shots_per_suspect: u64, // Shots to fire at a prop before giving up on it
+}
+
+/* Chat */
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatConfig {
+    pub max_length: usize,           // Longer messages are cut off
+    pub rate_limit: usize,           // Messages a player can send in each window
+    pub rate_window: FloatType,      // Seconds
+    pub filtered_words: Vec<String>, // Whole words that are replaced with asterisks
+}
+
+/* Lobbies */
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LobbyConfig {
+    pub id: String, // Sent by the client in the auth message to pick the lobby
+    #[serde(default = "default_token_var")]
+    pub token_var: String, // Environment variable holding the matchmaker lobby token
+    #[serde(default = "default_bool_false")]
+    pub custom: bool, // The first player to join can change the settings before each round
+    #[serde(default)]
+    pub overrides: LobbyOverrides,
+    #[serde(default)]
+    pub snapshot: Option<String>, // Snapshot file to restore when the server starts
+}
 
-    pub killed_player: u64,
-    pub hit_shot: u64,
-    pub percent_hit_scale: u64,
+/// Settings that replace the values in the rest of the config for a single lobby.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct LobbyOverrides {
+    pub map_id: Option<String>,
+    pub pre_game_duration: Option<FloatType>, // Seconds
+    pub hiding_duration: Option<FloatType>,   // Seconds
+    pub hunting_duration: Option<FloatType>,  // Seconds
+    pub min_players: Option<usize>,           // Replaces `bots.minPlayers`
+}
 
-    pub prop_health: u64,
-    pub ping: u64,
-    pub ping_per_unit: u64,
-    pub sleeping_per_second: f64,
+/// Limits for the settings the host of a custom lobby can pick.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CustomLobbyConfig {
+    pub pre_game_duration: (FloatType, FloatType), // Seconds; min and max
+    pub hiding_duration: (FloatType, FloatType),   // Seconds; min and max
+    pub hunting_duration: (FloatType, FloatType),  // Seconds; min and max
+    pub max_hunters: usize,
+    pub max_rounds: u64,
+}
+
+/* Stats */
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+#[serde(tag = "kind")]
+pub enum StatsStoreConfig {
+    None,
+    Sqlite { path: String },
 }
 
 /* Prefab */
@@ -126,7 +302,13 @@ pub struct PrefabConfig {
     pub kind: PrefabKind,
 
     #[serde(default = "default_health")]
-    pub health: f64,
+    pub health: f64, // Number of shots to destroy it; use `.inf` to make a prop indestructible
+
+    #[serde(default = "default_bool_false")]
+    pub destructible: bool, // If a fixture can be damaged; props can always be damaged
+
+    #[serde(default = "default_debris")]
+    pub debris: Vec<ObjectConfigHandle>, // Objects spawned when destroyed; positioned relative to the entity
 
     #[serde(default = "default_asset")]
     pub asset: Option<String>,
@@ -144,27 +326,109 @@ impl PrefabConfig {
     pub fn asset(&self) -> String {
         self.asset.clone().unwrap_or_else(|| self.id.clone())
     }
+
+    /// The health of the prefab if it can be damaged by shots.
+    pub fn damageable_health(&self) -> Option<f64> {
+        let is_damageable = match self.kind {
+            PrefabKind::Prop => true,
+            PrefabKind::Fixture => self.destructible,
+        };
+        if is_damageable && self.health.is_finite() {
+            Some(self.health)
+        } else {
+            None
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct MapConfig {
     pub name: String,
-    pub objects: Vec<MapObjectKind>,
+    pub objects: Vec<MapObjectKind>, // If there's a generator, these are always spawned along with the generated objects
+
+    #[serde(default = "default_spawn_zones")]
+    pub spawn_zones: Vec<SpawnZoneConfig>,
+
+    #[serde(default = "default_spawn_protection")]
+    pub spawn_protection: SpawnProtectionConfig,
+
+    #[serde(default = "default_generator", skip_serializing)]
+    pub generator: Option<Arc<GeneratorConfig>>, // Generated maps are saved without their generator
 }
 
-#[derive(Deserialize, Debug)]
+impl MapConfig {
+    /// Spawn zones that a team can spawn in; falls back to every zone if the team has none.
+    pub fn spawn_zones_for_team(&self, is_hunter: bool) -> Vec<&SpawnZoneConfig> {
+        let zones = self
+            .spawn_zones
+            .iter()
+            .filter(|z| z.team.allows(is_hunter))
+            .collect::<Vec<_>>();
+        if zones.is_empty() {
+            self.spawn_zones.iter().collect()
+        } else {
+            zones
+        }
+    }
+}
+
+/* Spawning */
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SpawnZoneConfig {
+    pub name: String,
+
+    #[serde(default = "default_spawn_team")]
+    pub team: SpawnTeam,
+
+    pub area: Rect, // Players spawn at the bottom of the area
+
+    #[serde(default = "default_spawn_weight")]
+    pub weight: FloatType, // Relative chance of picking this zone
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum SpawnTeam {
+    Hunters,
+    Props,
+    Any,
+}
+
+impl SpawnTeam {
+    pub fn allows(&self, is_hunter: bool) -> bool {
+        match *self {
+            SpawnTeam::Hunters => is_hunter,
+            SpawnTeam::Props => !is_hunter,
+            SpawnTeam::Any => true,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SpawnProtectionConfig {
+    #[serde(default = "default_bool_true")]
+    pub pin_hunters: bool, // Hold hunters at their spawn while the props hide
+
+    #[serde(default = "default_grace_period")]
+    pub hunting_grace_period: FloatType, // Seconds after the hunt starts that props can't be damaged
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 #[serde(untagged)]
 pub enum MapObjectKind {
     Object(ObjectConfigHandle),
     Group(Arc<GroupConfig>),
     Building(Arc<BuildingConfig>),
+    Tiled(Arc<TiledConfig>),
 }
 
 pub type ObjectConfigHandle = Arc<ObjectConfig>;
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct ObjectConfig {
     pub prefab_id: String,
@@ -173,13 +437,30 @@ pub struct ObjectConfig {
     pub position: Vector,
 
     #[serde(default = "default_rot")]
-    pub rotation: u8,
+    pub rotation: u8, // Quarter turns
+
+    #[serde(default = "default_yaw")]
+    pub yaw: FloatType, // Degrees; added on top of `rotation`
 
     #[serde(default = "default_spawn_chance")]
     pub spawn_chance: f64,
 }
 
-#[derive(Deserialize, Debug)]
+impl ObjectConfig {
+    /// The full rotation of the object in radians.
+    pub fn angle(&self) -> FloatType {
+        quarter_turns(self.rotation) + self.yaw.to_radians()
+    }
+
+    /// Splits an angle in radians into the quarter turns and yaw used by the config.
+    pub fn rotation_from_angle(angle: FloatType) -> (u8, FloatType) {
+        let turns = (normalize_angle(angle) / QUARTER_TURN).floor();
+        let yaw = (normalize_angle(angle) - turns * QUARTER_TURN).to_degrees();
+        ((turns as u8) % 4, yaw)
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct GroupConfig {
     pub group_name: String,
@@ -187,7 +468,7 @@ pub struct GroupConfig {
     pub objects: Vec<MapObjectKind>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct BuildingConfig {
     /*
@@ -212,10 +493,166 @@ pub struct BuildingConfig {
     pub room_size: FloatType,
     #[serde(default = "default_bool_false")]
     pub centered: bool,
-    pub lookup_table: HashMap<String, ObjectConfigHandle>, // Lookup for the map grid
+    pub lookup_table: BTreeMap<String, ObjectConfigHandle>, // Lookup for the map grid; sorted so saved maps are stable
     pub map: String,
 }
 
+/* Tiled maps */
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TiledConfig {
+    pub tiled_map: String, // Path to a Tiled JSON map (`.tmj`), relative to the working directory
+    #[serde(default = "default_pos")]
+    pub offset: Vector,
+
+    #[serde(skip)]
+    pub objects: Vec<MapObjectKind>, // Objects imported from the Tiled map when the config is loaded
+}
+
+impl TiledConfig {
+    /// Reads the objects from the Tiled map.
+    pub fn import(&mut self) -> Result<(), String> {
+        self.objects = crate::tiled::import_map(&self.tiled_map, &self.offset)
+            .map_err(|err| format!("could not import Tiled map {}: {}", self.tiled_map, err))?;
+        Ok(())
+    }
+}
+
+/// Imports the Tiled maps in a list of objects, including the ones in groups.
+fn import_tiled_maps(objects: &mut [MapObjectKind]) -> Result<(), String> {
+    for object in objects.iter_mut() {
+        match object {
+            MapObjectKind::Group(group) => {
+                let group =
+                    Arc::get_mut(group).expect("config objects aren't shared while loading");
+                import_tiled_maps(&mut group.objects)?;
+            }
+            MapObjectKind::Tiled(tiled) => {
+                Arc::get_mut(tiled)
+                    .expect("config objects aren't shared while loading")
+                    .import()?;
+            }
+            MapObjectKind::Object(_) | MapObjectKind::Building(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/* Map generator */
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct GeneratorConfig {
+    #[serde(default = "default_seed")]
+    pub seed: Option<u64>, // Use the same seed every round; otherwise a random seed is picked
+    pub center: Vector,
+    pub size: Vector, // Area that buildings and scatter objects are placed in
+
+    #[serde(default = "default_rects")]
+    pub reserved_areas: Vec<Rect>, // Areas kept clear of generated objects, e.g. spawns
+
+    #[serde(default = "default_building_rules")]
+    pub buildings: Vec<BuildingRuleConfig>,
+
+    #[serde(default = "default_scatter_rules")]
+    pub scatter: Vec<ScatterRuleConfig>,
+
+    pub pools: HashMap<String, Vec<String>>, // Named lists of prefab ids to pick from
+}
+
+impl GeneratorConfig {
+    /// Checks that the rules can be generated from, since the generator picks from the ranges
+    /// without checking them.
+    pub fn check(&self) -> Result<(), String> {
+        if !is_positive(self.size.x) || !is_positive(self.size.y) {
+            return Err("size must be positive".to_string());
+        }
+        for rule in self.buildings.iter() {
+            check_count_range("count", rule.count, 0)?;
+            check_count_range("roomsWide", rule.rooms_wide, 1)?;
+            check_count_range("roomsDeep", rule.rooms_deep, 1)?;
+            check_count_range("propsPerRoom", rule.props_per_room, 0)?;
+            if !is_positive(rule.room_size) {
+                return Err("roomSize must be positive".to_string());
+            }
+            if !is_distance(rule.spacing) || !is_distance(rule.room_padding) {
+                return Err("spacing and roomPadding can't be negative".to_string());
+            }
+            self.check_pool(&rule.room_pool)?;
+        }
+        for rule in self.scatter.iter() {
+            if !is_distance(rule.density) {
+                return Err("density can't be negative".to_string());
+            }
+            if !is_distance(rule.spacing) {
+                return Err("spacing can't be negative".to_string());
+            }
+            self.check_pool(&rule.pool)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_pool(&self, pool: &str) -> Result<(), String> {
+        match self.pools.get(pool) {
+            Some(prefabs) if !prefabs.is_empty() => Ok(()),
+            Some(_) => Err(format!("pool {} is empty", pool)),
+            None => Err(format!("missing pool {}", pool)),
+        }
+    }
+}
+
+fn is_positive(value: FloatType) -> bool {
+    value.is_finite() && value > 0.
+}
+
+fn is_distance(value: FloatType) -> bool {
+    value.is_finite() && value >= 0.
+}
+
+/// Checks a `[min, max]` range from the generator rules.
+fn check_count_range(name: &str, (min, max): (u32, u32), lowest: u32) -> Result<(), String> {
+    if min < lowest {
+        Err(format!("{} must be at least {}", name, lowest))
+    } else if min > max {
+        Err(format!("{} has a min above its max", name))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildingRuleConfig {
+    pub count: (u32, u32),
+    pub rooms_wide: (u32, u32),
+    pub rooms_deep: (u32, u32),
+    pub room_size: FloatType,
+    pub spacing: FloatType, // Minimum distance to other buildings and reserved areas
+
+    // Prefabs for the building grid
+    pub corner: String,
+    pub outside_wall: String,
+    pub outside_door: String,
+    pub inside_wall: String,
+    pub floor: String,
+
+    pub room_pool: String, // Pool to furnish each room from
+    pub props_per_room: (u32, u32),
+    #[serde(default = "default_room_padding")]
+    pub room_padding: FloatType, // Distance to keep furniture from the walls
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ScatterRuleConfig {
+    pub pool: String,
+    pub density: FloatType, // Objects per 100x100 units
+    pub spacing: FloatType, // Minimum distance to other objects
+
+    #[serde(default = "default_bool_true")]
+    pub random_yaw: bool,
+}
+
 /* Default values */
 fn default_kind() -> PrefabKind {
     PrefabKind::Prop
@@ -223,6 +660,9 @@ fn default_kind() -> PrefabKind {
 fn default_health() -> f64 {
     3.
 }
+fn default_debris() -> Vec<ObjectConfigHandle> {
+    Vec::new()
+}
 fn default_asset() -> Option<String> {
     None
 }
@@ -232,6 +672,9 @@ fn default_pos() -> Vector {
 fn default_rot() -> u8 {
     0
 }
+fn default_yaw() -> FloatType {
+    0.
+}
 fn default_spawn_chance() -> f64 {
     1.0
 }
@@ -247,6 +690,74 @@ fn default_color() -> String {
 fn default_bool_false() -> bool {
     false
 }
+fn default_bool_true() -> bool {
+    true
+}
+fn default_stats_store() -> StatsStoreConfig {
+    StatsStoreConfig::None
+}
+fn default_admin_file_dir() -> String {
+    "admin-files".to_string()
+}
+fn default_lobbies() -> Vec<LobbyConfig> {
+    vec![LobbyConfig {
+        id: "default".to_string(),
+        token_var: default_token_var(),
+        custom: false,
+        overrides: LobbyOverrides::default(),
+        snapshot: None,
+    }]
+}
+fn default_token_var() -> String {
+    "RIVET_TOKEN".to_string()
+}
+fn default_places() -> u64 {
+    1
+}
+fn default_score_display() -> ScoreDisplay {
+    ScoreDisplay::Count
+}
+fn default_map_id() -> String {
+    "map-a".to_string()
+}
+fn default_spawn_zones() -> Vec<SpawnZoneConfig> {
+    vec![SpawnZoneConfig {
+        name: "center".to_string(),
+        team: SpawnTeam::Any,
+        area: Rect::new(Vector::new(0., 0., 1.), Vector::new(150., 150., 0.)),
+        weight: 1.,
+    }]
+}
+fn default_spawn_team() -> SpawnTeam {
+    SpawnTeam::Any
+}
+fn default_spawn_weight() -> FloatType {
+    1.
+}
+fn default_spawn_protection() -> SpawnProtectionConfig {
+    SpawnProtectionConfig {
+        pin_hunters: true,
+        hunting_grace_period: default_grace_period(),
+    }
+}
+fn default_grace_period() -> FloatType {
+    0.
+}
+fn default_generator() -> Option<Arc<GeneratorConfig>> {
+    None
+}
+fn default_seed() -> Option<u64> {
+    None
+}
+fn default_building_rules() -> Vec<BuildingRuleConfig> {
+    Vec::new()
+}
+fn default_scatter_rules() -> Vec<ScatterRuleConfig> {
+    Vec::new()
+}
+fn default_room_padding() -> FloatType {
+    15.
+}
 
 /* Deserialize Vector */
 struct VectorVisitor;
@@ -314,3 +825,34 @@ impl<'de> serde::de::Deserialize<'de> for Rect {
         deserializer.deserialize_seq(RectVisitor)
     }
 }
+
+/* Serialize Vector */
+impl Serialize for Vector {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(3))?;
+        seq.serialize_element(&self.x)?;
+        seq.serialize_element(&self.y)?;
+        seq.serialize_element(&self.z)?;
+        seq.end()
+    }
+}
+
+/* Serialize Rect */
+impl Serialize for Rect {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // Move the position back to the bottom center of the rect
+        let mut position = self.center.clone();
+        position.z -= self.size.z / 2.;
+
+        let mut seq = serializer.serialize_seq(Some(2))?;
+        seq.serialize_element(&position)?;
+        seq.serialize_element(&self.size)?;
+        seq.end()
+    }
+}
@@ -7,6 +7,7 @@ use crate::quad_tree::{QuadTree, TreeBounds};
 use crate::utils::Ray;
 use crate::utils::Rect;
 use crate::utils::time_milliseconds;
+use crate::supervisor::Suspect;
 
 /// This holds the game's map data
 pub struct GameWorld {
@@ -50,6 +51,7 @@ impl GameWorld {
             measure!("Physics will update event");
 
             for entity in self.entities.iter_mut() {
+                let _suspect = Suspect::Entity(*entity.borrow().id()).watch();
                 entity.borrow_mut().physics_will_update(dt);
             }
         }
@@ -69,6 +71,7 @@ impl GameWorld {
             measure!("Physics did update event");
 
             for entity in self.entities.iter_mut() {
+                let _suspect = Suspect::Entity(*entity.borrow().id()).watch();
                 entity.borrow_mut().physics_did_update(dt);
             }
         }
@@ -84,6 +87,7 @@ impl GameWorld {
         for entity in self.entities.iter_mut() {
             measure!("Update entity velocity");
 
+            let _suspect = Suspect::Entity(*entity.borrow().id()).watch();
             let mut entity = entity.borrow_mut();
             let mut body = entity.body_mut();
 
@@ -110,6 +114,7 @@ impl GameWorld {
         self.quad_tree.update_tree(&mut missing_entities);
         for entity in missing_entities.iter() {
             let entity = entity.borrow();
+            let _suspect = Suspect::Entity(*entity.id()).watch();
             panic!("Entity {} was pushed out of tree during update.", entity.id());
         }
 
@@ -133,6 +138,35 @@ impl GameWorld {
         self.destroyed_entities.clear();
     }
 
+    /* Integrity */
+    /// Checks that an update didn't leave the world half changed: every entity can be used and
+    /// is in the quad tree exactly once.
+    pub fn is_consistent(&self) -> bool {
+        for entity in self.entities.iter() {
+            let entity = match entity.try_borrow() {
+                Ok(entity) => entity,
+                Err(_) => return false
+            };
+            let body = entity.body();
+            let position = body.get_pos();
+            if !position.x.is_finite() || !position.y.is_finite() {
+                return false;
+            }
+        }
+
+        self.quad_tree.entity_count() == self.entities.len()
+    }
+
+    /// Puts every entity back in the quad tree.
+    pub fn rebuild_tree(&mut self) {
+        measure!("Rebuild tree");
+
+        self.quad_tree.clear();
+        for entity in self.entities.iter() {
+            self.quad_tree.insert(entity.clone());
+        }
+    }
+
     /* Entities */
     pub fn entities(&self) -> &Vec<EntityHandle> {
         &self.entities
@@ -0,0 +1,490 @@
+use crate::game::Game;
+use crate::game_config::{
+    CharacterConfig, CustomLobbyConfig, LobbyConfig, LobbyOverrides, GAME_CONFIG,
+};
+use crate::network::ClientHandleData;
+use crate::rivet;
+use crate::teams::hunter_count;
+use crate::utils::{FloatType, Serializable};
+use rmpv::Value;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+/*** Settings ***/
+/// The rules a lobby plays with, after applying its overrides to the game config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LobbySettings {
+    pub map_id: String,
+    pub pre_game_duration: FloatType,
+    pub hiding_duration: FloatType,
+    pub hunting_duration: FloatType,
+    pub min_players: usize,
+
+    /// Number of hunters each round; picked from the number of players if `None`
+    pub hunter_count: Option<usize>,
+
+    /// Characters players can join with; any character if `None`
+    pub allowed_characters: Option<Vec<String>>,
+
+    /// Number of rounds to play before waiting for the host to start again; rounds keep going if
+    /// `None`
+    pub rounds: Option<u64>,
+}
+
+impl LobbySettings {
+    /// The most players a lobby's overrides can have bots fill it up to
+    pub const MAX_MIN_PLAYERS: usize = 32;
+
+    pub fn new(overrides: &LobbyOverrides) -> LobbySettings {
+        LobbySettings {
+            map_id: overrides
+                .map_id
+                .clone()
+                .unwrap_or_else(|| GAME_CONFIG.map_id.clone()),
+            pre_game_duration: overrides
+                .pre_game_duration
+                .unwrap_or(Game::PRE_GAME_DURATION),
+            hiding_duration: overrides.hiding_duration.unwrap_or(Game::HIDING_DURATION),
+            hunting_duration: overrides.hunting_duration.unwrap_or(Game::HUNTING_DURATION),
+            min_players: overrides
+                .min_players
+                .unwrap_or(GAME_CONFIG.bots.min_players),
+            hunter_count: None,
+            allowed_characters: None,
+            rounds: None,
+        }
+    }
+
+    /// Changes a setting if it's within the bounds for custom lobbies.
+    pub fn apply(
+        &mut self,
+        setting: LobbySetting,
+        bounds: &CustomLobbyConfig,
+    ) -> Result<(), SettingError> {
+        match setting {
+            LobbySetting::Map(map_id) => {
+                if !GAME_CONFIG.maps.contains_key(&map_id) {
+                    return Err(SettingError::UnknownMap);
+                }
+                self.map_id = map_id;
+            }
+            LobbySetting::PreGameDuration(duration) => {
+                self.pre_game_duration = check_range(duration, bounds.pre_game_duration)?
+            }
+            LobbySetting::HidingDuration(duration) => {
+                self.hiding_duration = check_range(duration, bounds.hiding_duration)?
+            }
+            LobbySetting::HuntingDuration(duration) => {
+                self.hunting_duration = check_range(duration, bounds.hunting_duration)?
+            }
+            LobbySetting::HunterCount(count) => {
+                if let Some(count) = count {
+                    if count == 0 || count > bounds.max_hunters {
+                        return Err(SettingError::OutOfBounds);
+                    }
+                }
+                self.hunter_count = count;
+            }
+            LobbySetting::AllowedCharacters(characters) => {
+                if characters.is_empty() {
+                    return Err(SettingError::NoCharacters);
+                }
+                let store = &GAME_CONFIG.store.characters;
+                if !characters
+                    .iter()
+                    .all(|id| store.iter().any(|c| &c.id == id))
+                {
+                    return Err(SettingError::UnknownCharacter);
+                }
+                self.allowed_characters = Some(characters);
+            }
+            LobbySetting::Rounds(rounds) => {
+                if let Some(rounds) = rounds {
+                    if rounds == 0 || rounds > bounds.max_rounds {
+                        return Err(SettingError::OutOfBounds);
+                    }
+                }
+                self.rounds = rounds;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The number of hunters for a round with this many players. A set hunter count always leaves
+    /// at least one prop, so a single player is a prop.
+    pub fn hunter_count(&self, player_count: usize) -> usize {
+        match self.hunter_count {
+            Some(count) => count.max(1).min(player_count.saturating_sub(1)),
+            None => hunter_count(player_count),
+        }
+    }
+
+    /// The characters players can join with.
+    pub fn characters(&self) -> Vec<&'static CharacterConfig> {
+        GAME_CONFIG
+            .store
+            .characters
+            .iter()
+            .filter(|c| self.allows_character(&c.id))
+            .collect()
+    }
+
+    pub fn allows_character(&self, character_id: &str) -> bool {
+        match self.allowed_characters {
+            Some(ref allowed) => allowed.iter().any(|id| id == character_id),
+            None => true,
+        }
+    }
+}
+
+impl Serializable for LobbySettings {
+    fn serialize(&self) -> Value {
+        Value::Array(vec![
+            self.map_id.as_str().into(),
+            self.pre_game_duration.into(),
+            self.hiding_duration.into(),
+            self.hunting_duration.into(),
+            self.hunter_count
+                .map_or(Value::Nil, |count| (count as u64).into()),
+            self.allowed_characters.as_ref().map_or(Value::Nil, |ids| {
+                Value::Array(ids.iter().map(|id| id.as_str().into()).collect())
+            }),
+            self.rounds.map_or(Value::Nil, |rounds| rounds.into()),
+        ])
+    }
+}
+
+fn check_range(
+    value: FloatType,
+    (min, max): (FloatType, FloatType),
+) -> Result<FloatType, SettingError> {
+    if value >= min && value <= max {
+        Ok(value)
+    } else {
+        Err(SettingError::OutOfBounds)
+    }
+}
+
+/// A setting the host of a custom lobby wants to change.
+#[derive(Debug, Clone)]
+pub enum LobbySetting {
+    Map(String),
+    PreGameDuration(FloatType),
+    HidingDuration(FloatType),
+    HuntingDuration(FloatType),
+    HunterCount(Option<usize>),
+    AllowedCharacters(Vec<String>),
+    Rounds(Option<u64>),
+}
+
+/// Reasons a lobby setting can't be changed.
+#[derive(Debug, PartialEq)]
+pub enum SettingError {
+    UnknownMap,
+    UnknownCharacter,
+    NoCharacters,
+    OutOfBounds,
+}
+
+/// Checks the overrides of a lobby in the config, since they aren't bounded like the settings
+/// picked by the host of a custom lobby.
+fn check_overrides(overrides: &LobbyOverrides) -> Result<(), String> {
+    if let Some(ref map_id) = overrides.map_id {
+        if !GAME_CONFIG.maps.contains_key(map_id) {
+            return Err(format!("unknown map {}", map_id));
+        }
+    }
+    let durations = [
+        ("preGameDuration", overrides.pre_game_duration),
+        ("hidingDuration", overrides.hiding_duration),
+        ("huntingDuration", overrides.hunting_duration),
+    ];
+    for (name, duration) in durations.iter() {
+        if let Some(duration) = duration {
+            if !duration.is_finite() || *duration <= 0. {
+                return Err(format!("{} must be a positive number of seconds", name));
+            }
+        }
+    }
+    if let Some(min_players) = overrides.min_players {
+        if min_players > LobbySettings::MAX_MIN_PLAYERS {
+            return Err(format!(
+                "minPlayers can't be more than {}",
+                LobbySettings::MAX_MIN_PLAYERS
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks the lobbies in the config before any game starts.
+fn check_lobbies(lobbies: &[LobbyConfig]) -> Result<(), String> {
+    if lobbies.is_empty() {
+        return Err("no lobbies in the game config".to_string());
+    }
+    for (i, lobby) in lobbies.iter().enumerate() {
+        if lobbies[..i].iter().any(|other| other.id == lobby.id) {
+            return Err(format!("lobby id {} is used more than once", lobby.id));
+        }
+        check_overrides(&lobby.overrides).map_err(|err| format!("lobby {}: {}", lobby.id, err))?;
+    }
+
+    Ok(())
+}
+
+/*** Routing ***/
+/// Sends new connections to the game of the lobby they asked for.
+#[derive(Clone)]
+pub struct LobbyRouter {
+    lobbies: HashMap<String, Sender<ClientHandleData>>,
+
+    /// The lobby used when the client doesn't ask for one
+    default_lobby: String,
+}
+
+impl LobbyRouter {
+    pub fn new(default_lobby: String) -> LobbyRouter {
+        LobbyRouter {
+            lobbies: HashMap::new(),
+            default_lobby,
+        }
+    }
+
+    pub fn insert(&mut self, lobby_id: String, tx_client_handle: Sender<ClientHandleData>) {
+        self.lobbies.insert(lobby_id, tx_client_handle);
+    }
+
+    /// Finds the game for a lobby id; `None` if the lobby doesn't exist.
+    pub fn lobby(&self, lobby_id: Option<&str>) -> Option<&Sender<ClientHandleData>> {
+        self.lobbies
+            .get(lobby_id.unwrap_or(self.default_lobby.as_str()))
+    }
+}
+
+/*** Lobby Manager ***/
+/// Hosts a game for each lobby in the config. Each game runs on its own thread.
+pub struct LobbyManager {
+    router: LobbyRouter,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl LobbyManager {
+    pub fn start(lobbies: &[LobbyConfig]) -> LobbyManager {
+        if let Err(err) = check_lobbies(lobbies) {
+            panic!("Invalid lobby config: {}", err);
+        }
+
+        let mut manager = LobbyManager {
+            router: LobbyRouter::new(lobbies[0].id.clone()),
+            threads: Vec::new(),
+        };
+        for lobby in lobbies.iter() {
+            let (tx_client_handle, rx_client_handle) = channel();
+            manager.router.insert(lobby.id.clone(), tx_client_handle);
+
+            // The game can't be moved between threads, so it's created on the thread it runs on
+            let lobby = lobby.clone();
+            let thread = thread::Builder::new()
+                .name(format!("lobby-{}", lobby.id))
+                .spawn(move || {
+                    let game = Game::new(rx_client_handle, lobby.clone());
+
+                    rivet::lobby_ready(&lobby.token_var).expect("rivet::lobby_ready");
+
+                    println!("Lobby {} ready.", lobby.id);
+                    game.launch();
+                })
+                .expect("spawn lobby thread");
+            manager.threads.push(thread);
+        }
+
+        manager
+    }
+
+    /// The router to give to the socket server.
+    pub fn router(&self) -> LobbyRouter {
+        self.router.clone()
+    }
+
+    /// Blocks until every game stops.
+    pub fn join(self) {
+        for thread in self.threads.into_iter() {
+            if thread.join().is_err() {
+                println!("Lobby thread panicked.");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_router() {
+        let (tx_a, _rx_a) = channel();
+        let (tx_b, _rx_b) = channel();
+        let mut router = LobbyRouter::new("a".to_string());
+        router.insert("a".to_string(), tx_a);
+        router.insert("b".to_string(), tx_b);
+
+        assert!(router.lobby(Some("b")).is_some());
+        assert!(router.lobby(Some("c")).is_none());
+
+        // Clients that don't ask for a lobby go to the default one
+        assert!(router.lobby(None).is_some());
+        router.default_lobby = "c".to_string();
+        assert!(router.lobby(None).is_none());
+    }
+
+    #[test]
+    fn test_overrides() {
+        let overrides = LobbyOverrides {
+            hunting_duration: Some(60.),
+            min_players: Some(0),
+            ..LobbyOverrides::default()
+        };
+        let settings = LobbySettings::new(&overrides);
+        assert_eq!(settings.hunting_duration, 60.);
+        assert_eq!(settings.min_players, 0);
+        assert_eq!(settings.hiding_duration, Game::HIDING_DURATION);
+        assert_eq!(settings.map_id, GAME_CONFIG.map_id);
+    }
+
+    #[test]
+    fn test_check_lobbies() {
+        let lobby = |id: &str, overrides: LobbyOverrides| LobbyConfig {
+            id: id.to_string(),
+            token_var: "RIVET_TOKEN".to_string(),
+            custom: false,
+            overrides,
+            snapshot: None,
+        };
+
+        assert!(check_lobbies(&[]).is_err());
+        assert!(check_lobbies(&[
+            lobby("a", LobbyOverrides::default()),
+            lobby(
+                "b",
+                LobbyOverrides {
+                    map_id: Some(GAME_CONFIG.map_id.clone()),
+                    hunting_duration: Some(60.),
+                    min_players: Some(0),
+                    ..LobbyOverrides::default()
+                }
+            ),
+        ])
+        .is_ok());
+
+        // Duplicate ids would leave one of the games unreachable
+        assert!(check_lobbies(&[
+            lobby("a", LobbyOverrides::default()),
+            lobby("a", LobbyOverrides::default()),
+        ])
+        .is_err());
+
+        let invalid = [
+            LobbyOverrides {
+                map_id: Some("missing".to_string()),
+                ..LobbyOverrides::default()
+            },
+            LobbyOverrides {
+                hiding_duration: Some(0.),
+                ..LobbyOverrides::default()
+            },
+            LobbyOverrides {
+                pre_game_duration: Some(-5.),
+                ..LobbyOverrides::default()
+            },
+            LobbyOverrides {
+                hunting_duration: Some(FloatType::NAN),
+                ..LobbyOverrides::default()
+            },
+            LobbyOverrides {
+                min_players: Some(LobbySettings::MAX_MIN_PLAYERS + 1),
+                ..LobbyOverrides::default()
+            },
+        ];
+        for overrides in invalid.iter() {
+            assert!(check_lobbies(&[lobby("a", overrides.clone())]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_custom_settings() {
+        let bounds = CustomLobbyConfig {
+            pre_game_duration: (5., 60.),
+            hiding_duration: (5., 30.),
+            hunting_duration: (60., 600.),
+            max_hunters: 4,
+            max_rounds: 10,
+        };
+        let mut settings = LobbySettings::new(&LobbyOverrides::default());
+
+        assert_eq!(
+            settings.apply(LobbySetting::HuntingDuration(120.), &bounds),
+            Ok(())
+        );
+        assert_eq!(settings.hunting_duration, 120.);
+        assert_eq!(
+            settings.apply(LobbySetting::HidingDuration(90.), &bounds),
+            Err(SettingError::OutOfBounds)
+        );
+        assert_eq!(
+            settings.apply(LobbySetting::HunterCount(Some(5)), &bounds),
+            Err(SettingError::OutOfBounds)
+        );
+        assert_eq!(
+            settings.apply(LobbySetting::Rounds(Some(0)), &bounds),
+            Err(SettingError::OutOfBounds)
+        );
+        assert_eq!(
+            settings.apply(LobbySetting::Map("missing".to_string()), &bounds),
+            Err(SettingError::UnknownMap)
+        );
+        assert_eq!(
+            settings.apply(LobbySetting::AllowedCharacters(Vec::new()), &bounds),
+            Err(SettingError::NoCharacters)
+        );
+        assert_eq!(
+            settings.apply(
+                LobbySetting::AllowedCharacters(vec!["missing".to_string()]),
+                &bounds
+            ),
+            Err(SettingError::UnknownCharacter)
+        );
+
+        // Invalid settings don't change anything
+        assert_eq!(settings.hiding_duration, Game::HIDING_DURATION);
+        assert_eq!(settings.hunter_count, None);
+        assert_eq!(settings.rounds, None);
+
+        assert_eq!(
+            settings.apply(
+                LobbySetting::AllowedCharacters(vec!["basic".to_string()]),
+                &bounds
+            ),
+            Ok(())
+        );
+        assert!(settings.allows_character("basic"));
+        assert!(!settings.allows_character("nathan"));
+        assert_eq!(settings.characters().len(), 1);
+    }
+
+    #[test]
+    fn test_hunter_count() {
+        let mut settings = LobbySettings::new(&LobbyOverrides::default());
+        assert_eq!(settings.hunter_count(10), hunter_count(10));
+
+        // Leave at least one prop
+        settings.hunter_count = Some(4);
+        assert_eq!(settings.hunter_count(10), 4);
+        assert_eq!(settings.hunter_count(4), 3);
+        assert_eq!(settings.hunter_count(2), 1);
+        assert_eq!(settings.hunter_count(1), 0);
+    }
+}
@@ -1,29 +1,36 @@
 #[macro_use]
 mod utils;
 
+mod abilities;
+mod bots;
+mod chat;
 mod entities;
 mod game;
 mod game_config;
 mod game_world;
 mod incremental_value;
+mod lobbies;
+mod map_generator;
 mod network;
 mod quad_tree;
 mod rivet;
+mod snapshot;
+mod stats_store;
+mod supervisor;
+mod teams;
+mod tiled;
 
-use std::sync::mpsc::channel;
-
+use crate::game_config::GAME_CONFIG;
+use crate::lobbies::LobbyManager;
 use crate::network::start_socket_server;
 
 fn main() {
-    // Start the game
-    let (tx_client_handle, rx_client_handle) = channel();
-    let game = game::Game::new(rx_client_handle);
+    // Start a game for each lobby
+    let lobbies = LobbyManager::start(&GAME_CONFIG.lobbies);
 
     // Start socket server
-    start_socket_server("0.0.0.0:3000", tx_client_handle);
-
-    rivet::lobby_ready().expect("rivet::lobby_ready");
+    start_socket_server("0.0.0.0:3000", lobbies.router());
 
-    // Start the game loop
-    game.launch();
+    // Run the game loops
+    lobbies.join();
 }
@@ -0,0 +1,372 @@
+use crate::game_config::{
+    BuildingConfig, BuildingRuleConfig, GeneratorConfig, MapConfig, MapObjectKind, ObjectConfig,
+    ObjectConfigHandle, ScatterRuleConfig,
+};
+use crate::utils::{FloatType, Rect, Vector};
+use rand::{Rng, SeedableRng, StdRng};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Builds a `MapConfig` from a generator's rules. The same seed and rules will always produce
+/// the same map, so a good map can be saved and played again.
+pub struct MapGenerator<'a> {
+    /// The rules to generate the map with
+    config: &'a GeneratorConfig,
+
+    /// Random number generator seeded from the map seed
+    rng: StdRng,
+
+    /// Areas on the ground that already have something in them
+    occupied: Vec<Rect>,
+
+    /// The objects that have been generated so far
+    objects: Vec<MapObjectKind>,
+}
+
+impl<'a> MapGenerator<'a> {
+    /// Number of times to try to place an object before giving up on it.
+    const MAX_ATTEMPTS: usize = 25;
+
+    pub fn new(config: &'a GeneratorConfig, seed: u64) -> MapGenerator<'a> {
+        MapGenerator {
+            config,
+            rng: StdRng::from_seed(&[seed as usize, (seed >> 32) as usize][..]),
+            occupied: config.reserved_areas.clone(),
+            objects: Vec::new(),
+        }
+    }
+
+    /// Keeps generated objects out of an area.
+    pub fn reserve_area(&mut self, area: Rect) {
+        self.occupied.push(area);
+    }
+
+    /// Generates the map. The map's objects are always spawned along with the generated ones.
+    pub fn generate(mut self, map: &MapConfig) -> MapConfig {
+        measure!("Generate map");
+
+        let config = self.config;
+        self.objects.extend(map.objects.iter().cloned());
+
+        // Buildings go first since they need the most room
+        for rule in config.buildings.iter() {
+            let count = self.rng.gen_range(rule.count.0, rule.count.1 + 1);
+            for _ in 0..count {
+                self.place_building(rule);
+            }
+        }
+
+        // Scatter objects in the remaining space
+        for rule in config.scatter.iter() {
+            self.scatter(rule);
+        }
+
+        MapConfig {
+            name: map.name.clone(),
+            objects: self.objects,
+            spawn_zones: map.spawn_zones.clone(),
+            spawn_protection: map.spawn_protection.clone(),
+            generator: None,
+        }
+    }
+
+    /// Finds a free spot for a building and lays out its walls and rooms.
+    fn place_building(&mut self, rule: &BuildingRuleConfig) {
+        measure!("Place building");
+
+        let rooms_wide = self.rng.gen_range(rule.rooms_wide.0, rule.rooms_wide.1 + 1);
+        let rooms_deep = self.rng.gen_range(rule.rooms_deep.0, rule.rooms_deep.1 + 1);
+        let width = rooms_wide as FloatType * rule.room_size;
+        let depth = rooms_deep as FloatType * rule.room_size;
+
+        // Find a position where the building fits; the building's offset is its bottom left corner
+        let offset = match self.find_free_position(width, depth, rule.spacing) {
+            Some(position) => Vector::new(position.x - width / 2., position.y - depth / 2., 0.),
+            None => return,
+        };
+
+        // Build the grid; see `BuildingConfig` for the format
+        let door_index = self.rng.gen_range(0, rooms_wide);
+        let mut map = Vec::new();
+        for y in 0..(rooms_deep * 2 + 1) {
+            let mut line = String::new();
+            for x in 0..(rooms_wide * 2 + 1) {
+                let is_top = y == 0;
+                let is_bottom = y == rooms_deep * 2;
+                let is_left = x == 0;
+                let is_right = x == rooms_wide * 2;
+                let chunk = match (x % 2 == 0, y % 2 == 0) {
+                    // Corners
+                    (true, true) => "x ",
+
+                    // Horizontal walls; the door is always on the bottom side
+                    (false, true) if is_bottom && x / 2 == door_index => "b2",
+                    (false, true) if is_bottom => "d2",
+                    (false, true) if is_top => "d ",
+                    (false, true) => "a ",
+
+                    // Vertical walls
+                    (true, false) if is_left => "d ",
+                    (true, false) if is_right => "d2",
+                    (true, false) => "a ",
+
+                    // Floors
+                    (false, false) => "f ",
+                };
+                line.push_str(chunk);
+            }
+            map.push(line.trim_end().to_string());
+        }
+
+        // Create the lookup table for the grid
+        let mut lookup_table = BTreeMap::new();
+        for &(symbol, prefab_id) in [
+            ("x", &rule.corner),
+            ("d", &rule.outside_wall),
+            ("b", &rule.outside_door),
+            ("a", &rule.inside_wall),
+            ("f", &rule.floor),
+        ]
+        .iter()
+        {
+            lookup_table.insert(
+                symbol.to_string(),
+                MapGenerator::object(prefab_id, Vector::zero(), 0.),
+            );
+        }
+
+        self.objects
+            .push(MapObjectKind::Building(Arc::new(BuildingConfig {
+                building_name: format!("Generated Building {}", self.objects.len()),
+                offset: offset.clone(),
+                room_size: rule.room_size,
+                centered: false,
+                lookup_table,
+                map: map.join("\n"),
+            })));
+
+        // Furnish the rooms
+        for room_x in 0..rooms_wide {
+            for room_y in 0..rooms_deep {
+                let center = Vector::new(
+                    offset.x + (room_x as FloatType + 0.5) * rule.room_size,
+                    offset.y + (room_y as FloatType + 0.5) * rule.room_size,
+                    1.,
+                );
+                self.furnish_room(rule, center);
+            }
+        }
+    }
+
+    /// Places props from the rule's pool inside of a room.
+    fn furnish_room(&mut self, rule: &BuildingRuleConfig, center: Vector) {
+        let count = self
+            .rng
+            .gen_range(rule.props_per_room.0, rule.props_per_room.1 + 1);
+        let range = f64::max(rule.room_size / 2. - rule.room_padding, 0.);
+        for _ in 0..count {
+            let prefab_id = match self.pick_from_pool(&rule.room_pool) {
+                Some(id) => id,
+                None => return,
+            };
+
+            // Pick a spot in the room and snap the prop to face a wall
+            let mut position = center.clone();
+            if range > 0. {
+                position.x += self.rng.gen_range(-range, range);
+                position.y += self.rng.gen_range(-range, range);
+            }
+            let yaw = (self.rng.gen_range(0, 4) * 90) as FloatType;
+
+            self.objects
+                .push(MapObjectKind::Object(MapGenerator::object(
+                    &prefab_id, position, yaw,
+                )));
+        }
+    }
+
+    /// Places objects from the rule's pool in the free space of the map.
+    fn scatter(&mut self, rule: &ScatterRuleConfig) {
+        measure!("Scatter");
+
+        let area = self.config.size.x * self.config.size.y;
+        let count = (area / 10_000. * rule.density) as usize;
+        for _ in 0..count {
+            let position = match self.find_free_position(rule.spacing, rule.spacing, 0.) {
+                Some(position) => position,
+                None => continue,
+            };
+            let prefab_id = match self.pick_from_pool(&rule.pool) {
+                Some(id) => id,
+                None => return,
+            };
+            let yaw = if rule.random_yaw {
+                self.rng.gen_range(0., 360.)
+            } else {
+                0.
+            };
+
+            self.objects
+                .push(MapObjectKind::Object(MapGenerator::object(
+                    &prefab_id,
+                    Vector::new(position.x, position.y, 1.),
+                    yaw,
+                )));
+        }
+    }
+
+    /// Finds a random position within the generator's area where a rect of the given size
+    /// doesn't overlap anything, then marks that area as occupied.
+    fn find_free_position(
+        &mut self,
+        width: FloatType,
+        depth: FloatType,
+        spacing: FloatType,
+    ) -> Option<Vector> {
+        let range_x = (self.config.size.x - width) / 2.;
+        let range_y = (self.config.size.y - depth) / 2.;
+        if range_x <= 0. || range_y <= 0. {
+            return None;
+        }
+
+        for _ in 0..MapGenerator::MAX_ATTEMPTS {
+            let position = Vector::new(
+                self.config.center.x + self.rng.gen_range(-range_x, range_x),
+                self.config.center.y + self.rng.gen_range(-range_y, range_y),
+                0.,
+            );
+
+            // Check the area with the spacing around it
+            let area = Rect::new(
+                position.clone(),
+                Vector::new(width + spacing * 2., depth + spacing * 2., 99999.),
+            );
+            let is_free = !self
+                .occupied
+                .iter()
+                .any(|r| area.intersects(r, &Vector::zero(), &Vector::zero()));
+            if is_free {
+                self.occupied.push(Rect::new(
+                    position.clone(),
+                    Vector::new(width, depth, 99999.),
+                ));
+                return Some(position);
+            }
+        }
+
+        None
+    }
+
+    /// Picks a random prefab id from a pool.
+    fn pick_from_pool(&mut self, pool: &str) -> Option<String> {
+        match self.config.pools.get(pool) {
+            Some(prefabs) => self.rng.choose(prefabs).cloned(),
+            None => {
+                println!("Missing map generator pool {}", pool);
+                None
+            }
+        }
+    }
+
+    fn object(prefab_id: &str, position: Vector, yaw: FloatType) -> ObjectConfigHandle {
+        ObjectConfigHandle::new(ObjectConfig {
+            prefab_id: prefab_id.to_string(),
+            position,
+            rotation: 0,
+            yaw,
+            spawn_chance: 1.,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::game_config::GAME_CONFIG;
+    use serde_yaml;
+
+    fn generate_yaml(seed: u64) -> String {
+        let map = GAME_CONFIG.maps.get("generated").unwrap();
+        let generator = map.generator.as_ref().unwrap();
+        let generated = MapGenerator::new(generator, seed).generate(map);
+        serde_yaml::to_string(&generated).unwrap()
+    }
+
+    #[test]
+    fn test_same_seed() {
+        assert_eq!(generate_yaml(1234), generate_yaml(1234));
+        assert_ne!(generate_yaml(1234), generate_yaml(4321));
+    }
+
+    fn parse_generator(building: &str, scatter: &str) -> GeneratorConfig {
+        let yaml = format!(
+            "center: [0, 0, 0]
+size: [1000, 1000, 0]
+buildings:
+- {{ {}, roomSize: 100, spacing: 10, corner: a, outsideWall: a, outsideDoor: a, insideWall: a, floor: a, roomPool: props }}
+scatter:
+- {{ {} }}
+pools:
+  props: [a]
+",
+            building, scatter
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn test_check_rules() {
+        let rooms = "roomsWide: [1, 2], roomsDeep: [1, 1]";
+        let building = |rule: &str| format!("{}, propsPerRoom: [0, 2]", rule);
+        let scatter = "pool: props, density: 0.1, spacing: 20";
+
+        assert!(GAME_CONFIG.maps["generated"]
+            .generator
+            .as_ref()
+            .unwrap()
+            .check()
+            .is_ok());
+        assert!(
+            parse_generator(&building(&format!("count: [1, 2], {}", rooms)), scatter)
+                .check()
+                .is_ok()
+        );
+
+        // Ranges the generator can't pick from
+        let invalid_buildings = [
+            format!("count: [2, 1], {}", rooms),
+            "count: [1, 1], roomsWide: [0, 2], roomsDeep: [1, 1]".to_string(),
+            "count: [1, 1], roomsWide: [1, 1], roomsDeep: [3, 2]".to_string(),
+        ];
+        for rule in invalid_buildings.iter() {
+            assert!(parse_generator(&building(rule), scatter).check().is_err());
+        }
+        let rule = format!("count: [1, 1], {}, propsPerRoom: [3, 1]", rooms);
+        assert!(parse_generator(&rule, scatter).check().is_err());
+
+        let rule = building(&format!("count: [1, 1], {}", rooms));
+        assert!(
+            parse_generator(&rule, "pool: props, density: -1, spacing: 20")
+                .check()
+                .is_err()
+        );
+        assert!(
+            parse_generator(&rule, "pool: props, density: 0.1, spacing: .nan")
+                .check()
+                .is_err()
+        );
+        assert!(
+            parse_generator(&rule, "pool: missing, density: 0.1, spacing: 20")
+                .check()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_saved_map_loads() {
+        let yaml = generate_yaml(1234);
+        let map = serde_yaml::from_str::<MapConfig>(&yaml).unwrap();
+        assert!(map.generator.is_none());
+        assert_eq!(serde_yaml::to_string(&map).unwrap(), yaml);
+    }
+}
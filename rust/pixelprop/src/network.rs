@@ -1,7 +1,9 @@
+use crate::chat::{ChatChannel, ChatRateLimiter};
 use crate::entities::MoveMessage;
-use crate::entities::{EntityHandle, EntityId};
+use crate::entities::{EntityHandle, EntityId, EntityKind, PlayerState};
 use crate::game::GameState;
 use crate::game_world::GameWorld;
+use crate::lobbies::{LobbyRouter, LobbySetting};
 use crate::utils::time_milliseconds;
 use crate::utils::Rect;
 use crate::utils::Serializable;
@@ -18,18 +20,13 @@ use ws::{
     listen, CloseCode, Error, Handler, Handshake, Message, Result as WsResult, Sender as WsSender,
 };
 
-/// Starts a thread that hosts the websocket.
-pub fn start_socket_server(address: &'static str, tx_client_handle: Sender<ClientHandleData>) {
+/// Starts a thread that hosts the websocket. Clients are sent to the game of their lobby once
+/// they authenticate.
+pub fn start_socket_server(address: &'static str, router: LobbyRouter) {
     thread::spawn(move || {
         listen(address, |out| {
             // Create the client
-            let (client, handle_data) = Client::new(out);
-
-            // Send the handle to the game
-            tx_client_handle.send(handle_data).unwrap();
-
-            // Return the client
-            client
+            Client::new(out, router.clone())
         })
         .unwrap();
     });
@@ -45,6 +42,11 @@ pub enum MessageError {
     MissingData,
     DataType,
     EventType,
+    NotAuthenticated,
+    UnknownLobby,
+
+    /// The game stopped listening to the client, e.g. after quarantining it or if its lobby died
+    GameClosed,
 }
 
 impl Display for MessageError {
@@ -61,17 +63,20 @@ pub type ClientId = u64;
 pub enum ClientMessage {
     Auth {
         rivet_player_token: String,
+        lobby_id: Option<String>, // The lobby to join; the default lobby if not given
+        reclaim_secret: Option<String>, // Sent back from a previous connection to the lobby
     },
     Join {
         username: String,
         character_id: String,
+        party: Option<String>, // Players in the same party are put on the same team
     },
     MoveDir(MoveMessage),
     FaceDir {
         dir: f64,
     },
     Rotate {
-        rot: u8,
+        rot: FloatType,
     },
     Shoot {
         target: Vector,
@@ -85,6 +90,15 @@ pub enum ClientMessage {
     TempData {
         data: String,
     },
+    Chat {
+        channel: ChatChannel,
+        message: String,
+    },
+    UseAbility {
+        ability_id: String,
+    },
+    LobbySetting(LobbySetting),
+    StartRounds,
     Leave,
 }
 
@@ -102,28 +116,45 @@ impl ClientMessage {
             8 => Ok(ClientMessage::ForcePing),
             9 => ClientMessage::cheat_code(data),
             10 => ClientMessage::temp_data_message(data),
+            11 => ClientMessage::chat_message(data),
+            12 => ClientMessage::use_ability_message(data),
+            13 => ClientMessage::lobby_setting_message(data),
+            14 => Ok(ClientMessage::StartRounds),
             _ => Err(MessageError::EventType),
         }
     }
 
     fn auth_message(data: &Value) -> Result<ClientMessage, MessageError> {
         let data: &Vec<Value> = unwrap_data!(data.as_array());
-        if data.len() != 1 {
+        if data.is_empty() || data.len() > 3 {
             return Err(MessageError::MissingData);
         }
         Ok(ClientMessage::Auth {
             rivet_player_token: unwrap_data!(data[0].as_str()).to_string(),
+            lobby_id: data
+                .get(1)
+                .and_then(|id| id.as_str())
+                .map(|id| id.to_string()),
+            reclaim_secret: data
+                .get(2)
+                .and_then(|secret| secret.as_str())
+                .map(|secret| secret.to_string()),
         })
     }
 
     fn join_message(data: &Value) -> Result<ClientMessage, MessageError> {
         let data: &Vec<Value> = unwrap_data!(data.as_array());
-        if data.len() != 2 {
+        if data.len() < 2 || data.len() > 3 {
             return Err(MessageError::MissingData);
         }
         Ok(ClientMessage::Join {
             username: unwrap_data!(data[0].as_str()).to_string(),
             character_id: unwrap_data!(data[1].as_str()).to_string(),
+            party: data
+                .get(2)
+                .and_then(|party| party.as_str())
+                .filter(|party| !party.is_empty())
+                .map(|party| party.to_string()),
         })
     }
 
@@ -146,8 +177,12 @@ impl ClientMessage {
     }
 
     fn rotate_message(data: &Value) -> Result<ClientMessage, MessageError> {
+        // Whole angles may be encoded as integers
+        let rot = data
+            .as_f64()
+            .or_else(|| data.as_i64().map(|r| r as FloatType));
         Ok(ClientMessage::Rotate {
-            rot: unwrap_data!(data.as_u64()) as u8,
+            rot: unwrap_data!(rot),
         })
     }
 
@@ -182,18 +217,82 @@ impl ClientMessage {
             data: unwrap_data!(data.as_str()).to_string(),
         })
     }
+
+    fn chat_message(data: &Value) -> Result<ClientMessage, MessageError> {
+        let data: &Vec<Value> = unwrap_data!(data.as_array());
+        if data.len() != 2 {
+            return Err(MessageError::MissingData);
+        }
+        Ok(ClientMessage::Chat {
+            channel: unwrap_data!(data[0].as_u64().and_then(ChatChannel::from_raw)),
+            message: unwrap_data!(data[1].as_str()).to_string(),
+        })
+    }
+
+    fn use_ability_message(data: &Value) -> Result<ClientMessage, MessageError> {
+        Ok(ClientMessage::UseAbility {
+            ability_id: unwrap_data!(data.as_str()).to_string(),
+        })
+    }
+
+    fn lobby_setting_message(data: &Value) -> Result<ClientMessage, MessageError> {
+        let data: &Vec<Value> = unwrap_data!(data.as_array());
+        if data.len() != 2 {
+            return Err(MessageError::MissingData);
+        }
+        let value = &data[1];
+        let setting = match unwrap_data!(data[0].as_u64()) {
+            0 => LobbySetting::Map(unwrap_data!(value.as_str()).to_string()),
+            1 => LobbySetting::PreGameDuration(unwrap_data!(value.as_f64())),
+            2 => LobbySetting::HidingDuration(unwrap_data!(value.as_f64())),
+            3 => LobbySetting::HuntingDuration(unwrap_data!(value.as_f64())),
+            4 => LobbySetting::HunterCount(if value.is_nil() {
+                None
+            } else {
+                Some(unwrap_data!(value.as_u64()) as usize)
+            }),
+            5 => LobbySetting::AllowedCharacters(
+                unwrap_data!(value.as_array())
+                    .iter()
+                    .map(|id| id.as_str().map(|id| id.to_string()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(MessageError::DataType)?,
+            ),
+            6 => LobbySetting::Rounds(if value.is_nil() {
+                None
+            } else {
+                Some(unwrap_data!(value.as_u64()))
+            }),
+            _ => return Err(MessageError::DataType),
+        };
+        Ok(ClientMessage::LobbySetting(setting))
+    }
 }
 
 /*** Client Handle ***/
 /// Used by the game to
 pub struct ClientHandle {
-    out: WsSender,
+    /// The socket to send messages to; bots don't have one
+    out: Option<WsSender>,
 
     pub is_admin: bool,
 
     pub rivet_player_token: Option<String>,
+    pub rivet_identity_id: Option<String>,
     pub sent_init_message: bool,
 
+    /// Issued by the server when the client authenticates; lets the client take their player
+    /// back after the game is restored from a snapshot, even in another process
+    pub reclaim_secret: Option<String>,
+
+    /// The name the client joined with, even if they're spectating
+    pub username: Option<String>,
+
+    /// If an admin muted the client's chat
+    pub is_muted: bool,
+
+    pub chat_limiter: ChatRateLimiter,
+
     watching_entities: Vec<EntityHandle>,
 
     /// Position at which the player is spectating from; this way if the player does not have
@@ -209,23 +308,58 @@ pub struct ClientHandle {
 
 impl ClientHandle {
     pub fn new(client_data: ClientHandleData) -> ClientHandle {
+        ClientHandle::with_socket(Some(client_data.0), client_data.1)
+    }
+
+    /// Creates a handle for a bot. Bots don't have a socket, so they send their messages through
+    /// the returned sender.
+    pub fn new_bot() -> (ClientHandle, Sender<ClientMessage>) {
+        let (tx_message, rx_message) = channel();
+        (ClientHandle::with_socket(None, rx_message), tx_message)
+    }
+
+    fn with_socket(out: Option<WsSender>, rx_message: Receiver<ClientMessage>) -> ClientHandle {
         ClientHandle {
-            out: client_data.0,
+            out,
             is_admin: false,
             rivet_player_token: None,
+            rivet_identity_id: None,
             sent_init_message: false,
+            reclaim_secret: None,
+            username: None,
+            is_muted: false,
+            chat_limiter: ChatRateLimiter::new(),
             watching_entities: Vec::new(),
             spectating_position: Vector::new(0., 0., 0.),
             player_id: None,
             spectating_id: None,
-            rx_message: client_data.1,
+            rx_message,
         }
     }
+
+    pub fn is_bot(&self) -> bool {
+        self.out.is_none()
+    }
+
+    /// The team the client is playing on, or `Spectator` if they don't have a player.
+    pub fn team(&self, world: &GameWorld) -> ClientTeam {
+        let player = self.player_id.and_then(|id| world.entity_with_id(&id));
+        if let Some(player) = player {
+            if let EntityKind::Player(ref player) = *player.borrow().kind() {
+                return match *player.state() {
+                    PlayerState::Hunter(_) => ClientTeam::Hunter,
+                    PlayerState::Prop(_) => ClientTeam::Prop,
+                };
+            }
+        }
+
+        ClientTeam::Spectator
+    }
 }
 
 impl SocketSender for ClientHandle {
-    fn socket_out(&self) -> &WsSender {
-        &self.out
+    fn socket_out(&self) -> Option<&WsSender> {
+        self.out.as_ref()
     }
 }
 
@@ -298,8 +432,10 @@ impl ClientHandle {
         let events = {
             measure!("Serialize events");
 
+            let team = self.team(world);
             events
                 .iter()
+                .filter(|e| e.visible_to(team))
                 .filter(|e| {
                     // If provides pos, check if it's in range; otherwise, send it to everyone
                     e.event_pos()
@@ -324,11 +460,11 @@ impl ClientHandle {
 
             // Compare with entities that are currently visible; we only query the origin and bounding
             // box since it's faster & not all entities have bodies
-            let spectating_rect = Rect {
+            let spectating_rect = Rect::new(
                 // TODO: Make this a constant
-                center: Vector::new(0., 0., 0.),
-                size: Vector::new(view_range * 2., view_range * 2., 99999.),
-            };
+                Vector::new(0., 0., 0.),
+                Vector::new(view_range * 2., view_range * 2., 99999.),
+            );
             let visible_entities = world.query_rect_all(
                 &spectating_rect,
                 &self.spectating_position,
@@ -446,33 +582,53 @@ pub struct Client {
     out: WsSender,
     is_open: bool,
     tx_message: Sender<ClientMessage>,
+
+    /// Finds the game to send the client to
+    router: LobbyRouter,
+
+    /// The handle data, until the client authenticates and it's sent to the game
+    handle_data: Option<ClientHandleData>,
 }
 
 impl Client {
-    pub fn new(out: WsSender) -> (Client, ClientHandleData) {
+    pub fn new(out: WsSender, router: LobbyRouter) -> Client {
         // Create the channels
         let (tx_message, rx_message) = channel();
 
         // Create an out for the handle
-        let client_handle_data = (out.clone(), rx_message);
+        let handle_data = Some((out.clone(), rx_message));
 
         // Create the client
-        let client = Client {
+        Client {
             out,
             is_open: false,
             tx_message,
-        };
+            router,
+            handle_data,
+        }
+    }
+
+    /// Sends the handle to the game of the lobby the client asked for.
+    fn join_lobby(&mut self, lobby_id: Option<&str>) -> Result<(), MessageError> {
+        let tx_client_handle = self
+            .router
+            .lobby(lobby_id)
+            .ok_or(MessageError::UnknownLobby)?;
+        if let Some(handle_data) = self.handle_data.take() {
+            tx_client_handle
+                .send(handle_data)
+                .map_err(|_| MessageError::GameClosed)?;
+        }
 
-        // Return both
-        (client, client_handle_data)
+        Ok(())
     }
 
     fn close(&mut self) {
-        // Send leave message
-        self.tx_message.send(ClientMessage::Leave).unwrap();
+        // Send leave message; the game may have already dropped a client it quarantined
+        self.tx_message.send(ClientMessage::Leave).ok();
 
         // Close the socket
-        self.out.close(CloseCode::Empty).unwrap();
+        self.out.close(CloseCode::Empty).ok();
 
         // Set to not open
         self.is_open = false;
@@ -480,7 +636,7 @@ impl Client {
 }
 
 impl Client {
-    fn parse_message(&self, msg: Message) -> Result<(), MessageError> {
+    fn parse_message(&mut self, msg: Message) -> Result<(), MessageError> {
         measure!("Parse message");
 
         // Get the data from the message
@@ -501,9 +657,23 @@ impl Client {
         let message_type = unwrap_data!(message[0].as_u64());
         let message_body = &message[1];
 
-        // Parse and send the message
+        // Parse the message
         let message = ClientMessage::from_data(message_type, message_body)?;
-        self.tx_message.send(message).unwrap();
+
+        // Join the lobby when authenticating; until then, the client isn't in a game
+        if self.handle_data.is_some() {
+            match message {
+                ClientMessage::Auth { ref lobby_id, .. } => {
+                    self.join_lobby(lobby_id.as_ref().map(|id| id.as_str()))?
+                }
+                _ => return Err(MessageError::NotAuthenticated),
+            }
+        }
+
+        // Send the message
+        self.tx_message
+            .send(message)
+            .map_err(|_| MessageError::GameClosed)?;
 
         Ok(())
     }
@@ -522,6 +692,14 @@ impl Handler for Client {
     fn on_message(&mut self, msg: Message) -> WsResult<()> {
         match self.parse_message(msg) {
             Ok(_) => Ok(()),
+            Err(MessageError::UnknownLobby) => {
+                println!("Error: {}", MessageError::UnknownLobby);
+                self.out.close(CloseCode::Policy)
+            }
+            Err(MessageError::GameClosed) => {
+                println!("Error: {}", MessageError::GameClosed);
+                self.out.close(CloseCode::Away)
+            }
             Err(err) => {
                 // TODO: Send error back, maybe with Err(...)
                 println!("Error: {}", err);
@@ -549,8 +727,8 @@ impl Handler for Client {
 }
 
 impl SocketSender for Client {
-    fn socket_out(&self) -> &WsSender {
-        &self.out
+    fn socket_out(&self) -> Option<&WsSender> {
+        Some(&self.out)
     }
 }
 
@@ -563,6 +741,10 @@ pub enum MessageType {
     ShootDelay,
     Stamina,
     GameResults,
+    MapInfo,
+    AbilityCooldown,
+    LobbySettings,
+    ReclaimSecret,
 }
 
 impl MessageType {
@@ -574,17 +756,27 @@ impl MessageType {
             MessageType::ShootDelay => 3,
             MessageType::Stamina => 4,
             MessageType::GameResults => 5,
+            MessageType::MapInfo => 6,
+            MessageType::AbilityCooldown => 7,
+            MessageType::LobbySettings => 8,
+            MessageType::ReclaimSecret => 9,
         }
     }
 }
 
 /// Trait used to easily serialize and send messages.
 pub trait SocketSender {
-    fn socket_out(&self) -> &WsSender;
+    fn socket_out(&self) -> Option<&WsSender>;
 
     fn send_message(&self, message_type: MessageType, message_body: Value) {
         measure!("Send message");
 
+        // Nothing to send to if there is no socket
+        let out = match self.socket_out() {
+            Some(out) => out,
+            None => return,
+        };
+
         // Create new message
         let message = Value::Array(vec![Value::from(message_type.message_flag()), message_body]);
 
@@ -593,7 +785,7 @@ pub trait SocketSender {
         write_value(&mut buf, &message).unwrap();
 
         // Send the message
-        self.socket_out().send(buf).unwrap();
+        out.send(buf).unwrap();
     }
 
     fn send_init(&self) {
@@ -617,6 +809,17 @@ pub trait SocketSender {
         self.send_message(MessageType::ShootDelay, data);
     }
 
+    /// Sends the time until an ability can be used and its charges left; `None` for the delay
+    /// means the player can't use the ability.
+    fn send_ability_cooldown(&self, ability_id: &str, delay: Option<u64>, charges: Option<u64>) {
+        let delay = delay.map_or_else(|| Value::Nil, |d| d.into());
+        let charges = charges.map_or_else(|| Value::Nil, |c| c.into());
+        self.send_message(
+            MessageType::AbilityCooldown,
+            Value::Array(vec![ability_id.into(), delay, charges]),
+        );
+    }
+
     fn send_stamina(&self, stamina: f64) {
         self.send_message(MessageType::Stamina, stamina.into());
     }
@@ -627,6 +830,22 @@ pub trait SocketSender {
             Value::Array(vec![props_win.into(), scoreboard, score_breakdown]),
         );
     }
+
+    fn send_map_info(&self, name: &str, seed: Option<u64>) {
+        let seed = seed.map_or_else(|| Value::Nil, |s| s.into());
+        self.send_message(MessageType::MapInfo, Value::Array(vec![name.into(), seed]));
+    }
+
+    fn send_reclaim_secret(&self, secret: &str) {
+        self.send_message(MessageType::ReclaimSecret, secret.into());
+    }
+
+    fn send_lobby_settings(&self, is_host: bool, settings: Value) {
+        self.send_message(
+            MessageType::LobbySettings,
+            Value::Array(vec![is_host.into(), settings]),
+        );
+    }
 }
 
 /*** Client Events ***/
@@ -637,6 +856,8 @@ pub enum ClientEventFlag {
     Ping,
     PlayerDeath,
     ScoreboardUpdate,
+    EntityDestroyed,
+    Chat,
 }
 
 impl ClientEventFlag {
@@ -647,6 +868,8 @@ impl ClientEventFlag {
             ClientEventFlag::Ping => 2,
             ClientEventFlag::PlayerDeath => 3,
             ClientEventFlag::ScoreboardUpdate => 4,
+            ClientEventFlag::EntityDestroyed => 5,
+            ClientEventFlag::Chat => 6,
         }
     }
 }
@@ -659,6 +882,24 @@ pub trait ClientEventData: Serializable {
     /// The position that the event occurs at. This way, we can determine which clients it should
     /// be sent to.
     fn event_pos(&self) -> Option<&Vector>;
+
+    /// The point a prop pinged from, if this is a ping event; bots use this to find props.
+    fn ping_point(&self) -> Option<&Vector> {
+        None
+    }
+
+    /// If a client on the team is allowed to receive the event.
+    fn visible_to(&self, _team: ClientTeam) -> bool {
+        true
+    }
+}
+
+/// Which side of the game a client is on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClientTeam {
+    Hunter,
+    Prop,
+    Spectator,
 }
 
 // Game state event
@@ -722,6 +963,10 @@ impl ClientEventData for PingEvent {
     fn event_pos(&self) -> Option<&Vector> {
         None // Show the ping to the whole map
     }
+
+    fn ping_point(&self) -> Option<&Vector> {
+        Some(&self.point)
+    }
 }
 
 impl Serializable for PingEvent {
@@ -771,3 +1016,60 @@ impl Serializable for ScoreboardUpdateEvent {
         self.data.clone()
     }
 }
+
+// Entity destroyed event
+pub struct EntityDestroyedEvent {
+    pub entity_id: EntityId,
+    pub position: Vector,
+}
+
+impl ClientEventData for EntityDestroyedEvent {
+    fn event_flag(&self) -> ClientEventFlag {
+        ClientEventFlag::EntityDestroyed
+    }
+
+    fn event_pos(&self) -> Option<&Vector> {
+        Some(&self.position)
+    }
+}
+
+impl Serializable for EntityDestroyedEvent {
+    fn serialize(&self) -> Value {
+        Value::Array(vec![self.entity_id.into(), self.position.serialize()])
+    }
+}
+
+// Chat event
+pub struct ChatEvent {
+    pub username: String,
+    pub channel: ChatChannel,
+    pub message: String,
+
+    /// The teams that can read the message
+    pub audience: Vec<ClientTeam>,
+}
+
+impl ClientEventData for ChatEvent {
+    fn event_flag(&self) -> ClientEventFlag {
+        ClientEventFlag::Chat
+    }
+
+    fn event_pos(&self) -> Option<&Vector> {
+        None
+    }
+
+    fn visible_to(&self, team: ClientTeam) -> bool {
+        self.audience.contains(&team)
+    }
+}
+
+impl Serializable for ChatEvent {
+    fn serialize(&self) -> Value {
+        // Only send the username, since a prop's entity id would give away which prop it is
+        Value::Array(vec![
+            self.username.clone().into(),
+            self.channel.raw().into(),
+            self.message.clone().into(),
+        ])
+    }
+}
@@ -217,6 +217,17 @@ impl QuadTree {
         }
     }
 
+    /// Number of entities in this tree and its children.
+    pub fn entity_count(&self) -> usize {
+        let mut count = self.entities.len();
+        if let Some(ref nodes) = self.nodes {
+            for tree in nodes.iter() {
+                count += tree.entity_count();
+            }
+        }
+        count
+    }
+
     /// If the tree is already split.
     pub fn is_split(&self) -> bool {
         match self.nodes {
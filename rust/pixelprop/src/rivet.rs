@@ -1,46 +1,77 @@
+use serde::Deserialize;
 use serde_json::json;
 
 fn endpoint() -> String {
     std::env::var("RIVET_API_ENDPOINT").expect("missing RIVET_API_ENDPOINT")
 }
 
-fn token() -> String {
-    std::env::var("RIVET_TOKEN").expect("missing RIVET_TOKEN")
+/// Reads the lobby token from an environment variable; each lobby has its own token.
+fn token(token_var: &str) -> String {
+    std::env::var(token_var).unwrap_or_else(|_| panic!("missing {}", token_var))
 }
 
-pub fn lobby_ready() -> reqwest::Result<()> {
+pub fn lobby_ready(token_var: &str) -> reqwest::Result<()> {
     println!("rivet::lobby_ready");
 
     let client = reqwest::blocking::Client::new();
     client
         .post(format!("{}/matchmaker/lobbies/ready", endpoint()))
-        .bearer_auth(token())
+        .bearer_auth(token(token_var))
         .json(&json!({}))
         .send()?
         .error_for_status()
         .map(|_| ())
 }
 
-pub fn player_connected(player_token: String) -> reqwest::Result<()> {
+/// What Rivet knows about a player that connected
+#[derive(Debug, Deserialize)]
+struct PlayerConnectedResponse {
+    /// The identity the player token was issued to; guests don't have one
+    #[serde(default)]
+    identity_id: Option<String>,
+}
+
+/// Validates the player token and returns the identity it was issued to. The identity is the only
+/// one that can be trusted for lifetime stats, since clients could claim to be anyone.
+pub fn player_connected(token_var: &str, player_token: String) -> reqwest::Result<Option<String>> {
     println!("rivet::player_connected");
 
     let client = reqwest::blocking::Client::new();
-    client
+    let body = client
         .post(format!("{}/matchmaker/players/connected", endpoint()))
-        .bearer_auth(token())
+        .bearer_auth(token(token_var))
         .json(&json!({ "player_token": player_token }))
         .send()?
-        .error_for_status()
-        .map(|_| ())
+        .error_for_status()?
+        .text()?;
+
+    // The token was accepted, so the player can still play without an identity; they just won't
+    // get lifetime stats, which is logged so it doesn't go unnoticed
+    match serde_json::from_str::<PlayerConnectedResponse>(&body) {
+        Ok(PlayerConnectedResponse {
+            identity_id: Some(identity_id),
+        }) => Ok(Some(identity_id)),
+        Ok(PlayerConnectedResponse { identity_id: None }) => {
+            println!("No identity for connected player, lifetime stats won't be saved");
+            Ok(None)
+        }
+        Err(err) => {
+            println!(
+                "Failed to parse connected player response {:?}: {}",
+                body, err
+            );
+            Ok(None)
+        }
+    }
 }
 
-pub fn player_disconnected(player_token: String) -> reqwest::Result<()> {
+pub fn player_disconnected(token_var: &str, player_token: String) -> reqwest::Result<()> {
     println!("rivet::player_disconnected");
 
     let client = reqwest::blocking::Client::new();
     client
         .post(format!("{}/matchmaker/players/disconnected", endpoint()))
-        .bearer_auth(token())
+        .bearer_auth(token(token_var))
         .json(&json!({ "player_token": player_token }))
         .send()?
         .error_for_status()
@@ -0,0 +1,280 @@
+use crate::abilities::{AbilitySet, Decoy};
+use crate::entities::{EntityId, ScoreCounter};
+use crate::game::GameState;
+use crate::game_config::ObjectConfigHandle;
+use crate::lobbies::LobbySettings;
+use crate::network::ClientId;
+use crate::teams::TeamHistory;
+use crate::utils::{FloatType, Rect, Vector};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::fs;
+use std::io;
+
+/// Bumped whenever the snapshot format changes; snapshots from other versions can't be restored.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/*** Errors ***/
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Version(u32),
+    MissingPrefab(String),
+    MissingMap(String),
+}
+
+impl Display for SnapshotError {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match *self {
+            SnapshotError::Io(ref err) => write!(f, "{}", err),
+            SnapshotError::Json(ref err) => write!(f, "{}", err),
+            SnapshotError::Version(version) => write!(
+                f,
+                "snapshot version {} can't be restored by version {}",
+                version, SNAPSHOT_VERSION
+            ),
+            SnapshotError::MissingPrefab(ref id) => write!(f, "missing prefab {}", id),
+            SnapshotError::MissingMap(ref id) => write!(f, "missing map {}", id),
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(err: io::Error) -> SnapshotError {
+        SnapshotError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for SnapshotError {
+    fn from(err: serde_json::Error) -> SnapshotError {
+        SnapshotError::Json(err)
+    }
+}
+
+/*** Game ***/
+/// Everything needed to recreate a running game: the world, the clients and the round. Sockets
+/// can't be saved, so players reconnect and are matched back to their entities by the reclaim
+/// secret their client sends in `Auth`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct GameSnapshot {
+    pub version: u32,
+    pub lobby_id: String,
+    pub taken_at: u64, // Milliseconds since the epoch
+
+    pub state: GameState,
+    pub state_timer: f64,
+    pub round_elapsed: u64, // Milliseconds since the round started
+    pub rounds_played: u64,
+    pub settings: LobbySettings,
+    #[serde(default)]
+    pub map_id: Option<String>, // The map in the world; the map in `settings` if missing
+    pub map_seed: Option<u64>,
+    pub next_map_seed: Option<u64>,
+
+    pub entities: Vec<EntitySnapshot>,
+    pub clients: Vec<ClientSnapshot>,
+    pub decoys: Vec<Decoy>,
+}
+
+impl GameSnapshot {
+    pub fn to_json(&self) -> Result<String, SnapshotError> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    /// Parses a snapshot and makes sure this version of the server can restore it.
+    pub fn from_json(json: &str) -> Result<GameSnapshot, SnapshotError> {
+        // Check the version first, since the rest of the format may have changed
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+        let version = serde_json::from_str::<Version>(json)?.version;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(version));
+        }
+
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), SnapshotError> {
+        Ok(fs::write(path, self.to_json()?)?)
+    }
+
+    pub fn load(path: &str) -> Result<GameSnapshot, SnapshotError> {
+        GameSnapshot::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+/*** Clients ***/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ClientSnapshot {
+    pub id: ClientId,
+    pub is_bot: bool,
+    pub is_admin: bool,
+    pub is_muted: bool,
+    pub username: Option<String>,
+    pub rivet_identity_id: Option<String>,
+    #[serde(default)]
+    pub reclaim_secret: Option<String>, // Missing from snapshots taken before secrets were issued
+    pub player_id: Option<EntityId>,
+    pub spectating_id: Option<EntityId>,
+}
+
+/*** Entities ***/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EntitySnapshot {
+    pub id: EntityId,
+    pub kind: EntityKindSnapshot,
+    pub asset: String,
+    pub rects: Vec<Rect>,
+    pub position: Vector,
+    pub velocity: Vector,
+    pub rotation: FloatType,
+    pub is_sleeping: bool,
+    pub dir: f64,
+    pub uses_dir: bool,
+    pub health: f64,
+    pub label: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum EntityKindSnapshot {
+    Player(Box<PlayerSnapshot>),
+    #[serde(rename_all = "camelCase")]
+    Prop {
+        object: ObjectConfigHandle,
+        prefab_id: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PlayerSnapshot {
+    pub username: String,
+    pub character_id: String,
+    pub party: Option<String>,
+    pub is_admin: bool,
+    pub state: PlayerStateSnapshot,
+    pub score_counter: ScoreCounter,
+    pub abilities: AbilitySet,
+    pub team_history: TeamHistory,
+    pub stamina: f64,
+    pub spawn_pos: Vector,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "team", rename_all = "camelCase")]
+pub enum PlayerStateSnapshot {
+    #[serde(rename_all = "camelCase")]
+    Hunter { last_shoot_time: u64 },
+    #[serde(rename_all = "camelCase")]
+    Prop {
+        last_ping_time: u64,
+        next_ping_time: u64,
+        disguise: Option<String>, // Prefab id
+    },
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::*;
+    use crate::entities::{Entity, EntityKind, Player, PlayerState, PropState};
+    use crate::game::Game;
+    use crate::game_config::{LobbyConfig, LobbyOverrides, GAME_CONFIG};
+    use std::sync::mpsc::channel;
+
+    /// A game in the test lobby.
+    pub fn game() -> Game {
+        let (_, rx_client_handle) = channel();
+        Game::new(
+            rx_client_handle,
+            LobbyConfig {
+                id: "test".to_string(),
+                token_var: "RIVET_TOKEN".to_string(),
+                custom: false,
+                overrides: LobbyOverrides::default(),
+                snapshot: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_version() {
+        let json = r#"{ "version": 0 }"#;
+        match GameSnapshot::from_json(json) {
+            Err(SnapshotError::Version(0)) => {}
+            other => panic!("expected a version error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_missing_map() {
+        let mut snapshot = game().snapshot();
+        assert_eq!(snapshot.map_id, Some(GAME_CONFIG.map_id.clone()));
+
+        // A snapshot from a server with other maps leaves the game as it was
+        snapshot.map_id = Some("missing".to_string());
+        let mut restored = game();
+        match restored.restore(snapshot) {
+            Err(SnapshotError::MissingMap(ref id)) if id == "missing" => {}
+            other => panic!("expected a missing map error, got {:?}", other),
+        }
+        assert_eq!(restored.snapshot().map_id, Some(GAME_CONFIG.map_id.clone()));
+    }
+
+    #[test]
+    fn test_mid_round() {
+        // Add a prop player to a snapshot of a new game
+        let mut snapshot = game().snapshot();
+        let mut player = Player::new("prop".to_string(), "basic".to_string(), None, false);
+        player.set_state(PlayerState::Prop(PropState::new()));
+        player.score_counter_mut().event(ScoreCounter::PING);
+        let player = Entity::new(EntityKind::Player(player)).snapshot();
+        let player_id = player.id;
+        snapshot.entities.push(player);
+        snapshot.clients.push(ClientSnapshot {
+            id: 0,
+            is_bot: false,
+            is_admin: false,
+            is_muted: false,
+            username: Some("prop".to_string()),
+            rivet_identity_id: None,
+            reclaim_secret: Some("secret".to_string()),
+            player_id: Some(player_id),
+            spectating_id: None,
+        });
+
+        // One prop left with 10 seconds to go
+        snapshot.state = GameState::Hunting;
+        snapshot.state_timer = 10.;
+        let snapshot = GameSnapshot::from_json(&snapshot.to_json().unwrap()).unwrap();
+
+        let mut restored = game();
+        restored.restore(snapshot.clone()).unwrap();
+        let restored = restored.snapshot();
+        assert_eq!(restored.state_timer, 10.);
+        assert_eq!(restored.entities.len(), snapshot.entities.len());
+
+        // The player is kept for when their client reconnects
+        let player = restored
+            .entities
+            .iter()
+            .find(|entity| entity.id == player_id)
+            .expect("restored player");
+        match player.kind {
+            EntityKindSnapshot::Player(ref player) => {
+                assert_eq!(player.score_counter.tally(ScoreCounter::PING).count, 1);
+                match player.state {
+                    PlayerStateSnapshot::Prop { .. } => {}
+                    _ => panic!("the player should still be a prop"),
+                }
+            }
+            _ => panic!("the player should still be a player"),
+        }
+    }
+}
@@ -0,0 +1,559 @@
+use crate::game_config::StatsStoreConfig;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::fmt;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/*** Records ***/
+/// The results of a single round.
+#[derive(Debug, Clone)]
+pub struct MatchRecord {
+    pub map: String,
+    pub map_seed: Option<u64>,
+    pub started_at: u64, // Milliseconds since the epoch
+    pub duration: f64,   // Seconds
+    pub props_win: bool,
+    pub players: Vec<PlayerMatchRecord>,
+}
+
+/// How a single player did in a round.
+#[derive(Debug, Clone)]
+pub struct PlayerMatchRecord {
+    /// The player's Rivet identity; players without an identity are only kept in the match
+    /// history and don't get lifetime stats
+    pub identity_id: Option<String>,
+    pub username: String,
+    pub character_id: String,
+    pub is_prop: bool,
+    pub placement: u64,
+    pub score: u64,
+    pub stats: PlayerStats,
+
+    /// The rows of the breakdown sent to the client as (label, value, score)
+    pub score_breakdown: Vec<(String, String, u64)>,
+}
+
+impl PlayerMatchRecord {
+    pub fn won(&self, props_win: bool) -> bool {
+        self.is_prop == props_win
+    }
+}
+
+/// The raw counters from a player's `ScoreCounter`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PlayerStats {
+    /* Hunters */
+    pub killed_players: u64,
+    pub hit_shots: u64,
+    pub missed_shots: u64,
+
+    /* Props */
+    pub pings: u64,
+    pub total_ping_volume: f64,
+    pub sleeping_time: f64,
+}
+
+/// A player's stats summed over every round they've played.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LifetimeStats {
+    pub identity_id: String,
+    pub username: String, // The most recent username
+    pub rounds: u64,
+    pub wins: u64,
+    pub prop_rounds: u64,
+    pub hunter_rounds: u64,
+    pub total_score: u64,
+    pub best_score: u64,
+    pub stats: PlayerStats,
+}
+
+/*** Errors ***/
+#[derive(Debug)]
+pub enum StatsStoreError {
+    Sqlite(rusqlite::Error),
+    Json(serde_json::Error),
+
+    /// The background writer thread stopped, so matches can't be saved
+    WriterStopped,
+}
+
+impl fmt::Display for StatsStoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatsStoreError::Sqlite(err) => write!(f, "SQLite error: {}", err),
+            StatsStoreError::Json(err) => write!(f, "JSON error: {}", err),
+            StatsStoreError::WriterStopped => write!(f, "stats writer thread stopped"),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StatsStoreError {
+    fn from(err: rusqlite::Error) -> StatsStoreError {
+        StatsStoreError::Sqlite(err)
+    }
+}
+
+impl From<serde_json::Error> for StatsStoreError {
+    fn from(err: serde_json::Error) -> StatsStoreError {
+        StatsStoreError::Json(err)
+    }
+}
+
+/*** Store ***/
+/// Persists match history and lifetime player stats.
+pub trait StatsStore: Send {
+    /// Saves a finished round and adds it to the lifetime stats of every player in it.
+    fn record_match(&mut self, record: &MatchRecord) -> Result<(), StatsStoreError>;
+
+    /// Returns the lifetime stats for a Rivet identity, if they've played a round.
+    fn lifetime_stats(&self, identity_id: &str) -> Result<Option<LifetimeStats>, StatsStoreError>;
+
+    /// Returns the players with the highest total score.
+    fn leaderboard(&self, limit: usize) -> Result<Vec<LifetimeStats>, StatsStoreError>;
+}
+
+/// Opens the store described by the config.
+pub fn open_stats_store(config: &StatsStoreConfig) -> Result<Box<dyn StatsStore>, StatsStoreError> {
+    match config {
+        StatsStoreConfig::None => Ok(Box::new(NullStatsStore)),
+        StatsStoreConfig::Sqlite { path } => Ok(Box::new(BackgroundStatsStore::new(
+            Box::new(SqliteStatsStore::open(path)?),
+            Box::new(SqliteStatsStore::open(path)?),
+        ))),
+    }
+}
+
+/* Background */
+/// Saves matches on a writer thread so writing to the store never stalls the game loop. Matches
+/// are written one at a time in the order they finished. Lookups run on the caller's thread
+/// against their own store, so they don't wait behind a write.
+pub struct BackgroundStatsStore {
+    records: Option<Sender<MatchRecord>>,
+    writer: Option<JoinHandle<()>>,
+    reader: Box<dyn StatsStore>,
+}
+
+impl BackgroundStatsStore {
+    pub fn new(
+        mut writer: Box<dyn StatsStore>,
+        reader: Box<dyn StatsStore>,
+    ) -> BackgroundStatsStore {
+        let (records, rx_records) = channel::<MatchRecord>();
+        let writer = thread::spawn(move || {
+            // Runs until the store is dropped
+            for record in rx_records {
+                if let Err(err) = writer.record_match(&record) {
+                    println!("Failed to save match record: {}", err);
+                }
+            }
+        });
+
+        BackgroundStatsStore {
+            records: Some(records),
+            writer: Some(writer),
+            reader,
+        }
+    }
+}
+
+impl StatsStore for BackgroundStatsStore {
+    fn record_match(&mut self, record: &MatchRecord) -> Result<(), StatsStoreError> {
+        match self.records {
+            Some(ref records) => records
+                .send(record.clone())
+                .map_err(|_| StatsStoreError::WriterStopped),
+            None => Err(StatsStoreError::WriterStopped),
+        }
+    }
+
+    fn lifetime_stats(&self, identity_id: &str) -> Result<Option<LifetimeStats>, StatsStoreError> {
+        self.reader.lifetime_stats(identity_id)
+    }
+
+    fn leaderboard(&self, limit: usize) -> Result<Vec<LifetimeStats>, StatsStoreError> {
+        self.reader.leaderboard(limit)
+    }
+}
+
+impl Drop for BackgroundStatsStore {
+    fn drop(&mut self) {
+        // Close the channel and wait for the writes that are still queued
+        self.records = None;
+        if let Some(writer) = self.writer.take() {
+            if writer.join().is_err() {
+                println!("Stats writer thread panicked");
+            }
+        }
+    }
+}
+
+/* Null */
+/// Store that doesn't save anything.
+pub struct NullStatsStore;
+
+impl StatsStore for NullStatsStore {
+    fn record_match(&mut self, _record: &MatchRecord) -> Result<(), StatsStoreError> {
+        Ok(())
+    }
+
+    fn lifetime_stats(&self, _identity_id: &str) -> Result<Option<LifetimeStats>, StatsStoreError> {
+        Ok(None)
+    }
+
+    fn leaderboard(&self, _limit: usize) -> Result<Vec<LifetimeStats>, StatsStoreError> {
+        Ok(Vec::new())
+    }
+}
+
+/* SQLite */
+/// Store that saves to a local SQLite database.
+pub struct SqliteStatsStore {
+    connection: Connection,
+}
+
+impl SqliteStatsStore {
+    const SCHEMA: &'static str = "
+        CREATE TABLE IF NOT EXISTS matches (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            map TEXT NOT NULL,
+            map_seed INTEGER,
+            started_at INTEGER NOT NULL,
+            duration REAL NOT NULL,
+            props_win INTEGER NOT NULL
+        );
+
+        CREATE TABLE IF NOT EXISTS match_players (
+            match_id INTEGER NOT NULL REFERENCES matches(id),
+            identity_id TEXT,
+            username TEXT NOT NULL,
+            character_id TEXT NOT NULL,
+            is_prop INTEGER NOT NULL,
+            placement INTEGER NOT NULL,
+            score INTEGER NOT NULL,
+            killed_players INTEGER NOT NULL,
+            hit_shots INTEGER NOT NULL,
+            missed_shots INTEGER NOT NULL,
+            pings INTEGER NOT NULL,
+            total_ping_volume REAL NOT NULL,
+            sleeping_time REAL NOT NULL,
+            score_breakdown TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS match_players_identity ON match_players(identity_id);
+
+        CREATE TABLE IF NOT EXISTS player_stats (
+            identity_id TEXT PRIMARY KEY,
+            username TEXT NOT NULL,
+            rounds INTEGER NOT NULL,
+            wins INTEGER NOT NULL,
+            prop_rounds INTEGER NOT NULL,
+            hunter_rounds INTEGER NOT NULL,
+            total_score INTEGER NOT NULL,
+            best_score INTEGER NOT NULL,
+            killed_players INTEGER NOT NULL,
+            hit_shots INTEGER NOT NULL,
+            missed_shots INTEGER NOT NULL,
+            pings INTEGER NOT NULL,
+            total_ping_volume REAL NOT NULL,
+            sleeping_time REAL NOT NULL
+        );
+    ";
+
+    const PLAYER_STATS_COLUMNS: &'static str = "identity_id, username, rounds, wins, prop_rounds, \
+        hunter_rounds, total_score, best_score, killed_players, hit_shots, missed_shots, pings, \
+        total_ping_volume, sleeping_time";
+
+    /// How long to wait for another lobby's write to finish; every lobby opens the same database
+    const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Opens or creates the database at the path; use `:memory:` for a database that isn't saved.
+    pub fn open(path: &str) -> Result<SqliteStatsStore, StatsStoreError> {
+        let connection = Connection::open(path)?;
+        connection.busy_timeout(SqliteStatsStore::BUSY_TIMEOUT)?;
+        connection.execute_batch(SqliteStatsStore::SCHEMA)?;
+        Ok(SqliteStatsStore { connection })
+    }
+
+    fn lifetime_stats_from_row(row: &Row) -> rusqlite::Result<LifetimeStats> {
+        Ok(LifetimeStats {
+            identity_id: row.get(0)?,
+            username: row.get(1)?,
+            rounds: row.get(2)?,
+            wins: row.get(3)?,
+            prop_rounds: row.get(4)?,
+            hunter_rounds: row.get(5)?,
+            total_score: row.get(6)?,
+            best_score: row.get(7)?,
+            stats: PlayerStats {
+                killed_players: row.get(8)?,
+                hit_shots: row.get(9)?,
+                missed_shots: row.get(10)?,
+                pings: row.get(11)?,
+                total_ping_volume: row.get(12)?,
+                sleeping_time: row.get(13)?,
+            },
+        })
+    }
+}
+
+impl StatsStore for SqliteStatsStore {
+    fn record_match(&mut self, record: &MatchRecord) -> Result<(), StatsStoreError> {
+        measure!("Record match");
+
+        let transaction = self.connection.transaction()?;
+
+        // Save the match; SQLite integers are signed, so the seed is stored as its bit pattern
+        transaction.execute(
+            "INSERT INTO matches (map, map_seed, started_at, duration, props_win)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                record.map,
+                record.map_seed.map(|seed| seed as i64),
+                record.started_at,
+                record.duration,
+                record.props_win
+            ],
+        )?;
+        let match_id = transaction.last_insert_rowid();
+
+        for player in record.players.iter() {
+            let stats = &player.stats;
+
+            // Save the player's results for the match
+            transaction.execute(
+                "INSERT INTO match_players (match_id, identity_id, username, character_id,
+                     is_prop, placement, score, killed_players, hit_shots, missed_shots, pings,
+                     total_ping_volume, sleeping_time, score_breakdown)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+                params![
+                    match_id,
+                    player.identity_id,
+                    player.username,
+                    player.character_id,
+                    player.is_prop,
+                    player.placement,
+                    player.score,
+                    stats.killed_players,
+                    stats.hit_shots,
+                    stats.missed_shots,
+                    stats.pings,
+                    stats.total_ping_volume,
+                    stats.sleeping_time,
+                    serde_json::to_string(&player.score_breakdown)?
+                ],
+            )?;
+
+            // Add the match to the lifetime stats
+            let identity_id = match player.identity_id {
+                Some(ref id) => id,
+                None => continue,
+            };
+            transaction.execute(
+                &format!(
+                    "INSERT INTO player_stats ({columns})
+                     VALUES (?1, ?2, 1, ?3, ?4, ?5, ?6, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+                     ON CONFLICT(identity_id) DO UPDATE SET
+                         username = excluded.username,
+                         rounds = rounds + 1,
+                         wins = wins + excluded.wins,
+                         prop_rounds = prop_rounds + excluded.prop_rounds,
+                         hunter_rounds = hunter_rounds + excluded.hunter_rounds,
+                         total_score = total_score + excluded.total_score,
+                         best_score = MAX(best_score, excluded.best_score),
+                         killed_players = killed_players + excluded.killed_players,
+                         hit_shots = hit_shots + excluded.hit_shots,
+                         missed_shots = missed_shots + excluded.missed_shots,
+                         pings = pings + excluded.pings,
+                         total_ping_volume = total_ping_volume + excluded.total_ping_volume,
+                         sleeping_time = sleeping_time + excluded.sleeping_time",
+                    columns = SqliteStatsStore::PLAYER_STATS_COLUMNS
+                ),
+                params![
+                    identity_id,
+                    player.username,
+                    player.won(record.props_win) as u64,
+                    player.is_prop as u64,
+                    !player.is_prop as u64,
+                    player.score,
+                    stats.killed_players,
+                    stats.hit_shots,
+                    stats.missed_shots,
+                    stats.pings,
+                    stats.total_ping_volume,
+                    stats.sleeping_time
+                ],
+            )?;
+        }
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn lifetime_stats(&self, identity_id: &str) -> Result<Option<LifetimeStats>, StatsStoreError> {
+        let stats = self
+            .connection
+            .query_row(
+                &format!(
+                    "SELECT {} FROM player_stats WHERE identity_id = ?1",
+                    SqliteStatsStore::PLAYER_STATS_COLUMNS
+                ),
+                params![identity_id],
+                SqliteStatsStore::lifetime_stats_from_row,
+            )
+            .optional()?;
+        Ok(stats)
+    }
+
+    fn leaderboard(&self, limit: usize) -> Result<Vec<LifetimeStats>, StatsStoreError> {
+        let mut statement = self.connection.prepare(&format!(
+            "SELECT {} FROM player_stats ORDER BY total_score DESC LIMIT ?1",
+            SqliteStatsStore::PLAYER_STATS_COLUMNS
+        ))?;
+        let rows = statement.query_map(
+            params![limit as u64],
+            SqliteStatsStore::lifetime_stats_from_row,
+        )?;
+        let mut leaderboard = Vec::new();
+        for row in rows {
+            leaderboard.push(row?);
+        }
+        Ok(leaderboard)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn player(identity_id: Option<&str>, is_prop: bool, score: u64) -> PlayerMatchRecord {
+        PlayerMatchRecord {
+            identity_id: identity_id.map(|id| id.to_string()),
+            username: format!("{:?}", identity_id),
+            character_id: "basic".to_string(),
+            is_prop,
+            placement: 0,
+            score,
+            stats: PlayerStats {
+                killed_players: 1,
+                hit_shots: 2,
+                missed_shots: 3,
+                pings: 4,
+                total_ping_volume: 5.,
+                sleeping_time: 6.,
+            },
+            score_breakdown: vec![("totalScoring".to_string(), "".to_string(), score)],
+        }
+    }
+
+    fn record(props_win: bool, players: Vec<PlayerMatchRecord>) -> MatchRecord {
+        MatchRecord {
+            map: "map-a".to_string(),
+            map_seed: None,
+            started_at: 0,
+            duration: 60.,
+            props_win,
+            players,
+        }
+    }
+
+    #[test]
+    fn test_lifetime_stats() {
+        let mut store = SqliteStatsStore::open(":memory:").unwrap();
+        store
+            .record_match(&record(
+                true,
+                vec![player(Some("a"), true, 100), player(Some("b"), false, 50)],
+            ))
+            .unwrap();
+        store
+            .record_match(&record(
+                true,
+                vec![player(Some("a"), false, 30), player(None, true, 500)],
+            ))
+            .unwrap();
+
+        let a = store.lifetime_stats("a").unwrap().unwrap();
+        assert_eq!(a.rounds, 2);
+        assert_eq!(a.wins, 1);
+        assert_eq!(a.prop_rounds, 1);
+        assert_eq!(a.hunter_rounds, 1);
+        assert_eq!(a.total_score, 130);
+        assert_eq!(a.best_score, 100);
+        assert_eq!(a.stats.killed_players, 2);
+        assert_eq!(a.stats.sleeping_time, 12.);
+        assert!(store.lifetime_stats("c").unwrap().is_none());
+
+        // Players without an identity are left off the leaderboard
+        let leaderboard = store.leaderboard(10).unwrap();
+        assert_eq!(
+            leaderboard
+                .iter()
+                .map(|s| s.identity_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+    }
+
+    #[test]
+    fn test_background_store() {
+        // The writer and the reader need separate connections to the same database
+        let path = std::env::temp_dir().join(format!("pixelprop-stats-{}.db", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut store = BackgroundStatsStore::new(
+            Box::new(SqliteStatsStore::open(path).unwrap()),
+            Box::new(SqliteStatsStore::open(path).unwrap()),
+        );
+        for username in ["first", "second"].iter() {
+            let mut player = player(Some("a"), true, 10);
+            player.username = username.to_string();
+            store.record_match(&record(true, vec![player])).unwrap();
+        }
+
+        // Dropping the store waits for the queued writes, which are saved in order
+        drop(store);
+        let stats = SqliteStatsStore::open(path)
+            .unwrap()
+            .lifetime_stats("a")
+            .unwrap()
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(stats.rounds, 2);
+        assert_eq!(stats.username, "second");
+    }
+
+    #[test]
+    fn test_match_history() {
+        let mut store = SqliteStatsStore::open(":memory:").unwrap();
+        store
+            .record_match(&record(false, vec![player(None, true, 42)]))
+            .unwrap();
+
+        let (map, props_win, breakdown): (String, bool, String) = store
+            .connection
+            .query_row(
+                "SELECT map, props_win, score_breakdown FROM matches
+                 JOIN match_players ON match_players.match_id = matches.id",
+                params![],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(map, "map-a");
+        assert!(!props_win);
+        assert_eq!(breakdown, r#"[["totalScoring","",42]]"#);
+    }
+
+    #[test]
+    fn test_large_map_seed() {
+        let mut store = SqliteStatsStore::open(":memory:").unwrap();
+        let mut record = record(true, vec![player(Some("a"), true, 10)]);
+        record.map_seed = Some(u64::MAX);
+        store.record_match(&record).unwrap();
+
+        let seed: i64 = store
+            .connection
+            .query_row("SELECT map_seed FROM matches", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(seed as u64, u64::MAX);
+        assert!(store.lifetime_stats("a").unwrap().is_some());
+    }
+}
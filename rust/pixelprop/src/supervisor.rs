@@ -0,0 +1,260 @@
+use crate::entities::EntityId;
+use crate::network::ClientId;
+use crate::snapshot::GameSnapshot;
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::thread;
+
+/*** Phases ***/
+thread_local! {
+    /// The `measure!` labels the update is currently inside of
+    static PHASES: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+
+    /// The phases the last panic happened in
+    static PANIC_PHASE: RefCell<Option<String>> = const { RefCell::new(None) };
+
+    /// What the update was working on when it panicked
+    static SUSPECT: Cell<Option<Suspect>> = const { Cell::new(None) };
+}
+
+/// Keeps track of the phase of the update while it's alive. Created by `measure!`.
+pub struct PhaseGuard;
+
+impl PhaseGuard {
+    pub fn enter(label: &'static str) -> PhaseGuard {
+        PHASES.with(|phases| phases.borrow_mut().push(label));
+        PhaseGuard
+    }
+}
+
+impl Drop for PhaseGuard {
+    fn drop(&mut self) {
+        PHASES.with(|phases| {
+            let mut phases = phases.borrow_mut();
+
+            // The innermost guard is dropped first while unwinding, so it has the full path
+            if thread::panicking() {
+                PANIC_PHASE.with(|panic_phase| {
+                    let mut panic_phase = panic_phase.borrow_mut();
+                    if panic_phase.is_none() {
+                        *panic_phase = Some(phases.join(" > "));
+                    }
+                });
+            }
+
+            phases.pop();
+        });
+    }
+}
+
+/// Returns the phases the last panic happened in and forgets them.
+pub fn take_panic_phase() -> Option<String> {
+    PANIC_PHASE.with(|panic_phase| panic_phase.borrow_mut().take())
+}
+
+/*** Suspects ***/
+/// Something the update was working on that may have caused a panic.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Suspect {
+    Client(ClientId),
+    Entity(EntityId),
+}
+
+impl Suspect {
+    /// Marks this as the suspect until the guard is dropped. If the update panics, the suspect
+    /// is kept so it can be quarantined.
+    pub fn watch(self) -> SuspectGuard {
+        SuspectGuard {
+            previous: SUSPECT.with(|suspect| suspect.replace(Some(self))),
+        }
+    }
+}
+
+pub struct SuspectGuard {
+    previous: Option<Suspect>,
+}
+
+impl Drop for SuspectGuard {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            SUSPECT.with(|suspect| suspect.set(self.previous));
+        }
+    }
+}
+
+/// Returns what the update was working on when it panicked and forgets it.
+pub fn take_suspect() -> Option<Suspect> {
+    SUSPECT.with(|suspect| suspect.take())
+}
+
+/// Reads the message a panic was started with.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.as_str()
+    } else {
+        "unknown panic"
+    }
+}
+
+/*** Supervisor ***/
+/// How the game recovers from a panicked update.
+#[derive(Debug, PartialEq)]
+pub enum Recovery {
+    /// Remove the suspect and keep going
+    Quarantine,
+
+    /// Go back to the last good snapshot
+    Restore,
+
+    /// Start a new round on a new map
+    Reset,
+}
+
+/// Keeps the last snapshot of the game that updated cleanly and decides how to recover when an
+/// update panics.
+pub struct Supervisor {
+    /// The last snapshot taken after a clean update
+    last_good: Option<GameSnapshot>,
+
+    /// Number of clean updates since the last snapshot
+    updates_since_snapshot: u64,
+
+    /// Number of updates in a row that panicked
+    consecutive_panics: u64,
+}
+
+impl Supervisor {
+    /// Number of clean updates between snapshots (5 seconds).
+    pub const SNAPSHOT_INTERVAL: u64 = 75;
+
+    /// Number of updates in a row that can panic before the game is restored.
+    pub const MAX_CONSECUTIVE_PANICS: u64 = 3;
+
+    pub fn new() -> Supervisor {
+        Supervisor {
+            last_good: None,
+            updates_since_snapshot: 0,
+            consecutive_panics: 0,
+        }
+    }
+
+    /// Called after an update that didn't panic. Returns true if a snapshot should be taken.
+    pub fn update_succeeded(&mut self) -> bool {
+        self.consecutive_panics = 0;
+        self.updates_since_snapshot += 1;
+        self.last_good.is_none() || self.updates_since_snapshot >= Supervisor::SNAPSHOT_INTERVAL
+    }
+
+    pub fn save(&mut self, snapshot: GameSnapshot) {
+        self.last_good = Some(snapshot);
+        self.updates_since_snapshot = 0;
+    }
+
+    pub fn last_good(&self) -> Option<&GameSnapshot> {
+        self.last_good.as_ref()
+    }
+
+    /// Picks how to recover from a panicked update. Quarantining the suspect is enough unless
+    /// the world is broken, nothing was suspected, or the game keeps panicking anyway.
+    pub fn update_panicked(
+        &mut self,
+        suspect: Option<Suspect>,
+        world_consistent: bool,
+    ) -> Recovery {
+        self.consecutive_panics += 1;
+
+        // The snapshot itself may be the problem
+        if self.consecutive_panics > Supervisor::MAX_CONSECUTIVE_PANICS * 2 {
+            self.last_good = None;
+            self.consecutive_panics = 0;
+            return Recovery::Reset;
+        }
+
+        if world_consistent
+            && suspect.is_some()
+            && self.consecutive_panics <= Supervisor::MAX_CONSECUTIVE_PANICS
+        {
+            Recovery::Quarantine
+        } else if self.last_good.is_some() {
+            Recovery::Restore
+        } else {
+            Recovery::Reset
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::panic;
+
+    #[test]
+    fn test_panic_phase() {
+        let result = panic::catch_unwind(|| {
+            let _outer = PhaseGuard::enter("Update");
+            let _suspect = Suspect::Client(4).watch();
+            {
+                let _inner = PhaseGuard::enter("Send updates");
+            }
+            let _inner = PhaseGuard::enter("Update players");
+            panic!("broken client");
+        });
+        let payload = result.expect_err("the update should panic");
+
+        assert_eq!(panic_message(&*payload), "broken client");
+        assert_eq!(
+            take_panic_phase(),
+            Some("Update > Update players".to_string())
+        );
+        assert_eq!(take_suspect(), Some(Suspect::Client(4)));
+
+        // Both are cleared once read
+        assert_eq!(take_panic_phase(), None);
+        assert_eq!(take_suspect(), None);
+    }
+
+    #[test]
+    fn test_suspect_cleared() {
+        {
+            let _client = Suspect::Client(1).watch();
+            {
+                let _entity = Suspect::Entity(2).watch();
+            }
+        }
+        assert_eq!(take_suspect(), None);
+    }
+
+    #[test]
+    fn test_recovery() {
+        let mut supervisor = Supervisor::new();
+
+        // Nothing to go back to yet
+        assert_eq!(supervisor.update_panicked(None, true), Recovery::Reset);
+        assert!(supervisor.update_succeeded());
+        supervisor.save(crate::snapshot::test::game().snapshot());
+        assert!(!supervisor.update_succeeded());
+
+        let suspect = Some(Suspect::Entity(3));
+        assert_eq!(
+            supervisor.update_panicked(suspect, true),
+            Recovery::Quarantine
+        );
+        assert_eq!(
+            supervisor.update_panicked(suspect, false),
+            Recovery::Restore
+        );
+        assert_eq!(supervisor.update_panicked(None, true), Recovery::Restore);
+
+        // Keeps panicking after quarantining
+        assert_eq!(supervisor.update_panicked(suspect, true), Recovery::Restore);
+
+        // Keeps panicking after restoring
+        for _ in 0..2 {
+            assert_eq!(supervisor.update_panicked(suspect, true), Recovery::Restore);
+        }
+        assert_eq!(supervisor.update_panicked(suspect, true), Recovery::Reset);
+        assert!(supervisor.last_good().is_none());
+    }
+}
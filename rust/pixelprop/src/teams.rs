@@ -0,0 +1,343 @@
+use crate::game_config::{TeamConfig, TeamStrategy, GAME_CONFIG};
+use crate::network::ClientId;
+use rand::{self, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/*** History ***/
+/// What the team assigner knows about a player's past rounds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeamHistory {
+    /// Rounds since the player was last picked as a hunter; `None` if they've never been picked
+    rounds_since_hunter: Option<u64>,
+
+    /// Scores from the player's most recent rounds, newest last
+    recent_scores: VecDeque<u64>,
+}
+
+impl TeamHistory {
+    pub fn new() -> TeamHistory {
+        TeamHistory {
+            rounds_since_hunter: None,
+            recent_scores: VecDeque::new(),
+        }
+    }
+
+    /// Called when the teams are picked at the start of a round.
+    pub fn assigned(&mut self, is_hunter: bool) {
+        self.rounds_since_hunter = if is_hunter {
+            Some(0)
+        } else {
+            Some(self.rounds_since_hunter.map_or(1, |r| r + 1))
+        };
+    }
+
+    /// Called with the player's `ScoreCounter` total at the end of a round.
+    pub fn record_score(&mut self, score: u64) {
+        self.recent_scores.push_back(score);
+        while self.recent_scores.len() > GAME_CONFIG.teams.skill_history {
+            self.recent_scores.pop_front();
+        }
+    }
+
+    /// The player's average score over their recent rounds.
+    pub fn skill(&self) -> Option<f64> {
+        if self.recent_scores.is_empty() {
+            None
+        } else {
+            let total = self.recent_scores.iter().sum::<u64>();
+            Some(total as f64 / self.recent_scores.len() as f64)
+        }
+    }
+
+    /// How overdue the player is to be a hunter; players who have never hunted go first.
+    fn hunter_priority(&self) -> u64 {
+        self.rounds_since_hunter.unwrap_or(u64::MAX)
+    }
+}
+
+/*** Assignment ***/
+/// A player that is being put on a team.
+#[derive(Debug, Clone)]
+pub struct TeamCandidate {
+    pub client_id: ClientId,
+    pub history: TeamHistory,
+
+    /// Players with the same party are kept on the same team when possible
+    pub party: Option<String>,
+}
+
+/// Strategy that decides who hunts each round.
+pub trait TeamAssigner {
+    /// Returns the ids of the players who will be hunters.
+    fn assign_hunters(&self, candidates: &[TeamCandidate], hunter_count: usize) -> Vec<ClientId>;
+}
+
+/// Creates the assigner for the configured strategy.
+pub fn team_assigner(config: &TeamConfig) -> Box<dyn TeamAssigner> {
+    match config.strategy {
+        TeamStrategy::Random => Box::new(RandomAssigner),
+        TeamStrategy::Rotation => Box::new(RotationAssigner),
+        TeamStrategy::Balanced => Box::new(BalancedAssigner {
+            slack: config.balance_slack,
+        }),
+    }
+}
+
+/// Number of hunters for a round; at least two and one more for every five players.
+pub fn hunter_count(player_count: usize) -> usize {
+    usize::min(
+        usize::max(2, (player_count as f64 * 0.2) as usize + 1),
+        player_count,
+    )
+}
+
+/// Groups the candidates by party in a random order. Players without a party are on their own.
+fn party_units(candidates: &[TeamCandidate]) -> Vec<Vec<&TeamCandidate>> {
+    let mut units: Vec<Vec<&TeamCandidate>> = Vec::new();
+    for candidate in candidates.iter() {
+        let party_unit = candidate.party.as_ref().and_then(|party| {
+            units
+                .iter_mut()
+                .find(|unit| unit[0].party.as_ref() == Some(party))
+        });
+        match party_unit {
+            Some(unit) => unit.push(candidate),
+            None => units.push(vec![candidate]),
+        }
+    }
+    rand::thread_rng().shuffle(&mut units);
+    units
+}
+
+/// Sorts the units so the ones most overdue to hunt go first.
+fn sort_by_rotation(units: &mut Vec<Vec<&TeamCandidate>>) {
+    // Stable sort so ties keep their random order
+    units.sort_by_key(|unit| {
+        let priority = unit
+            .iter()
+            .map(|c| c.history.hunter_priority())
+            .max()
+            .unwrap_or(0);
+        u64::MAX - priority
+    });
+}
+
+/// Takes whole units in order until there are enough hunters. If the parties don't fit, then
+/// the remaining spots are filled by splitting them up.
+fn fill_hunters(units: &[Vec<&TeamCandidate>], hunter_count: usize) -> Vec<ClientId> {
+    let mut hunters = Vec::new();
+    let mut used = vec![false; units.len()];
+    for (i, unit) in units.iter().enumerate() {
+        if hunters.len() + unit.len() <= hunter_count {
+            hunters.extend(unit.iter().map(|c| c.client_id));
+            used[i] = true;
+        }
+    }
+
+    // Split up parties for the spots that are left
+    for (i, unit) in units.iter().enumerate() {
+        if used[i] {
+            continue;
+        }
+        for candidate in unit.iter() {
+            if hunters.len() >= hunter_count {
+                return hunters;
+            }
+            hunters.push(candidate.client_id);
+        }
+    }
+
+    hunters
+}
+
+/* Random */
+/// Picks hunters at random.
+pub struct RandomAssigner;
+
+impl TeamAssigner for RandomAssigner {
+    fn assign_hunters(&self, candidates: &[TeamCandidate], hunter_count: usize) -> Vec<ClientId> {
+        fill_hunters(&party_units(candidates), hunter_count)
+    }
+}
+
+/* Rotation */
+/// Picks the players who have gone the longest without hunting.
+pub struct RotationAssigner;
+
+impl TeamAssigner for RotationAssigner {
+    fn assign_hunters(&self, candidates: &[TeamCandidate], hunter_count: usize) -> Vec<ClientId> {
+        let mut units = party_units(candidates);
+        sort_by_rotation(&mut units);
+        fill_hunters(&units, hunter_count)
+    }
+}
+
+/* Balanced */
+/// Picks hunters from the players who are due to hunt so that the hunters' average skill is as
+/// close as possible to the average skill of everyone in the game.
+pub struct BalancedAssigner {
+    /// How many players past the ones due to hunt can also be picked
+    slack: usize,
+}
+
+impl BalancedAssigner {
+    /// Max number of units to pick from, since every combination of them is tried.
+    const MAX_POOL_SIZE: usize = 12;
+}
+
+impl TeamAssigner for BalancedAssigner {
+    fn assign_hunters(&self, candidates: &[TeamCandidate], hunter_count: usize) -> Vec<ClientId> {
+        let mut units = party_units(candidates);
+        sort_by_rotation(&mut units);
+
+        // Players without any rounds are rated as an average player
+        let skills = candidates
+            .iter()
+            .filter_map(|c| c.history.skill())
+            .collect::<Vec<_>>();
+        let average_skill = if skills.is_empty() {
+            0.
+        } else {
+            skills.iter().sum::<f64>() / skills.len() as f64
+        };
+        let unit_skill = |unit: &Vec<&TeamCandidate>| -> f64 {
+            unit.iter()
+                .map(|c| c.history.skill().unwrap_or(average_skill))
+                .sum()
+        };
+
+        // Pick from the units that are due to hunt and a few more
+        let mut pool_size = 0;
+        let mut pool_players = 0;
+        while pool_size < units.len()
+            && pool_size < BalancedAssigner::MAX_POOL_SIZE
+            && pool_players < hunter_count + self.slack
+        {
+            pool_players += units[pool_size].len();
+            pool_size += 1;
+        }
+
+        // Find the group of units with the closest skill to the average; ties go to the group
+        // with the units that are most overdue
+        let mut best: Option<(u64, f64, usize)> = None; // (mask, skill difference, rank)
+        for mask in 1u64..(1 << pool_size) {
+            let mut players = 0;
+            let mut skill = 0.;
+            let mut rank = 0;
+            for (i, unit) in units.iter().take(pool_size).enumerate() {
+                if mask & (1 << i) != 0 {
+                    players += unit.len();
+                    skill += unit_skill(unit);
+                    rank += i;
+                }
+            }
+            if players != hunter_count {
+                continue;
+            }
+
+            let difference = (skill / players as f64 - average_skill).abs();
+            let is_better = match best {
+                Some((_, best_difference, best_rank)) => {
+                    difference < best_difference - 0.001
+                        || (difference < best_difference + 0.001 && rank < best_rank)
+                }
+                None => true,
+            };
+            if is_better {
+                best = Some((mask, difference, rank));
+            }
+        }
+
+        match best {
+            Some((mask, _, _)) => units
+                .iter()
+                .take(pool_size)
+                .enumerate()
+                .filter(|&(i, _)| mask & (1 << i) != 0)
+                .flat_map(|(_, unit)| unit.iter().map(|c| c.client_id))
+                .collect(),
+            None => fill_hunters(&units, hunter_count), // The parties can't be kept together
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn candidate(
+        client_id: ClientId,
+        rounds_since_hunter: Option<u64>,
+        scores: &[u64],
+        party: Option<&str>,
+    ) -> TeamCandidate {
+        TeamCandidate {
+            client_id,
+            history: TeamHistory {
+                rounds_since_hunter,
+                recent_scores: scores.iter().cloned().collect(),
+            },
+            party: party.map(|p| p.to_string()),
+        }
+    }
+
+    fn sorted(mut ids: Vec<ClientId>) -> Vec<ClientId> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_hunter_count() {
+        assert_eq!(hunter_count(4), 2);
+        assert_eq!(hunter_count(9), 2);
+        assert_eq!(hunter_count(10), 3);
+        assert_eq!(hunter_count(20), 5);
+    }
+
+    #[test]
+    fn test_rotation() {
+        let candidates = vec![
+            candidate(0, Some(0), &[], None),
+            candidate(1, Some(3), &[], None),
+            candidate(2, None, &[], None),
+            candidate(3, Some(1), &[], None),
+        ];
+        let hunters = RotationAssigner.assign_hunters(&candidates, 2);
+        assert_eq!(sorted(hunters), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_parties_stay_together() {
+        let candidates = vec![
+            candidate(0, Some(5), &[], Some("a")),
+            candidate(1, Some(0), &[], Some("a")),
+            candidate(2, Some(1), &[], None),
+            candidate(3, Some(2), &[], None),
+            candidate(4, Some(0), &[], None),
+        ];
+        for _ in 0..10 {
+            let hunters = sorted(RotationAssigner.assign_hunters(&candidates, 2));
+            assert_eq!(hunters, vec![0, 1]);
+
+            let hunters = sorted(RandomAssigner.assign_hunters(&candidates, 2));
+            assert_eq!(hunters.contains(&0), hunters.contains(&1));
+        }
+    }
+
+    #[test]
+    fn test_balanced() {
+        // Everyone is due to hunt, so the balancer picks the pair closest to the average of 100
+        let candidates = vec![
+            candidate(0, None, &[200], None),
+            candidate(1, None, &[190], None),
+            candidate(2, None, &[10], None),
+            candidate(3, None, &[0], None),
+        ];
+        let assigner = BalancedAssigner { slack: 2 };
+        for _ in 0..10 {
+            let hunters = sorted(assigner.assign_hunters(&candidates, 2));
+            assert!(hunters == vec![0, 3] || hunters == vec![1, 2]);
+        }
+    }
+}
@@ -0,0 +1,867 @@
+use crate::entities::EntityKind;
+use crate::game_config::{BuildingConfig, GroupConfig, MapObjectKind, ObjectConfig, PrefabKind};
+use crate::game_world::GameWorld;
+use crate::utils::{normalize_angle, FloatType, Vector};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+/*
+Tiled (https://www.mapeditor.org) maps are converted with these rules:
+
+- 1 pixel in Tiled is 1 unit in the world. Tiled's y axis points down, so it's flipped.
+- The map's `originX` and `originY` float properties give the world position of Tiled's
+  (0, 0); these are written by the exporter so a map round-trips to the same place.
+- Object layers become groups. An object's prefab is its type (class), a `prefabId` property,
+  or the type of its tile. The prefab's origin is placed at the object's center and Tiled's
+  clockwise rotation becomes the yaw. `z` and `spawnChance` can be set as properties.
+- Tile layers become buildings. Tiles must be half the room size, so the layer is laid out
+  like `BuildingConfig::map`: the prefab's origin is placed at the center of the tile and
+  rotating the tile rotates the prefab.
+- Hidden layers are skipped and group layers become nested groups.
+
+Tile layers must use the CSV layer format and external tilesets must be saved as JSON.
+*/
+
+/*** Errors ***/
+#[derive(Debug)]
+pub enum TiledError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    InfiniteMap,
+    NonSquareTiles,
+    TooManyTileKinds,
+}
+
+impl Display for TiledError {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match *self {
+            TiledError::Io(ref err) => write!(f, "{}", err),
+            TiledError::Json(ref err) => write!(f, "{}", err),
+            ref err => write!(f, "{:?}", err),
+        }
+    }
+}
+
+impl From<io::Error> for TiledError {
+    fn from(err: io::Error) -> TiledError {
+        TiledError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for TiledError {
+    fn from(err: serde_json::Error) -> TiledError {
+        TiledError::Json(err)
+    }
+}
+
+/*** Tiled JSON format ***/
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TiledMap {
+    #[serde(rename = "type", default = "default_map_type")]
+    pub kind: String,
+    #[serde(default = "default_version")]
+    pub version: String,
+    #[serde(default = "default_orientation")]
+    pub orientation: String,
+    #[serde(default = "default_render_order")]
+    pub renderorder: String,
+    #[serde(default)]
+    pub infinite: bool,
+    pub width: u32,
+    pub height: u32,
+    pub tilewidth: u32,
+    pub tileheight: u32,
+    #[serde(default)]
+    pub nextlayerid: u32,
+    #[serde(default)]
+    pub nextobjectid: u32,
+    pub layers: Vec<TiledLayer>,
+    #[serde(default)]
+    pub tilesets: Vec<TiledTileset>,
+    #[serde(default)]
+    pub properties: Vec<TiledProperty>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type")]
+pub enum TiledLayer {
+    #[serde(rename = "tilelayer")]
+    Tiles {
+        #[serde(flatten)]
+        info: TiledLayerInfo,
+        width: u32,
+        height: u32,
+        data: Vec<u32>,
+    },
+    #[serde(rename = "objectgroup")]
+    Objects {
+        #[serde(flatten)]
+        info: TiledLayerInfo,
+        #[serde(default = "default_draw_order")]
+        draworder: String,
+        objects: Vec<TiledObject>,
+    },
+    #[serde(rename = "group")]
+    Group {
+        #[serde(flatten)]
+        info: TiledLayerInfo,
+        layers: Vec<TiledLayer>,
+    },
+    #[serde(rename = "imagelayer")]
+    Image {
+        #[serde(flatten)]
+        info: TiledLayerInfo,
+    },
+}
+
+impl TiledLayer {
+    fn info(&self) -> &TiledLayerInfo {
+        match *self {
+            TiledLayer::Tiles { ref info, .. }
+            | TiledLayer::Objects { ref info, .. }
+            | TiledLayer::Group { ref info, .. }
+            | TiledLayer::Image { ref info, .. } => info,
+        }
+    }
+}
+
+/// Properties shared by every kind of layer.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TiledLayerInfo {
+    pub id: u32,
+    pub name: String,
+    #[serde(default = "default_bool_true")]
+    pub visible: bool,
+    #[serde(default = "default_opacity")]
+    pub opacity: FloatType,
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+    #[serde(default)]
+    pub offsetx: FloatType,
+    #[serde(default)]
+    pub offsety: FloatType,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TiledObject {
+    pub id: u32,
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type", alias = "class", default)]
+    pub kind: String,
+    pub x: FloatType,
+    pub y: FloatType,
+    #[serde(default)]
+    pub width: FloatType,
+    #[serde(default)]
+    pub height: FloatType,
+    #[serde(default)]
+    pub rotation: FloatType, // Degrees clockwise
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub point: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gid: Option<u32>,
+    #[serde(default = "default_bool_true")]
+    pub visible: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub properties: Vec<TiledProperty>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TiledTileset {
+    #[serde(default)]
+    pub firstgid: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>, // External tileset, relative to the map
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub tiles: Vec<TiledTile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TiledTile {
+    pub id: u32,
+    #[serde(rename = "type", alias = "class", default)]
+    pub kind: String,
+    #[serde(default)]
+    pub properties: Vec<TiledProperty>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TiledProperty {
+    pub name: String,
+    #[serde(rename = "type", default = "default_property_type")]
+    pub kind: String,
+    pub value: serde_json::Value,
+}
+
+impl TiledProperty {
+    fn float(name: &str, value: FloatType) -> TiledProperty {
+        TiledProperty {
+            name: name.to_string(),
+            kind: "float".to_string(),
+            value: value.into(),
+        }
+    }
+}
+
+/// Finds a property by name.
+fn property<'a>(properties: &'a [TiledProperty], name: &str) -> Option<&'a serde_json::Value> {
+    properties.iter().find(|p| p.name == name).map(|p| &p.value)
+}
+
+/// Finds the prefab id in the type or properties of an object or tile.
+fn prefab_id(kind: &str, properties: &[TiledProperty]) -> Option<String> {
+    if let Some(id) = property(properties, "prefabId").and_then(|v| v.as_str()) {
+        Some(id.to_string())
+    } else if !kind.is_empty() {
+        Some(kind.to_string())
+    } else {
+        None
+    }
+}
+
+/*** Importer ***/
+/// Tile flags stored in the high bits of a gid.
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const FLIP_FLAGS: u32 = FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY;
+
+/// Symbols used for the building grid of a tile layer.
+const BUILDING_SYMBOLS: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// Loads a Tiled JSON map and converts it to map objects. `offset` is added to everything in
+/// the map.
+pub fn import_map(path: &str, offset: &Vector) -> Result<Vec<MapObjectKind>, TiledError> {
+    let contents = fs::read_to_string(path)?;
+    let mut map = serde_json::from_str::<TiledMap>(&contents)?;
+
+    // Load external tilesets
+    let directory = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    for tileset in map.tilesets.iter_mut() {
+        if let Some(source) = tileset.source.take() {
+            let contents = fs::read_to_string(directory.join(&source))?;
+            let external = serde_json::from_str::<TiledTileset>(&contents)?;
+            tileset.name = external.name;
+            tileset.tiles = external.tiles;
+        }
+    }
+
+    TiledImporter::new(&map).import(offset)
+}
+
+struct TiledImporter<'a> {
+    map: &'a TiledMap,
+
+    /// Prefab ids for each tile gid
+    tile_prefabs: HashMap<u32, String>,
+}
+
+impl<'a> TiledImporter<'a> {
+    fn new(map: &'a TiledMap) -> TiledImporter<'a> {
+        let mut tile_prefabs = HashMap::new();
+        for tileset in map.tilesets.iter() {
+            for tile in tileset.tiles.iter() {
+                if let Some(id) = prefab_id(&tile.kind, &tile.properties) {
+                    tile_prefabs.insert(tileset.firstgid + tile.id, id);
+                }
+            }
+        }
+
+        TiledImporter { map, tile_prefabs }
+    }
+
+    fn import(&self, offset: &Vector) -> Result<Vec<MapObjectKind>, TiledError> {
+        if self.map.infinite {
+            return Err(TiledError::InfiniteMap);
+        }
+
+        // Move Tiled's origin to where it is in the world
+        let properties = &self.map.properties;
+        let mut origin = offset.clone();
+        origin.x += property(properties, "originX")
+            .and_then(|v| v.as_f64())
+            .unwrap_or(0.);
+        origin.y += property(properties, "originY")
+            .and_then(|v| v.as_f64())
+            .unwrap_or(0.);
+
+        self.import_layers(&self.map.layers, &origin)
+    }
+
+    /// Converts layers to groups and buildings. Buildings are not moved by their group, so
+    /// `offset` is the position of the layers in the world.
+    fn import_layers(
+        &self,
+        layers: &[TiledLayer],
+        offset: &Vector,
+    ) -> Result<Vec<MapObjectKind>, TiledError> {
+        let mut objects = Vec::new();
+        for layer in layers.iter() {
+            let info = layer.info();
+            if !info.visible {
+                continue;
+            }
+
+            // Find the layer's position in the world
+            let mut layer_offset = offset.clone();
+            layer_offset.x += info.offsetx;
+            layer_offset.y -= info.offsety;
+
+            match *layer {
+                TiledLayer::Tiles {
+                    width, ref data, ..
+                } => {
+                    if let Some(building) = self.import_tiles(info, width, data, &layer_offset)? {
+                        objects.push(building);
+                    }
+                }
+                TiledLayer::Objects {
+                    objects: ref layer_objects,
+                    ..
+                } => {
+                    let group_objects = layer_objects
+                        .iter()
+                        .filter(|o| o.visible)
+                        .filter_map(|o| self.import_object(o))
+                        .collect();
+                    objects.push(MapObjectKind::Group(Arc::new(GroupConfig {
+                        group_name: info.name.clone(),
+                        offset: layer_offset,
+                        objects: group_objects,
+                    })));
+                }
+                TiledLayer::Group { ref layers, .. } => {
+                    objects.push(MapObjectKind::Group(Arc::new(GroupConfig {
+                        group_name: info.name.clone(),
+                        offset: Vector::zero(),
+                        objects: self.import_layers(layers, &layer_offset)?,
+                    })));
+                }
+                TiledLayer::Image { .. } => {}
+            }
+        }
+
+        Ok(objects)
+    }
+
+    /// Converts an object on an object layer.
+    fn import_object(&self, object: &TiledObject) -> Option<MapObjectKind> {
+        // Find the prefab
+        let prefab_id = prefab_id(&object.kind, &object.properties).or_else(|| {
+            object
+                .gid
+                .and_then(|gid| self.tile_prefabs.get(&(gid & !FLIP_FLAGS)).cloned())
+        });
+        let prefab_id = match prefab_id {
+            Some(id) => id,
+            None => {
+                println!("Tiled object {} has no prefab", object.id);
+                return None;
+            }
+        };
+
+        // Find the center; rects rotate around their top left corner and tiles around their
+        // bottom left corner
+        let mut center = if object.point {
+            Vector::zero()
+        } else if object.gid.is_some() {
+            Vector::new(object.width / 2., -object.height / 2., 0.)
+        } else {
+            Vector::new(object.width / 2., object.height / 2., 0.)
+        };
+        center.rotate_z(object.rotation.to_radians());
+        center.x += object.x;
+        center.y += object.y;
+
+        // Read the properties
+        let z = property(&object.properties, "z").and_then(|v| v.as_f64());
+        let spawn_chance = property(&object.properties, "spawnChance").and_then(|v| v.as_f64());
+
+        // Tiled rotates clockwise with y down, so the yaw is flipped
+        let (rotation, yaw) = ObjectConfig::rotation_from_angle((-object.rotation).to_radians());
+
+        Some(MapObjectKind::Object(Arc::new(ObjectConfig {
+            prefab_id,
+            position: Vector::new(center.x, -center.y, z.unwrap_or(0.)),
+            rotation,
+            yaw,
+            spawn_chance: spawn_chance.unwrap_or(1.),
+        })))
+    }
+
+    /// Converts a tile layer to a building.
+    fn import_tiles(
+        &self,
+        info: &TiledLayerInfo,
+        width: u32,
+        data: &[u32],
+        offset: &Vector,
+    ) -> Result<Option<MapObjectKind>, TiledError> {
+        if self.map.tilewidth != self.map.tileheight {
+            return Err(TiledError::NonSquareTiles);
+        }
+        let tile_size = self.map.tilewidth as FloatType;
+        let room_size = tile_size * 2.;
+
+        // Build the grid, giving each prefab a symbol
+        let mut symbols = HashMap::new();
+        let mut lookup_table = BTreeMap::new();
+        let mut map = Vec::new();
+        for (y, row) in data.chunks(width.max(1) as usize).enumerate() {
+            let mut line = String::new();
+            for (x, &gid) in row.iter().enumerate() {
+                let prefab_id = match self.tile_prefabs.get(&(gid & !FLIP_FLAGS)) {
+                    Some(id) => id,
+                    None => {
+                        if gid != 0 {
+                            println!("Tiled tile {} in layer {} has no prefab", gid, info.name);
+                        }
+                        line.push_str("  ");
+                        continue;
+                    }
+                };
+
+                // Find the symbol for the prefab
+                let symbol_count = symbols.len();
+                let symbol = match symbols.get(prefab_id) {
+                    Some(&symbol) => symbol,
+                    None => {
+                        let symbol = BUILDING_SYMBOLS
+                            .chars()
+                            .nth(symbol_count)
+                            .ok_or(TiledError::TooManyTileKinds)?;
+                        symbols.insert(prefab_id.clone(), symbol);
+                        lookup_table.insert(
+                            symbol.to_string(),
+                            Arc::new(ObjectConfig {
+                                prefab_id: prefab_id.clone(),
+                                position: Vector::zero(),
+                                rotation: 0,
+                                yaw: 0.,
+                                spawn_chance: 1.,
+                            }),
+                        );
+                        symbol
+                    }
+                };
+
+                // Vertical walls are already rotated a quarter turn by the building, so remove
+                // that from the tile's rotation
+                let base_rotation = if x % 2 == 0 && y % 2 == 1 { 1 } else { 0 };
+                let rotation = (TiledImporter::tile_rotation(gid) + 4 - base_rotation) % 4;
+
+                line.push(symbol);
+                if rotation == 0 {
+                    line.push(' ');
+                } else {
+                    line.push_str(&rotation.to_string());
+                }
+            }
+            map.push(line.trim_end().to_string());
+        }
+
+        // Don't create empty buildings
+        if lookup_table.is_empty() {
+            return Ok(None);
+        }
+
+        // Move the offset so the center of the top left tile lines up with the grid; see
+        // `generate_building` for how the grid is positioned
+        let map_height = (map.len() / 2) as FloatType * room_size;
+        let offset = Vector::new(
+            offset.x + tile_size / 2.,
+            offset.y - tile_size / 2. - map_height,
+            offset.z,
+        );
+
+        Ok(Some(MapObjectKind::Building(Arc::new(BuildingConfig {
+            building_name: info.name.clone(),
+            offset,
+            room_size,
+            centered: false,
+            lookup_table,
+            map: map.join("\n"),
+        }))))
+    }
+
+    /// Quarter turns counter-clockwise for a tile's flip flags. Mirrored tiles can't be
+    /// represented, so they're not rotated.
+    fn tile_rotation(gid: u32) -> u8 {
+        let clockwise_turns = match gid & FLIP_FLAGS {
+            0 => 0,
+            f if f == FLIPPED_HORIZONTALLY | FLIPPED_DIAGONALLY => 1,
+            f if f == FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY => 2,
+            f if f == FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY => 3,
+            _ => {
+                println!("Tiled tile {} is mirrored", gid & !FLIP_FLAGS);
+                0
+            }
+        };
+        (4 - clockwise_turns) % 4
+    }
+}
+
+/*** Exporter ***/
+/// Tile size used for exported maps; this is half of the room size used by the buildings.
+const EXPORT_TILE_SIZE: u32 = 58;
+
+/// Converts the props and fixtures in the world to a Tiled map. Each entity is exported as a
+/// rect the size of its collision rects, centered on its origin.
+pub fn export_world(world: &GameWorld) -> TiledMap {
+    measure!("Export Tiled map");
+
+    // Read the entities
+    let mut fixtures = Vec::new();
+    let mut props = Vec::new();
+    for entity in world.entities().iter() {
+        let entity = entity.borrow();
+        let kind = entity.kind();
+        let prop = match *kind {
+            EntityKind::Prop(ref prop) => prop,
+            EntityKind::Player(_) => continue,
+        };
+        let body = entity.body();
+
+        // Find the size of the unrotated rects
+        let mut min = Vector::zero();
+        let mut max = Vector::zero();
+        for (i, rect) in body.rects_raw().iter().enumerate() {
+            let (lower_x, upper_x) = (rect.x_lower_extent(0.), rect.x_upper_extent(0.));
+            let (lower_y, upper_y) = (rect.y_lower_extent(0.), rect.y_upper_extent(0.));
+            if i == 0 {
+                min.set(lower_x, lower_y, 0.);
+                max.set(upper_x, upper_y, 0.);
+            } else {
+                min.set(min.x.min(lower_x), min.y.min(lower_y), 0.);
+                max.set(max.x.max(upper_x), max.y.max(upper_y), 0.);
+            }
+        }
+
+        let object = (
+            prop.prefab.id.clone(),
+            body.get_pos().clone(),
+            *body.get_rotation(),
+            max.x - min.x,
+            max.y - min.y,
+        );
+        match prop.prefab.kind {
+            PrefabKind::Fixture => fixtures.push(object),
+            PrefabKind::Prop => props.push(object),
+        }
+    }
+
+    // Put the top left of all the origins at Tiled's origin
+    let positions = fixtures.iter().chain(props.iter()).map(|o| &o.1);
+    let (mut min_x, mut max_x) = (0. as FloatType, 0. as FloatType);
+    let (mut min_y, mut max_y) = (0. as FloatType, 0. as FloatType);
+    for (i, position) in positions.enumerate() {
+        if i == 0 {
+            min_x = position.x;
+            max_x = position.x;
+            min_y = position.y;
+            max_y = position.y;
+        } else {
+            min_x = min_x.min(position.x);
+            max_x = max_x.max(position.x);
+            min_y = min_y.min(position.y);
+            max_y = max_y.max(position.y);
+        }
+    }
+    let origin = Vector::new(min_x.floor(), max_y.ceil(), 0.);
+
+    // Create the layers
+    let mut next_object_id = 1;
+    let mut layers = Vec::new();
+    for (name, objects) in [("Fixtures", fixtures), ("Props", props)].iter() {
+        let objects = objects
+            .iter()
+            .map(|&(ref prefab_id, ref position, rotation, width, height)| {
+                let id = next_object_id;
+                next_object_id += 1;
+
+                // Flip the yaw and move the position from the center to the top left corner
+                let rotation = normalize_angle(-rotation);
+                let mut corner = Vector::new(-width / 2., -height / 2., 0.);
+                corner.rotate_z(rotation);
+                corner.x += position.x - origin.x;
+                corner.y += origin.y - position.y;
+
+                let mut properties = Vec::new();
+                if position.z != 0. {
+                    properties.push(TiledProperty::float("z", position.z));
+                }
+
+                TiledObject {
+                    id,
+                    name: String::new(),
+                    kind: prefab_id.clone(),
+                    x: corner.x,
+                    y: corner.y,
+                    width,
+                    height,
+                    rotation: rotation.to_degrees(),
+                    point: false,
+                    gid: None,
+                    visible: true,
+                    properties,
+                }
+            })
+            .collect();
+
+        layers.push(TiledLayer::Objects {
+            info: TiledLayerInfo {
+                id: layers.len() as u32 + 1,
+                name: name.to_string(),
+                visible: true,
+                opacity: 1.,
+                x: 0,
+                y: 0,
+                offsetx: 0.,
+                offsety: 0.,
+            },
+            draworder: default_draw_order(),
+            objects,
+        });
+    }
+
+    let tile_size = EXPORT_TILE_SIZE as FloatType;
+    TiledMap {
+        kind: default_map_type(),
+        version: default_version(),
+        orientation: default_orientation(),
+        renderorder: default_render_order(),
+        infinite: false,
+        width: ((max_x - origin.x) / tile_size).ceil() as u32 + 1,
+        height: ((origin.y - min_y) / tile_size).ceil() as u32 + 1,
+        tilewidth: EXPORT_TILE_SIZE,
+        tileheight: EXPORT_TILE_SIZE,
+        nextlayerid: layers.len() as u32 + 1,
+        nextobjectid: next_object_id,
+        layers,
+        tilesets: Vec::new(),
+        properties: vec![
+            TiledProperty::float("originX", origin.x),
+            TiledProperty::float("originY", origin.y),
+        ],
+    }
+}
+
+/// Exports the world and writes it to a file.
+pub fn export_world_to_file(world: &GameWorld, path: &str) -> Result<(), TiledError> {
+    let json = serde_json::to_string_pretty(&export_world(world))?;
+    fs::write(path, json)?;
+    Ok(())
+}
+
+/* Default values */
+fn default_map_type() -> String {
+    "map".to_string()
+}
+fn default_version() -> String {
+    "1.10".to_string()
+}
+fn default_orientation() -> String {
+    "orthogonal".to_string()
+}
+fn default_render_order() -> String {
+    "right-down".to_string()
+}
+fn default_draw_order() -> String {
+    "topdown".to_string()
+}
+fn default_property_type() -> String {
+    "string".to_string()
+}
+fn default_opacity() -> FloatType {
+    1.
+}
+fn default_bool_true() -> bool {
+    true
+}
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::entities::{Entity, Prop};
+    use crate::game_config::{GameConfig, TiledConfig, GAME_CONFIG};
+    use crate::utils::quarter_turns;
+    use std::f64;
+
+    const EPSILON: FloatType = 0.0001;
+
+    fn angles_match(a: FloatType, b: FloatType) -> bool {
+        let diff = normalize_angle(a - b);
+        diff < EPSILON || diff > f64::consts::PI * 2. - EPSILON
+    }
+
+    fn import(json: &str) -> Vec<MapObjectKind> {
+        let map = serde_json::from_str::<TiledMap>(json).unwrap();
+        TiledImporter::new(&map).import(&Vector::zero()).unwrap()
+    }
+
+    /// Flattens groups to a list of objects with their positions in the world.
+    fn flatten(
+        objects: &[MapObjectKind],
+        offset: &Vector,
+        out: &mut Vec<(String, Vector, FloatType)>,
+    ) {
+        for object in objects.iter() {
+            match *object {
+                MapObjectKind::Object(ref object) => {
+                    let mut position = object.position.clone();
+                    position.add(offset, 1.);
+                    out.push((object.prefab_id.clone(), position, object.angle()));
+                }
+                MapObjectKind::Group(ref group) => {
+                    let mut group_offset = offset.clone();
+                    group_offset.add(&group.offset, 1.);
+                    flatten(&group.objects, &group_offset, out);
+                }
+                _ => panic!("Unexpected object kind"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_missing_map_is_config_error() {
+        let mut config =
+            serde_json::from_str::<TiledConfig>(r#"{"tiledMap": "missing.tmj"}"#).unwrap();
+        let err = config.import().unwrap_err();
+        assert!(err.contains("could not import Tiled map missing.tmj"));
+
+        // The import error is kept when the map is loaded with the rest of the config
+        let yaml = std::fs::read_to_string("game-config.yaml")
+            .unwrap()
+            .replace(
+                "\nmaps:\n",
+                "\nmaps:\n  broken:\n    name: Broken\n    objects:\n    - tiledMap: missing.tmj\n",
+            );
+        let err = GameConfig::parse(&yaml).unwrap_err();
+        assert!(err.contains("invalid map broken: could not import Tiled map missing.tmj"));
+    }
+
+    #[test]
+    fn test_import_objects() {
+        let objects = import(
+            r#"{
+                "width": 10, "height": 10, "tilewidth": 58, "tileheight": 58,
+                "properties": [
+                    { "name": "originX", "type": "float", "value": 100 },
+                    { "name": "originY", "type": "float", "value": 50 }
+                ],
+                "layers": [{
+                    "type": "objectgroup", "id": 1, "name": "Props", "offsetx": 10,
+                    "objects": [
+                        { "id": 1, "type": "classic-bed", "x": 0, "y": 0, "width": 20, "height": 10, "rotation": 90 },
+                        { "id": 2, "x": 5, "y": 5, "point": true,
+                          "properties": [{ "name": "prefabId", "value": "classic-lamp" }, { "name": "z", "type": "float", "value": 3 }] },
+                        { "id": 3, "type": "classic-lamp", "x": 0, "y": 0, "visible": false }
+                    ]
+                }]
+            }"#,
+        );
+
+        let mut flattened = Vec::new();
+        flatten(&objects, &Vector::zero(), &mut flattened);
+        assert_eq!(flattened.len(), 2);
+
+        // Rotated around the top left corner and flipped to y up
+        let (ref prefab_id, ref position, angle) = flattened[0];
+        assert_eq!(prefab_id, "classic-bed");
+        assert!(position.distance(&Vector::new(105., 40., 0.)) < EPSILON);
+        assert!(angles_match(angle, -f64::consts::FRAC_PI_2));
+
+        let (ref prefab_id, ref position, _) = flattened[1];
+        assert_eq!(prefab_id, "classic-lamp");
+        assert!(position.distance(&Vector::new(115., 45., 3.)) < EPSILON);
+    }
+
+    #[test]
+    fn test_import_tiles() {
+        let objects = import(
+            r#"{
+                "width": 3, "height": 3, "tilewidth": 58, "tileheight": 58,
+                "tilesets": [{
+                    "firstgid": 1, "name": "Walls",
+                    "tiles": [
+                        { "id": 0, "type": "classic-wall-t-connect" },
+                        { "id": 1, "type": "classic-wall-outside" },
+                        { "id": 2, "properties": [{ "name": "prefabId", "value": "classic-floor" }] }
+                    ]
+                }],
+                "layers": [{
+                    "type": "tilelayer", "id": 1, "name": "House", "width": 3, "height": 3,
+                    "data": [1, 2684354562, 1, 2, 3, 0, 1, 2, 1]
+                }]
+            }"#,
+        );
+
+        let building = match objects[0] {
+            MapObjectKind::Building(ref building) => building,
+            _ => panic!("Expected a building"),
+        };
+        assert_eq!(building.building_name, "House");
+        assert_eq!(building.room_size, 116.);
+        assert_eq!(building.map, "a b3a\nb3c\na b a");
+        assert_eq!(
+            building.lookup_table["a"].prefab_id,
+            "classic-wall-t-connect"
+        );
+        assert_eq!(building.lookup_table["b"].prefab_id, "classic-wall-outside");
+        assert_eq!(building.lookup_table["c"].prefab_id, "classic-floor");
+
+        // The top left tile's center is at Tiled's origin
+        assert_eq!(building.offset, Vector::new(29., -145., 0.));
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let mut world = GameWorld::new();
+        let objects = vec![
+            ("classic-bed", Vector::new(123.5, -40., 1.), 0, 30.),
+            ("classic-wall-outside", Vector::new(-300., 200., 0.), 1, 0.),
+        ];
+        for &(prefab_id, ref position, rotation, yaw) in objects.iter() {
+            let object = Arc::new(ObjectConfig {
+                prefab_id: prefab_id.to_string(),
+                position: position.clone(),
+                rotation,
+                yaw,
+                spawn_chance: 1.,
+            });
+            let prefab = GAME_CONFIG
+                .prefab_with_id(&object.prefab_id)
+                .unwrap()
+                .clone();
+            let entity = Entity::new(EntityKind::Prop(Prop::from_prefab(object, prefab)));
+            world.insert_entity(entity).unwrap();
+        }
+
+        // Export and import the map
+        let json = serde_json::to_string(&export_world(&world)).unwrap();
+        let mut imported = Vec::new();
+        flatten(&import(&json), &Vector::zero(), &mut imported);
+
+        assert_eq!(imported.len(), 2);
+        for &(prefab_id, ref position, rotation, yaw) in objects.iter() {
+            let angle = quarter_turns(rotation) + yaw.to_radians();
+            let found = imported.iter().any(|&(ref id, ref p, a)| {
+                id == prefab_id && p.distance(position) < EPSILON && angles_match(a, angle)
+            });
+            assert!(found, "Missing {} after round trip", prefab_id);
+        }
+    }
+}
@@ -41,19 +41,32 @@ impl<T: AddAssign + Copy> Counter<T> {
     }
 }
 
+impl<T: AddAssign + Copy + PartialOrd> Counter<T> {
+    /// Makes sure `count` and anything before it is never returned again; used when restoring ids.
+    pub fn skip_past(&mut self, count: T) {
+        if self.count <= count {
+            self.count = count;
+            self.count.add_assign(self.increment);
+        }
+    }
+}
+
 /*** Performance ***/
-// Add guard profile
+// Add guard profile; the phase is always tracked so panics can say where they happened
 #[cfg(profile)]
 #[macro_export]
 macro_rules! measure {
-    ($arg:tt) => (let _guard = ::flame::start_guard($arg);)
+    ($arg:tt) => (
+        let _guard = ::flame::start_guard($arg);
+        let _phase = $crate::supervisor::PhaseGuard::enter($arg);
+    )
 }
 
-// Don't do anything if not profiling
+// Only track the phase if not profiling
 #[cfg(not(profile))]
 #[macro_export]
 macro_rules! measure {
-    ($arg:tt) => ()
+    ($arg:tt) => (let _phase = $crate::supervisor::PhaseGuard::enter($arg);)
 }
 
 // Used for profiling extra details; for now, do nothing
@@ -168,7 +181,11 @@ impl Serializable for Vector {
 
 impl Serializable for Rect {
     fn serialize(&self) -> Value {
-        Value::Array(vec![self.center.serialize(), self.size.serialize()])
+        let mut data = vec![self.center.serialize(), self.size.serialize()];
+        if self.is_rotated() {
+            data.push(self.rotation.into());
+        }
+        Value::Array(data)
     }
 }
 
@@ -180,7 +197,11 @@ impl Vector {
 
 impl Rect {
     pub fn serialize_with_offset(&self, offset: &Vector) -> Value {
-        Value::Array(vec![self.center.serialize_with_offset(offset), self.size.serialize()])
+        let mut data = vec![self.center.serialize_with_offset(offset), self.size.serialize()];
+        if self.is_rotated() {
+            data.push(self.rotation.into());
+        }
+        Value::Array(data)
     }
 }
 
@@ -228,6 +249,23 @@ serializable_impl!(String);
 /*** Math ***/
 pub type FloatType = f64;
 
+/// One 90º turn in radians.
+pub const QUARTER_TURN: FloatType = f64::consts::FRAC_PI_2;
+
+/// Angles closer than this to a quarter turn are treated as a quarter turn.
+const ANGLE_EPSILON: FloatType = 0.000_001;
+
+/// Converts a number of quarter turns (the old `u8` rotation) in to radians.
+pub fn quarter_turns(turns: u8) -> FloatType {
+    turns as FloatType * QUARTER_TURN
+}
+
+/// Wraps an angle so it's between 0 and 2π.
+pub fn normalize_angle(angle: FloatType) -> FloatType {
+    let angle = angle % (f64::consts::PI * 2.);
+    if angle < 0. { angle + f64::consts::PI * 2. } else { angle }
+}
+
 /*** Vector ***/
 #[derive(Clone, Debug)]
 pub struct Vector {
@@ -280,6 +318,20 @@ impl Vector {
         self.z += other.z * multiplier;
     }
 
+    #[inline]
+    pub fn dot(&self, other: &Vector) -> FloatType {
+        self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// Rotates the vector around the z axis.
+    #[inline]
+    pub fn rotate_z(&mut self, angle: FloatType) {
+        let (sin, cos) = angle.sin_cos();
+        let (x, y) = (self.x, self.y);
+        self.x = x * cos - y * sin;
+        self.y = x * sin + y * cos;
+    }
+
     #[inline]
     pub fn magnitude(&self) -> FloatType {
         (self.x.powf(2.) + self.y.powf(2.) + self.z.powf(2.)).powf(0.5)
@@ -345,32 +397,68 @@ impl IncrementalValueDiffInner<FloatType> for Vector {
 #[derive(Clone, Debug)]
 pub struct Rect {
     pub center: Vector,
-    pub size: Vector
+    pub size: Vector,
+
+    /// Yaw around the rect's center in radians. This is always kept between -45º and 45º, since
+    /// anything past that is the same as swapping the x and y size; this way, rects rotated by
+    /// quarter turns stay axis aligned.
+    pub rotation: FloatType
 }
 
 impl Rect {
     pub fn new(center: Vector, size: Vector) -> Rect {
-        Rect { center, size }
+        Rect { center, size, rotation: 0. }
     }
 
     pub fn copy_from(&mut self, other: &Rect) {
         self.center.copy_from(&other.center);
         self.size.copy_from(&other.size);
+        self.rotation = other.rotation;
+    }
+
+    /// If the rect is not axis aligned.
+    pub fn is_rotated(&self) -> bool {
+        self.rotation != 0.
+    }
+
+    /// Half of the width of the axis aligned box surrounding the rect.
+    fn x_half_extent(&self) -> FloatType {
+        if self.is_rotated() {
+            (self.size.x * self.rotation.cos().abs() + self.size.y * self.rotation.sin().abs()) / 2.
+        } else {
+            self.size.x / 2.
+        }
+    }
+
+    /// Half of the depth of the axis aligned box surrounding the rect.
+    fn y_half_extent(&self) -> FloatType {
+        if self.is_rotated() {
+            (self.size.x * self.rotation.sin().abs() + self.size.y * self.rotation.cos().abs()) / 2.
+        } else {
+            self.size.y / 2.
+        }
     }
 
-    pub fn x_upper_extent(&self, offset: FloatType) -> FloatType { self.center.x + self.size.x / 2. + offset }
-    pub fn x_lower_extent(&self, offset: FloatType) -> FloatType { self.center.x - self.size.x / 2. + offset }
+    // Extents are of the axis aligned box surrounding the rect, so they're safe to use for bounds
+    pub fn x_upper_extent(&self, offset: FloatType) -> FloatType { self.center.x + self.x_half_extent() + offset }
+    pub fn x_lower_extent(&self, offset: FloatType) -> FloatType { self.center.x - self.x_half_extent() + offset }
 
-    pub fn y_upper_extent(&self, offset: FloatType) -> FloatType { self.center.y + self.size.y / 2. + offset }
-    pub fn y_lower_extent(&self, offset: FloatType) -> FloatType { self.center.y - self.size.y / 2. + offset }
+    pub fn y_upper_extent(&self, offset: FloatType) -> FloatType { self.center.y + self.y_half_extent() + offset }
+    pub fn y_lower_extent(&self, offset: FloatType) -> FloatType { self.center.y - self.y_half_extent() + offset }
 
     pub fn z_upper_extent(&self, offset: FloatType) -> FloatType { self.center.z + self.size.z / 2. + offset }
     pub fn z_lower_extent(&self, offset: FloatType) -> FloatType { self.center.z - self.size.z / 2. + offset }
 
-    pub fn rotate(&mut self, rotation: &u8) {
+    /// Rotates the rect around the origin by an angle in radians.
+    pub fn rotate(&mut self, rotation: FloatType) {
         measure_verbose!("Rotate rect");
 
-        for i in 0..*rotation {
+        // Split the rotation into whole quarter turns and the remaining yaw
+        let turns = (rotation / QUARTER_TURN).round();
+        let remainder = rotation - turns * QUARTER_TURN;
+
+        // Quarter turns are applied exactly so axis aligned rects stay axis aligned
+        for _ in 0..((turns as i64).rem_euclid(4)) {
             // Rotate center (x, y) = (-y, x)
             self.center.y *= -1.;
             swap(&mut self.center.x, &mut self.center.y);
@@ -378,6 +466,75 @@ impl Rect {
             // Swap size (w, h) = (h, w)
             swap(&mut self.size.x, &mut self.size.y);
         }
+
+        // Apply the remaining yaw
+        if remainder.abs() > ANGLE_EPSILON {
+            self.center.rotate_z(remainder);
+            self.rotation += remainder;
+
+            // Keep the yaw between -45º and 45º
+            if self.rotation >= QUARTER_TURN / 2. {
+                self.rotation -= QUARTER_TURN;
+                swap(&mut self.size.x, &mut self.size.y);
+            } else if self.rotation < -QUARTER_TURN / 2. {
+                self.rotation += QUARTER_TURN;
+                swap(&mut self.size.x, &mut self.size.y);
+            }
+            if self.rotation.abs() <= ANGLE_EPSILON {
+                self.rotation = 0.;
+            }
+        }
+    }
+
+    /// The local x and y axes of the rect.
+    fn axes(&self) -> [Vector; 2] {
+        let (sin, cos) = self.rotation.sin_cos();
+        [Vector::new(cos, sin, 0.), Vector::new(-sin, cos, 0.)]
+    }
+
+    /// Half of the length of the rect when projected on to a unit axis.
+    pub fn projected_radius(&self, axis: &Vector) -> FloatType {
+        let [local_x, local_y] = self.axes();
+        (self.size.x * local_x.dot(axis).abs() + self.size.y * local_y.dot(axis).abs() + self.size.z * axis.z.abs()) / 2.
+    }
+
+    /// Finds the axis with the least penetration between two rects using the separating axis
+    /// theorem. Returns the unit axis pointing from this rect to the other rect and how far they
+    /// overlap along it, or `None` if they don't intersect.
+    pub fn min_overlap(&self, other: &Rect, offset: &Vector, other_offset: &Vector) -> Option<(Vector, FloatType)> {
+        measure_verbose!("Min overlap");
+
+        // Find the distance between the centers
+        let mut diff = other.center.clone();
+        diff.add(other_offset, 1.);
+        diff.add(&self.center, -1.);
+        diff.add(offset, -1.);
+
+        // Rects only rotate around the z axis, so that's always a separating axis
+        let mut best_axis = Vector::new(0., 0., 1.);
+        let mut best_overlap = (self.size.z + other.size.z) / 2. - diff.z.abs();
+        if best_overlap <= 0. {
+            return None;
+        }
+
+        // Check the local axes of both of the rects
+        for axis in self.axes().iter().chain(other.axes().iter()) {
+            let distance = (diff.x * axis.x + diff.y * axis.y).abs();
+            let overlap = self.projected_radius(axis) + other.projected_radius(axis) - distance;
+            if overlap <= 0. {
+                return None;
+            } else if overlap < best_overlap {
+                best_overlap = overlap;
+                best_axis = axis.clone();
+            }
+        }
+
+        // Point the axis towards the other rect
+        if best_axis.dot(&diff) < 0. {
+            best_axis.scale(&-1.);
+        }
+
+        Some((best_axis, best_overlap))
     }
 
     /// Checks if the ray intersects this rectangle and returns the % of the ray length that
@@ -385,6 +542,22 @@ impl Rect {
     /// Based on https://tavianator.com/fast-branchless-raybounding-box-intersections/
     /// (found from https://github.com/tmpvar/ray-aabb-slab)
     pub fn intersects_ray(&self, r: &Ray, offset: &Vector) -> Option<FloatType> {
+        // Move the ray in to the rect's local space so it can be tested like an axis aligned box;
+        // rotating doesn't change the length of the ray, so the distance is the same
+        if self.is_rotated() {
+            let mut center = self.center.clone();
+            center.add(offset, 1.);
+
+            let mut origin = r.origin.clone();
+            origin.add(&center, -1.);
+            origin.rotate_z(-self.rotation);
+            let mut dir = r.dir.clone();
+            dir.rotate_z(-self.rotation);
+
+            let local_rect = Rect::new(Vector::zero(), self.size.clone());
+            return local_rect.intersects_ray(&Ray::new(origin, dir, r.length), &Vector::zero());
+        }
+
         // Define lower and upper bounds for the AABB test
         let min = Vector::new(
             self.x_lower_extent(offset.x),
@@ -426,7 +599,7 @@ impl Rect {
 
         // This can be merged into `collide` to reuse some code (just find distance in each dimension
         // but this is more performance and doesn't allocate as much memory/calculations)
-        return
+        let bounds_intersect =
             self.x_upper_extent(offset.x) > other.x_lower_extent(other_offset.x) &&
             self.x_lower_extent(offset.x) < other.x_upper_extent(other_offset.x) &&
 
@@ -434,10 +607,30 @@ impl Rect {
             self.y_lower_extent(offset.y) < other.y_upper_extent(other_offset.y) &&
 
             self.z_upper_extent(offset.z) > other.z_lower_extent(other_offset.z) &&
-            self.z_lower_extent(offset.z) < other.z_upper_extent(other_offset.z)
+            self.z_lower_extent(offset.z) < other.z_upper_extent(other_offset.z);
+
+        // The bounds are exact if neither rect is rotated
+        if !bounds_intersect || (!self.is_rotated() && !other.is_rotated()) {
+            return bounds_intersect;
+        }
+
+        self.min_overlap(other, offset, other_offset).is_some()
     }
 
     pub fn contains_point(&self, other: &Vector, offset: &Vector) -> bool {
+        // Move the point in to the rect's local space if rotated
+        if self.is_rotated() {
+            let mut point = other.clone();
+            point.add(&self.center, -1.);
+            point.add(offset, -1.);
+            point.rotate_z(-self.rotation);
+
+            return
+                point.x.abs() < self.size.x / 2. &&
+                point.y.abs() < self.size.y / 2. &&
+                point.z.abs() < self.size.z / 2.
+        }
+
         return
             other.x < self.x_upper_extent(offset.x) &&
             other.x > self.x_lower_extent(offset.x) &&
@@ -462,7 +655,7 @@ impl fmt::Display for Rect {
 
 impl PartialEq for Rect {
     fn eq(&self, other: &Rect) -> bool {
-        self.center == other.center && self.size == other.size
+        self.center == other.center && self.size == other.size && self.rotation == other.rotation
     }
 }
 
@@ -529,3 +722,62 @@ pub fn pack_value(data: &mut PackData, flag: &PackFlag, value: Value) {
     data.push((Value::from(flag.pack_flag()), value));
 }
 
+
+/* Tests */
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rotate_quarter_turns() {
+        // Quarter turns should stay axis aligned and match the old `u8` rotation
+        let mut rect = Rect::new(Vector::new(10., 0., 0.), Vector::new(4., 2., 1.));
+        rect.rotate(quarter_turns(1));
+        assert!(!rect.is_rotated());
+        assert_eq!(rect.center, Vector::new(0., 10., 0.));
+        assert_eq!(rect.size, Vector::new(2., 4., 1.));
+
+        rect.rotate(quarter_turns(3));
+        assert!(!rect.is_rotated());
+        assert_eq!(rect.center, Vector::new(10., 0., 0.));
+        assert_eq!(rect.size, Vector::new(4., 2., 1.));
+    }
+
+    #[test]
+    fn test_rotate_yaw() {
+        let mut rect = Rect::new(Vector::zero(), Vector::new(4., 2., 1.));
+        rect.rotate(f64::consts::FRAC_PI_4 + 0.1);
+        assert!(rect.is_rotated());
+        assert!(rect.rotation.abs() <= QUARTER_TURN / 2.);
+
+        // Bounds grow to fit the rotated rect
+        assert!(rect.x_upper_extent(0.) > 1.);
+        assert!(rect.y_upper_extent(0.) > 1.);
+    }
+
+    #[test]
+    fn test_oriented_intersects() {
+        // A thin rect rotated 45º only touches the corner region of the bounds
+        let mut diagonal = Rect::new(Vector::zero(), Vector::new(10., 1., 1.));
+        diagonal.rotate(f64::consts::FRAC_PI_4);
+        let corner = Rect::new(Vector::new(3., -3., 0.), Vector::new(1., 1., 1.));
+        let center = Rect::new(Vector::new(3., 3., 0.), Vector::new(1., 1., 1.));
+
+        assert!(!diagonal.intersects(&corner, &Vector::zero(), &Vector::zero()));
+        assert!(diagonal.intersects(&center, &Vector::zero(), &Vector::zero()));
+        assert!(diagonal.contains_point(&Vector::new(2., 2., 0.), &Vector::zero()));
+        assert!(!diagonal.contains_point(&Vector::new(2., -2., 0.), &Vector::zero()));
+    }
+
+    #[test]
+    fn test_oriented_ray() {
+        let mut diagonal = Rect::new(Vector::zero(), Vector::new(10., 1., 1.));
+        diagonal.rotate(f64::consts::FRAC_PI_4);
+
+        // A ray along the rect's length hits it; a parallel ray through the bounds misses it
+        let hit = Ray::new(Vector::new(-10., -10., 0.), Vector::new(1., 1., 0.), 100.);
+        assert!(diagonal.intersects_ray(&hit, &Vector::zero()).is_some());
+        let miss = Ray::new(Vector::new(-10., -8., 0.), Vector::new(1., 1., 0.), 100.);
+        assert!(diagonal.intersects_ray(&miss, &Vector::zero()).is_none());
+    }
+}
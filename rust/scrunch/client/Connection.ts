@@ -1,15 +1,25 @@
 import { float, int } from "./types";
 import msgpack = require("msgpack-lite");
-import { EntityInitData, EntityUpdateData, EntityId } from "./entities/EntityData";
+import { EntityInitData, EntityUpdateData, EntityId, PlayerClass } from "./entities/EntityData";
 import { Game } from "./Game";
 import { RivetClient } from "@rivet-gg/api";
 
-type JoinData = int;
-type UpdateData = [int, EntityInitData[], EntityUpdateData[], EntityId[], EntityId[]];
+type JoinData = [int, PlayerClass[]];
+type UpdateData = [int, EntityInitData[], EntityUpdateData[], EntityId[], EntityId[], int]; // Ends with the size the map is moving to
+type ClassesData = PlayerClass[];
+type Standing = [EntityId, string, int]; // Player ID, username and points
+type RoundData = { number: int, playing: boolean, endsIn: int };
+type LeaderboardData = { round: RoundData | null, leaders: Standing[] };
+type RoundResultsData = { round: int, winner: Standing | null, standings: Standing[] };
+type JoinFailedData = "betweenRounds" | "invalidUsername" | "unknownClass" | "lockedClass" | "noRoom";
 
 enum IncomingMessageType {
     Join = 0,
-    Update = 1
+    Update = 1,
+    Classes = 2,
+    Leaderboard = 3,
+    RoundResults = 4,
+    JoinFailed = 5
 }
 
 enum OutgoingMessageType {
@@ -116,6 +126,18 @@ export class Connection {
             case IncomingMessageType.Update:
                 this.onUpdate(data);
                 break;
+            case IncomingMessageType.Classes:
+                this.onClasses(data);
+                break;
+            case IncomingMessageType.Leaderboard:
+                this.onLeaderboard(data);
+                break;
+            case IncomingMessageType.RoundResults:
+                this.onRoundResults(data);
+                break;
+            case IncomingMessageType.JoinFailed:
+                this.onJoinFailed(data);
+                break;
             default:
                 console.error(`Unknown message type ${type}`)
                 break;
@@ -149,8 +171,12 @@ export class Connection {
         this.socket.send(binary);
     }
 
-    public sendJoin(username: string) {
-        this.sendMessage(OutgoingMessageType.Join, username);
+    public sendJoin(username: string, classId?: string, color?: int) {
+        this.sendMessage(OutgoingMessageType.Join, {
+            username,
+            classId: classId || null,
+            color: color != undefined ? color : null
+        });
     }
 
     public sendMove(x: int, y: int) {
@@ -159,7 +185,8 @@ export class Connection {
 
     /* Events */
     public onJoin(data: JoinData) {
-        let playerId = data;
+        let playerId = data[0];
+        Game.shared.classes = data[1]; // Classes for the next respawn
 
         Game.shared.mainPlayerId = playerId;
         Game.shared.spectatingId = playerId;
@@ -171,5 +198,48 @@ export class Connection {
         data[2].forEach(e => Game.shared.updateEntity((e))); // Updated entities
         data[3].forEach(id => Game.shared.removeEntity(id, false)); // Disappeared entities
         data[4].forEach(id => Game.shared.removeEntity(id, true)); // Destroyed entities
+        Game.shared.targetMapSize = data[5];
+    }
+
+    private onClasses(data: ClassesData) {
+        Game.shared.classes = data;
+    }
+
+    private onLeaderboard(data: LeaderboardData) {
+        let lines = data.leaders.map(([id, username, points], i) => `${i + 1}. ${username} - ${points}`);
+
+        // Show the time left in the round
+        if (data.round) {
+            const seconds = Math.ceil(data.round.endsIn / 1000);
+            const time = `${Math.floor(seconds / 60)}:${(seconds % 60).toString().padStart(2, "0")}`;
+            lines.unshift(data.round.playing ? `Round ${data.round.number} - ${time} left` : `Next round in ${time}`);
+        }
+
+        document.getElementById("leaderboard").innerText = lines.join("\n");
+    }
+
+    private onRoundResults(data: RoundResultsData) {
+        let text = `Round ${data.round} over!`;
+        if (data.winner) {
+            text += ` ${data.winner[1]} won with ${data.winner[2]} points.`;
+        }
+        document.getElementById("loadingState").innerText = `${text} Press Enter to join the next round`;
+    }
+
+    private onJoinFailed(data: JoinFailedData) {
+        const reasons = {
+            betweenRounds: "The next round hasn't started yet.",
+            invalidUsername: "That username can't be used.",
+            unknownClass: "That class doesn't exist, so you'll get a random one.",
+            lockedClass: "That class is still locked, so you'll get a random one.",
+            noRoom: "The map is full."
+        };
+
+        // Fall back to a random class so the next join goes through
+        if (data == "unknownClass" || data == "lockedClass") {
+            Game.shared.selectedClassId = undefined;
+        }
+
+        document.getElementById("loadingState").innerText = `${reasons[data]} Press Enter to join`;
     }
 }
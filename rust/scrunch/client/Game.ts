@@ -4,7 +4,7 @@ import { float, int } from "./types";
 import { Grid } from "./Grid";
 import { Entity, EntityState } from "./entities/Entity";
 import { Connection } from "./Connection";
-import { EntityData, EntityInitData, EntityKind, EntityUpdateData } from "./entities/EntityData";
+import { EntityData, EntityInitData, EntityKind, EntityUpdateData, PlayerClass } from "./entities/EntityData";
 import { GapEntity } from "./entities/GapEntity";
 import { PlayerEntity } from "./entities/PlayerEntity";
 import { PointOrbEntity } from "./entities/PointOrbEntity";
@@ -22,11 +22,17 @@ export class Game extends PIXI.Application {
 
     // Entities
     public mapSize: int;
+    public targetMapSize: int; // Tiles outside of this are about to be cut off by the border
     public grid: Grid;
     public mainPlayerId?: int;
     public spectatingId?: int;
     public entities: { [id: number]: Entity } = { };
 
+    // Classes
+    public classes: PlayerClass[] = [];
+    public selectedClassId?: string = new URLSearchParams(location.search).get("class") || undefined;
+    public selectedColor?: int = Game.parseColor(new URLSearchParams(location.search).get("color"));
+
     public get mainPlayer(): PlayerEntity | undefined {
         if (this.mainPlayerId != undefined) {
             return this.entityForId(this.mainPlayerId) as PlayerEntity;
@@ -107,11 +113,19 @@ export class Game extends PIXI.Application {
         // Add join event
         addEventListener("keydown", (ev) => {
             if (ev.key == "Enter") {
-                this.connection.sendJoin("test username");
+                this.connection.sendJoin("test username", this.selectedClassId, this.selectedColor);
             }
         });
     }
 
+    // Parses a color picked in the URL, like `?color=ff8800`
+    private static parseColor(hex: string | null): int | undefined {
+        if (hex == null || !/^[0-9a-fA-F]{6}$/.test(hex)) {
+            return undefined;
+        }
+        return parseInt(hex, 16);
+    }
+
     public updateSize() {
         // Calculate the size
         // let width = window.innerWidth;
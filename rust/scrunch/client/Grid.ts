@@ -20,6 +20,7 @@ export class Grid extends PIXI.Container { // TODO: Render shapes to a texture
 
     public drawGrid() {
         const mapSize = Game.shared.mapSize;
+        const targetMapSize = Game.shared.targetMapSize;
         const mainPlayer = Game.shared.mainPlayer;
 
         // Get the frame positions
@@ -72,6 +73,8 @@ export class Grid extends PIXI.Container { // TODO: Render shapes to a texture
                     color = 0xff0000;
                 } else if (showMovementGrid && mainPlayer && mainPlayer.canMoveTo(x, y)) {
                     color = 0x00ff00;
+                } else if (Math.abs(x) > targetMapSize || Math.abs(y) > targetMapSize) {
+                    color = 0xf2c2c2; // Warn that the border is closing in
                 } else if (otherPlayerCanMoveHere) {
                     color = 0xd1d1d1;
                 } else {
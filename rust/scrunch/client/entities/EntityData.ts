@@ -35,7 +35,9 @@ export type GapUpdateData = undefined;
 export interface PlayerInitData{
     username: string,
     points: int;
-    class: PlayerClass
+    class: PlayerClass,
+    color?: int, // Picked by the player; overrides the class's color
+    bot: boolean
 }
 
 export interface PlayerUpdateData {
@@ -43,9 +45,14 @@ export interface PlayerUpdateData {
 }
 
 export interface PlayerClass {
+    id: string,
+    name: string,
+    description: string,
     color: int,
     moveWait: int, // In milliseconds
-    movePositions: MapIndex[]
+    movePositions: MapIndex[],
+    unlockPoints?: int, // Points needed in a single life to pick the class
+    unlocked?: boolean // Only sent in the list of classes to pick from
 }
 
 /* Points */
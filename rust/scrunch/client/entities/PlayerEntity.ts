@@ -13,6 +13,7 @@ export class PlayerEntity extends Entity {
 
     // Player properties
     public username: string;
+    public isBot: boolean;
     public points: int;
     public playerClass: PlayerClass;
 
@@ -77,15 +78,16 @@ export class PlayerEntity extends Entity {
         // Update player data
         let playerData = data.data as PlayerInitData;
         this.username = playerData.username;
+        this.isBot = playerData.bot;
         this.points = playerData.points;
         this.playerClass = playerData.class;
 
         // Set the colors
-        this.bodyColor = this.playerClass.color;
+        this.bodyColor = playerData.color != undefined ? playerData.color : this.playerClass.color;
         this.topColor = new Color(this.bodyColor).lighten(0.16).rgbNumber();
 
         // Update the username
-        this.usernameText.text = this.username;
+        this.usernameText.text = this.isBot ? `${this.username} [BOT]` : this.username;
         this.usernameText.position.set(-this.usernameText.width / 2, -this.usernameText.height / 2 + 40);
     }
 
@@ -197,7 +199,7 @@ export class PlayerEntity extends Entity {
         this.bodyGraphics.endFill();
 
         // Draw the body
-        this.bodyGraphics.beginFill(this.playerClass.color, 0.95);
+        this.bodyGraphics.beginFill(this.bodyColor, 0.95);
         this.bodyGraphics.drawRoundedRect(-topWidth / 2, -height - topHeight / 2, topWidth, topHeight + height, borderRadius);
         this.bodyGraphics.endFill();
 
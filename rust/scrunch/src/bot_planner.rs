@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::bots::distance;
+use crate::entities::{EntityId, EntityKind, Player};
+use crate::game_map::{GameMap, MapIndex};
+use crate::tiles::EnterAction;
+
+/// A player that can move onto an index
+#[derive(Debug)]
+struct Threat {
+    attacker: EntityId,
+
+    /// Milliseconds until the attacker can move
+    ready_in: u64,
+}
+
+/// The indexes every player can move to next. Built once per tick, so it doesn't know about moves
+/// made earlier in the same tick.
+pub struct ThreatMap {
+    threats: HashMap<MapIndex, Vec<Threat>>,
+}
+
+impl ThreatMap {
+    pub fn build(map: &GameMap) -> ThreatMap {
+        let mut threats: HashMap<MapIndex, Vec<Threat>> = HashMap::new();
+        for (id, entity) in map.entities() {
+            if let EntityKind::Player(player) = entity.kind() {
+                let ready_in = player.time_until_ready();
+                for relative_index in player.player_class().move_positions() {
+                    let mut index = entity.index().clone();
+                    index.add(relative_index);
+                    threats.entry(index).or_default().push(Threat {
+                        attacker: *id,
+                        ready_in,
+                    });
+                }
+            }
+        }
+        ThreatMap { threats }
+    }
+
+    /// If a player that isn't ignored can move onto the index within `within` milliseconds.
+    pub fn is_threatened<F>(&self, index: &MapIndex, within: u64, ignore: F) -> bool
+    where
+        F: Fn(&EntityId) -> bool,
+    {
+        self.threats.get(index).is_some_and(|threats| {
+            threats
+                .iter()
+                .any(|threat| threat.ready_in < within && !ignore(&threat.attacker))
+        })
+    }
+}
+
+/// Searches a few moves ahead for the moves with the best reward for their risk. Assumes the other
+/// players stay where they are.
+pub struct Planner<'a> {
+    map: &'a GameMap,
+    threats: &'a ThreatMap,
+
+    /// The bot being planned for
+    bot_id: EntityId,
+    player: &'a Player,
+
+    /// Where to head if there's nothing to gain within the moves searched
+    goal: Option<MapIndex>,
+
+    /// When to stop searching deeper
+    deadline: Instant,
+
+    /// Indexes left behind along the current path, which are gaps by the time it gets there
+    gaps: Vec<MapIndex>,
+
+    /// Orbs and players squished along the current path
+    squished: Vec<EntityId>,
+}
+
+impl<'a> Planner<'a> {
+    /// Most moves ahead it searches
+    const MAX_DEPTH: usize = 3;
+
+    /// How much a move is worth for each move it is in the future
+    const DISCOUNT: f64 = 0.8;
+
+    /// Cost of getting squished on top of the points lost
+    const DEATH_PENALTY: f64 = 20.;
+
+    /// Cost per index away from the goal at the end of the path
+    const GOAL_WEIGHT: f64 = 0.1;
+
+    pub fn new(
+        map: &'a GameMap,
+        threats: &'a ThreatMap,
+        bot_id: EntityId,
+        player: &'a Player,
+        goal: Option<MapIndex>,
+        deadline: Instant,
+    ) -> Planner<'a> {
+        Planner {
+            map,
+            threats,
+            bot_id,
+            player,
+            goal,
+            deadline,
+            gaps: Vec::new(),
+            squished: Vec::new(),
+        }
+    }
+
+    /// Picks the best move from the base, searching deeper until it runs out of time. A search
+    /// one move deep always finishes.
+    pub fn plan(mut self, base: &MapIndex) -> Option<MapIndex> {
+        let mut best = None;
+        for max_depth in 1..=Planner::MAX_DEPTH {
+            match self.best_move(base, max_depth) {
+                Some(result) => best = result,
+                None => break,
+            }
+        }
+        best
+    }
+
+    /// Finds the best move from the base; `None` if it ran out of time.
+    fn best_move(&mut self, base: &MapIndex, max_depth: usize) -> Option<Option<MapIndex>> {
+        self.gaps.clear();
+        self.squished.clear();
+
+        let mut best = None;
+        let mut best_value = f64::NEG_INFINITY;
+        for index in self.moves_from(base) {
+            let value = self.move_value(base, &index, 0, max_depth)?;
+            if value > best_value {
+                best = Some(index);
+                best_value = value;
+            }
+        }
+        Some(best)
+    }
+
+    /// Value of moving from one index to another `depth` moves from now, including the best moves
+    /// after it; `None` if it ran out of time.
+    fn move_value(
+        &mut self,
+        from: &MapIndex,
+        to: &MapIndex,
+        depth: usize,
+        max_depth: usize,
+    ) -> Option<f64> {
+        if max_depth > 1 && Instant::now() >= self.deadline {
+            return None;
+        }
+
+        // Points gained from whatever is squished
+        let (reward, squished) = match self.map.entity_at(to) {
+            Some((id, entity)) if !self.squished.contains(id) => match entity.kind() {
+                EntityKind::Player(other) => (*other.point_count() as f64, Some(*id)),
+                EntityKind::Tile(tile) if tile.enter_action() == EnterAction::Consume => {
+                    (tile.points() as f64, Some(*id))
+                }
+                EntityKind::Tile(_) => (0., None),
+            },
+            _ => (0., None),
+        };
+        self.squished.extend(squished);
+
+        // Check if anyone can squish the bot before it moves again
+        let move_wait = self.player.player_class().move_wait();
+        let leave_time = (depth as u64 + 1) * move_wait;
+        let (bot_id, squished_ids) = (self.bot_id, &self.squished);
+        let threatened = self.threats.is_threatened(to, leave_time, |attacker| {
+            *attacker == bot_id || squished_ids.contains(attacker)
+        });
+
+        let value = if threatened {
+            reward - *self.player.point_count() as f64 - Planner::DEATH_PENALTY
+        } else if depth + 1 < max_depth {
+            // Add the best move after this one
+            self.gaps.push(from.clone());
+            let mut best_next: Option<f64> = None;
+            for next in self.moves_from(to) {
+                let next_value = self.move_value(to, &next, depth + 1, max_depth)?;
+                best_next = Some(best_next.map_or(next_value, |best| best.max(next_value)));
+            }
+            self.gaps.pop();
+            reward + Planner::DISCOUNT * best_next.unwrap_or(0.)
+        } else {
+            let goal_distance = self.goal.as_ref().map_or(0, |goal| distance(to, goal));
+            reward - Planner::GOAL_WEIGHT * goal_distance as f64
+        };
+
+        if squished.is_some() {
+            self.squished.pop();
+        }
+
+        Some(value)
+    }
+
+    /// Moves from the index that stay in the map and don't land on a hazard.
+    fn moves_from(&self, base: &MapIndex) -> Vec<MapIndex> {
+        self.player
+            .player_class()
+            .move_positions()
+            .iter()
+            .map(|relative_index| {
+                let mut index = base.clone();
+                index.add(relative_index);
+                index
+            })
+            .filter(|index| self.map.index_within_bounds(index) && !self.gaps.contains(index))
+            .filter(|index| {
+                !self
+                    .map
+                    .entity_kind_at(index)
+                    .is_some_and(EntityKind::is_hazard)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::entities::Entity;
+    use crate::game_map::IndexType;
+    use crate::player_classes::parse_classes;
+    use crate::tiles::PointOrb;
+    use std::time::Duration;
+
+    /// Moves one index up, down, left or right
+    const CLASS: &str = "
+        [[class]]
+        id = \"rook\"
+        name = \"Rook\"
+        description = \"\"
+        color = 0xffffff
+        move_wait = 500
+        move_positions = [[0, 1], [1, 0], [0, -1], [-1, 0]]
+    ";
+
+    fn add_player(map: &mut GameMap, x: IndexType, y: IndexType) -> EntityId {
+        let class = parse_classes(CLASS).unwrap().remove(0);
+        let player = Player::new_bot("bot".to_string(), class);
+        map.insert_entity(Entity::new(MapIndex::new(x, y), EntityKind::Player(player)))
+            .unwrap()
+    }
+
+    fn add_orb(map: &mut GameMap, x: IndexType, y: IndexType) -> EntityId {
+        map.insert_entity(Entity::new(
+            MapIndex::new(x, y),
+            EntityKind::Tile(Box::new(PointOrb::new(5))),
+        ))
+        .unwrap()
+    }
+
+    /// Plans the bot's move; the map always has room for the indexes next to the center.
+    fn plan(map: &mut GameMap, bot_id: EntityId, deadline: Instant) -> Option<MapIndex> {
+        map.update_map_size(0);
+        let threats = ThreatMap::build(map);
+        let entity = map.entity_with_id(&bot_id).unwrap();
+        let player = match entity.kind() {
+            EntityKind::Player(player) => player,
+            _ => panic!("the bot should be a player"),
+        };
+        Planner::new(map, &threats, bot_id, player, None, deadline).plan(entity.index())
+    }
+
+    #[test]
+    fn test_threat_map() {
+        let mut map = GameMap::new();
+        let enemy = add_player(&mut map, 1, 1);
+        let threats = ThreatMap::build(&map);
+
+        // A player that hasn't moved yet can move right away
+        assert!(threats.is_threatened(&MapIndex::new(1, 0), 1, |_| false));
+        assert!(!threats.is_threatened(&MapIndex::new(1, 0), 1, |id| *id == enemy));
+        assert!(!threats.is_threatened(&MapIndex::new(0, 0), 1, |_| false));
+    }
+
+    #[test]
+    fn test_avoid_threats() {
+        let mut map = GameMap::new();
+        let bot_id = add_player(&mut map, 0, 0);
+        add_player(&mut map, 1, 1);
+
+        // The orb is next to the other player, so taking it would get the bot squished
+        add_orb(&mut map, 1, 0);
+        let index = plan(&mut map, bot_id, Instant::now() + Duration::from_secs(1)).unwrap();
+        assert!(index == MapIndex::new(-1, 0) || index == MapIndex::new(0, -1));
+    }
+
+    #[test]
+    fn test_take_orb() {
+        let mut map = GameMap::new();
+        let bot_id = add_player(&mut map, 0, 0);
+        add_orb(&mut map, 1, 0);
+        let index = plan(&mut map, bot_id, Instant::now() + Duration::from_secs(1));
+        assert_eq!(index, Some(MapIndex::new(1, 0)));
+    }
+
+    #[test]
+    fn test_deadline_passed() {
+        let mut map = GameMap::new();
+        let bot_id = add_player(&mut map, 0, 0);
+        add_orb(&mut map, 0, -1);
+
+        // A search one move deep still runs
+        let index = plan(&mut map, bot_id, Instant::now());
+        assert_eq!(index, Some(MapIndex::new(0, -1)));
+    }
+}
@@ -0,0 +1,313 @@
+use rand::Rng;
+use serde::Deserialize;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::fs;
+use std::io;
+use std::time::Instant;
+
+use crate::bot_planner::{Planner, ThreatMap};
+use crate::entities::{EntityId, EntityKind, Player};
+use crate::game_map::{GameMap, IndexType, MapIndex};
+use crate::tiles::EnterAction;
+
+/// File the bot config is loaded from
+const BOTS_PATH: &str = "bots.toml";
+
+lazy_static::lazy_static! {
+    pub static ref BOT_CONFIG: BotConfig = {
+        match load_bot_config(BOTS_PATH) {
+            Ok(config) => config,
+            Err(err) => panic!("Invalid bot config in {}: {}", BOTS_PATH, err),
+        }
+    };
+}
+
+/// Errors that occur when loading the bot config
+#[derive(Debug)]
+pub enum BotConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+
+    /// Bots need at least one name to pick from
+    NoNames,
+
+    /// At least one difficulty needs a weight
+    NoDifficulties,
+}
+
+impl Display for BotConfigError {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            BotConfigError::Io(err) => write!(f, "{}", err),
+            BotConfigError::Toml(err) => write!(f, "{}", err),
+            BotConfigError::NoNames => write!(f, "no bot names"),
+            BotConfigError::NoDifficulties => write!(f, "every difficulty has a weight of 0"),
+        }
+    }
+}
+
+/// How likely a new bot is to play at each difficulty
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DifficultyWeights {
+    #[serde(default)]
+    random: u32,
+    #[serde(default)]
+    greedy: u32,
+    #[serde(default)]
+    hunter: u32,
+}
+
+/// Controls how many bots are in the game and how they play
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BotConfig {
+    /// Bots are added until the players and bots add up to this
+    target_players: usize,
+
+    /// Bots kept in the game no matter how many players join
+    #[serde(default)]
+    min_bots: usize,
+
+    /// Usernames the bots pick from
+    names: Vec<String>,
+
+    difficulty: DifficultyWeights,
+}
+
+impl BotConfig {
+    /// Number of bots that should be in a game with this many human players.
+    pub fn bot_count(&self, human_count: usize) -> usize {
+        usize::max(
+            self.target_players.saturating_sub(human_count),
+            self.min_bots,
+        )
+    }
+
+    pub fn random_name(&self) -> String {
+        rand::thread_rng()
+            .choose(&self.names)
+            .expect("there is always a bot name")
+            .clone()
+    }
+
+    pub fn random_difficulty(&self) -> BotDifficulty {
+        let weights = &self.difficulty;
+        let mut roll =
+            rand::thread_rng().gen_range(0, weights.random + weights.greedy + weights.hunter);
+        if roll < weights.random {
+            return BotDifficulty::Random;
+        }
+        roll -= weights.random;
+        if roll < weights.greedy {
+            BotDifficulty::Greedy
+        } else {
+            BotDifficulty::Hunter
+        }
+    }
+}
+
+/* Loading */
+pub fn load_bot_config(path: &str) -> Result<BotConfig, BotConfigError> {
+    parse_bot_config(&fs::read_to_string(path).map_err(BotConfigError::Io)?)
+}
+
+/// Parses and validates the bot config.
+pub fn parse_bot_config(source: &str) -> Result<BotConfig, BotConfigError> {
+    let config = toml::from_str::<BotConfig>(source).map_err(BotConfigError::Toml)?;
+
+    if config.names.is_empty() {
+        return Err(BotConfigError::NoNames);
+    }
+
+    let weights = &config.difficulty;
+    if weights.random + weights.greedy + weights.hunter == 0 {
+        return Err(BotConfigError::NoDifficulties);
+    }
+
+    Ok(config)
+}
+
+/* Bots */
+/// A player controlled by the server.
+#[derive(Debug)]
+pub struct Bot {
+    pub id: EntityId,
+    pub difficulty: BotDifficulty,
+}
+
+/// How smart a bot is about where it moves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BotDifficulty {
+    /// Moves anywhere that isn't a hazard, like a gap
+    Random,
+
+    /// Goes after the point orbs
+    Greedy,
+
+    /// Plans a few moves ahead to squish players and take orbs while staying out of reach
+    Hunter,
+}
+
+impl BotDifficulty {
+    /// Distance around the bot it looks for orbs and players to go after
+    const SIGHT_RANGE: IndexType = 8;
+
+    /// Picks where the bot should move next; `None` if it should stay put. The deadline limits
+    /// how long hunters spend planning.
+    pub fn find_move(
+        self,
+        map: &GameMap,
+        threats: &ThreatMap,
+        id: &EntityId,
+        base: &MapIndex,
+        player: &Player,
+        deadline: Instant,
+    ) -> Option<MapIndex> {
+        let moves = possible_moves(map, base, player);
+        match self {
+            BotDifficulty::Random => rand::thread_rng().choose(&moves).cloned(),
+            BotDifficulty::Greedy => {
+                // Stay out of reach of the players that could move there first
+                let move_wait = player.player_class().move_wait();
+                let safe_moves = moves
+                    .into_iter()
+                    .filter(|index| !threats.is_threatened(index, move_wait, |other| other == id))
+                    .collect();
+                greedy_move(map, base, safe_moves)
+            }
+            BotDifficulty::Hunter => {
+                // Stalk the closest weaker player in sight, or the closest orb if there isn't one
+                let points = *player.point_count();
+                let goal = closest_in_sight(map, base, |kind| match kind {
+                    EntityKind::Player(other) => *other.point_count() < points,
+                    _ => false,
+                })
+                .or_else(|| closest_in_sight(map, base, is_reward));
+                Planner::new(map, threats, *id, player, goal, deadline).plan(base)
+            }
+        }
+    }
+}
+
+/// Moves that stay in the map and don't land on a hazard.
+fn possible_moves(map: &GameMap, base: &MapIndex, player: &Player) -> Vec<MapIndex> {
+    player
+        .player_class()
+        .move_positions()
+        .iter()
+        .map(|relative_index| {
+            let mut index = base.clone();
+            index.add(relative_index);
+            index
+        })
+        .filter(|index| map.index_within_bounds(index))
+        .filter(|index| !map.entity_kind_at(index).is_some_and(EntityKind::is_hazard))
+        .collect()
+}
+
+fn greedy_move(map: &GameMap, base: &MapIndex, moves: Vec<MapIndex>) -> Option<MapIndex> {
+    // Take the biggest orb within reach
+    if let Some(index) = best_target(map, &moves, |kind| match kind {
+        EntityKind::Tile(tile) if is_reward(kind) => Some(tile.points()),
+        _ => None,
+    }) {
+        return Some(index);
+    }
+
+    // Otherwise head to the closest orb in sight
+    if let Some(orb) = closest_in_sight(map, base, is_reward) {
+        return closest_move(&moves, &orb);
+    }
+
+    rand::thread_rng().choose(&moves).cloned()
+}
+
+/// If the entity is a tile that gives points when taken, like an orb.
+fn is_reward(kind: &EntityKind) -> bool {
+    match kind {
+        EntityKind::Tile(tile) => tile.enter_action() == EnterAction::Consume && tile.points() > 0,
+        EntityKind::Player(_) => false,
+    }
+}
+
+/// Finds the move landing on the entity with the highest value, skipping entities without one.
+fn best_target<F>(map: &GameMap, moves: &[MapIndex], value: F) -> Option<MapIndex>
+where
+    F: Fn(&EntityKind) -> Option<u64>,
+{
+    moves
+        .iter()
+        .filter_map(|index| Some((index, value(map.entity_kind_at(index)?)?)))
+        .max_by_key(|&(_, value)| value)
+        .map(|(index, _)| index.clone())
+}
+
+/// Finds the closest entity within sight of the base that matches the filter.
+fn closest_in_sight<F>(map: &GameMap, base: &MapIndex, filter: F) -> Option<MapIndex>
+where
+    F: Fn(&EntityKind) -> bool,
+{
+    let range = BotDifficulty::SIGHT_RANGE;
+    ((base.x - range)..=(base.x + range))
+        .flat_map(|x| ((base.y - range)..=(base.y + range)).map(move |y| MapIndex::new(x, y)))
+        .filter(|index| index != base && map.entity_kind_at(index).is_some_and(&filter))
+        .min_by_key(|index| distance(base, index))
+}
+
+/// Finds the move that ends up closest to the target.
+fn closest_move(moves: &[MapIndex], target: &MapIndex) -> Option<MapIndex> {
+    moves
+        .iter()
+        .min_by_key(|index| distance(index, target))
+        .cloned()
+}
+
+pub fn distance(a: &MapIndex, b: &MapIndex) -> IndexType {
+    (a.x - b.x).abs() + (a.y - b.y).abs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = "
+        target_players = 10
+        min_bots = 2
+        names = [\"Squishy\"]
+
+        [difficulty]
+        greedy = 1
+    ";
+
+    #[test]
+    fn test_parse_bot_config() {
+        let config = parse_bot_config(CONFIG).unwrap();
+        assert_eq!(config.random_name(), "Squishy");
+        assert_eq!(config.random_difficulty(), BotDifficulty::Greedy);
+
+        assert!(matches!(
+            parse_bot_config(&CONFIG.replace("[\"Squishy\"]", "[]")),
+            Err(BotConfigError::NoNames)
+        ));
+        assert!(matches!(
+            parse_bot_config(&CONFIG.replace("greedy = 1", "greedy = 0")),
+            Err(BotConfigError::NoDifficulties)
+        ));
+        assert!(matches!(
+            parse_bot_config(&CONFIG.replace("greedy = 1", "smart = 1")),
+            Err(BotConfigError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn test_bot_count() {
+        let config = parse_bot_config(CONFIG).unwrap();
+        assert_eq!(config.bot_count(0), 10);
+        assert_eq!(config.bot_count(7), 3);
+
+        // Some bots stay no matter how many players join
+        assert_eq!(config.bot_count(9), 2);
+        assert_eq!(config.bot_count(50), 2);
+    }
+}
@@ -1,37 +1,49 @@
-use rand;
-use rand::distributions::{IndependentSample, Range};
 use rmpv::Value;
 use std::ops::AddAssign;
 
 use crate::game_map::MapIndex;
 use crate::incremental_value::IncrementalValue;
+use crate::player_classes::PlayerClass;
+use crate::tiles::{EnterAction, Tile};
 use crate::utils::time_milliseconds;
 
 /*** Base ***/
+/// Identifies each kind of entity to the client, which has a matching `EntityKind` enum. Every
+/// kind of tile needs its own flag.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KindFlag {
+    Player = 0,
+    Gap = 1,
+    PointOrb = 2,
+}
+
 /// Entities that are inside the game's map.
 #[derive(Debug)]
 pub enum EntityKind {
     Player(Player),
-    Gap { spawner: EntityId, spawn_time: u64 },
-    PointOrb { points: IncrementalValue<u64> },
+    Tile(Box<dyn Tile>),
 }
 
 impl EntityKind {
-    pub fn kind_flag(&self) -> u8 {
+    pub fn kind_flag(&self) -> KindFlag {
         match *self {
-            EntityKind::Player(_) => 0,
-            EntityKind::Gap { .. } => 1,
-            EntityKind::PointOrb { .. } => 2,
+            EntityKind::Player(_) => KindFlag::Player,
+            EntityKind::Tile(ref tile) => tile.kind_flag(),
         }
     }
 
     pub fn serialize(&self, init: bool) -> Value {
         match *self {
             EntityKind::Player(ref player) => player.serialize(init),
-            EntityKind::Gap { .. } => Value::Nil,
-            EntityKind::PointOrb { ref points } => {
-                Value::Array(vec![Value::from(points.get().clone())])
-            }
+            EntityKind::Tile(ref tile) => tile.serialize(init),
+        }
+    }
+
+    /// If moving onto the entity squishes the player; bots steer clear of these.
+    pub fn is_hazard(&self) -> bool {
+        match *self {
+            EntityKind::Player(_) => false,
+            EntityKind::Tile(ref tile) => tile.enter_action() == EnterAction::Squish,
         }
     }
 
@@ -39,8 +51,7 @@ impl EntityKind {
     fn needs_update(&self) -> bool {
         match *self {
             EntityKind::Player(ref player) => player.needs_update(),
-            EntityKind::Gap { .. } => false,
-            EntityKind::PointOrb { ref points } => points.is_changed(),
+            EntityKind::Tile(ref tile) => tile.needs_update(),
         }
     }
 
@@ -48,8 +59,7 @@ impl EntityKind {
     fn updated(&mut self) {
         match *self {
             EntityKind::Player(ref mut player) => player.updated(),
-            EntityKind::Gap { .. } => {}
-            EntityKind::PointOrb { ref mut points } => points.updated(),
+            EntityKind::Tile(ref mut tile) => tile.updated(),
         }
     }
 }
@@ -67,6 +77,9 @@ pub struct Entity {
     /// The current index of the entity
     index: IncrementalValue<MapIndex>,
 
+    /// When the entity was created in milliseconds
+    spawn_time: u64,
+
     /// The kind of entity
     kind: EntityKind,
 }
@@ -76,6 +89,7 @@ impl Entity {
         Entity {
             is_new: true,
             index: IncrementalValue::new(index),
+            spawn_time: time_milliseconds(),
             kind,
         }
     }
@@ -99,7 +113,12 @@ impl Entity {
         &self.index.get()
     }
 
-    pub fn move_to(&mut self, index: &MapIndex) {
+    pub fn spawn_time(&self) -> u64 {
+        self.spawn_time
+    }
+
+    /// Only called by `GameMap::move_to`, which keeps track of which index each entity is at.
+    pub(crate) fn move_to(&mut self, index: &MapIndex) {
         self.index.get_mut().clone_from(index);
     }
 
@@ -121,7 +140,7 @@ impl Entity {
 
         // Init values
         if init {
-            data.push(("kind".into(), self.kind.kind_flag().into()));
+            data.push(("kind".into(), (self.kind.kind_flag() as u8).into()));
         }
 
         // Update values
@@ -138,162 +157,6 @@ impl Entity {
 }
 
 /*** Player ***/
-// Define the player
-lazy_static::lazy_static! {
-    static ref PLAYER_CLASSES: Vec<PlayerClass> = {
-        vec![
-//            // TESTER
-//            PlayerClass::new(
-//                0xd12727,
-//                0,
-//                vec![
-//                    MapIndex::new(1, 0), MapIndex::new(2, 0), MapIndex::new(3, 0), MapIndex::new(4, 0),
-//                    MapIndex::new(-1, 0), MapIndex::new(-2, 0), MapIndex::new(-3, 0), MapIndex::new(-4, 0),
-//                    MapIndex::new(0, 1), MapIndex::new(0, 2), MapIndex::new(0, 3), MapIndex::new(0, 4),
-//                    MapIndex::new(0, -1), MapIndex::new(0, -2), MapIndex::new(0, -3), MapIndex::new(0, -4)
-//                ]
-//            ),
-
-            // Horizontal
-            PlayerClass::new(
-                0xd12727,
-                500,
-                vec![
-                    MapIndex::new(1, 0), MapIndex::new(2, 0),
-                    MapIndex::new(-1, 0), MapIndex::new(-2, 0),
-                    MapIndex::new(0, 1), MapIndex::new(0, 2),
-                    MapIndex::new(0, -1), MapIndex::new(0, -2)
-                ]
-            ),
-
-            // Diagonal
-            PlayerClass::new(
-                0x4286f4,
-                300,
-                vec![
-                    MapIndex::new(1, 1), MapIndex::new(2, 2),
-                    MapIndex::new(-1, 1), MapIndex::new(-2, 2),
-                    MapIndex::new(1, -1), MapIndex::new(2, -2),
-                    MapIndex::new(-1, -1), MapIndex::new(-2, -2),
-                ]
-            ),
-
-            // Threes on all sides
-            PlayerClass::new(
-                0x3df78e,
-                700,
-                vec![
-                    MapIndex::new(2, -1), MapIndex::new(2, 0), MapIndex::new(2, 1),
-                    MapIndex::new(-2, -1), MapIndex::new(-2, 0), MapIndex::new(-2, 1),
-                    MapIndex::new(-1, 2), MapIndex::new(0, 2), MapIndex::new(1, 2),
-                    MapIndex::new(-1, -2), MapIndex::new(0, -2), MapIndex::new(1, -2),
-                ]
-            ),
-
-            // All sides
-            PlayerClass::new(
-                0x96f73d,
-                700,
-                vec![
-                    MapIndex::new(-1, -1),
-                    MapIndex::new(0, -1),
-                    MapIndex::new(1, -1),
-                    MapIndex::new(1, 0),
-                    MapIndex::new(1, 1),
-                    MapIndex::new(0, 1),
-                    MapIndex::new(-1, 1),
-                    MapIndex::new(-1, 0),
-                ]
-            ),
-
-            // Horizontal with skips
-            PlayerClass::new(
-                0xf76b3d,
-                1000,
-                vec![
-                    MapIndex::new(1, 0), MapIndex::new(3, 0),
-                    MapIndex::new(-1, 0), MapIndex::new(-3, 0),
-                    MapIndex::new(0, 1), MapIndex::new(0, 3),
-                    MapIndex::new(0, -1), MapIndex::new(0, -3)
-                ]
-            ),
-
-            // Diagonal corners
-            PlayerClass::new(
-                0x773df7,
-                600,
-                vec![
-                    MapIndex::new(-2, -1), MapIndex::new(-1, -2),
-                    MapIndex::new(2, -1), MapIndex::new(1, -2),
-                    MapIndex::new(2, 1), MapIndex::new(1, 2),
-                    MapIndex::new(-2, 1), MapIndex::new(-1, 2),
-                ]
-            ),
-        ]
-    };
-}
-
-/// The class of the player
-#[derive(Debug, Clone)]
-pub struct PlayerClass {
-    /// The color for the class
-    color: u64,
-
-    /// Time in between moves, in milliseconds
-    move_wait: u64,
-
-    /// Positions relative to the player's base in which they can move
-    move_positions: Vec<MapIndex>,
-}
-
-impl PlayerClass {
-    pub fn new(color: u64, move_wait: u64, move_positions: Vec<MapIndex>) -> PlayerClass {
-        PlayerClass {
-            color,
-            move_wait,
-            move_positions,
-        }
-    }
-
-    // TODO: Remove this temporary method
-    pub fn random() -> PlayerClass {
-        // Find a random player class
-        let mut rng = rand::thread_rng();
-        let index_range = Range::new(0, PLAYER_CLASSES.len());
-        let class_index = index_range.ind_sample(&mut rng);
-
-        // Lookup and clone the class
-        PLAYER_CLASSES[class_index].clone()
-    }
-
-    pub fn move_positions(&self) -> &Vec<MapIndex> {
-        &self.move_positions
-    }
-
-    pub fn valid_move_position(&self, relative_index: &MapIndex) -> bool {
-        self.move_positions.contains(relative_index)
-    }
-}
-
-impl PlayerClass {
-    pub fn serialize(&self) -> Value {
-        // Convert the move positions from a set to an array
-        let move_positions = self
-            .move_positions
-            .iter()
-            .map(|x| x.serialize())
-            .collect::<Vec<Value>>()
-            .into();
-
-        // Convert the data to a map
-        Value::Map(vec![
-            ("color".into(), self.color.into()),
-            ("movePositions".into(), move_positions),
-            ("moveWait".into(), self.move_wait.into()),
-        ])
-    }
-}
-
 /// The player itself
 #[derive(Debug)]
 pub struct Player {
@@ -306,20 +169,64 @@ pub struct Player {
     /// The class of the player
     class: PlayerClass,
 
-    /// The last time the player moved in milliseconds
-    move_time: u64,
+    /// Color picked by the player; the class's color is used if `None`
+    color: Option<u64>,
+
+    /// If the player is controlled by the server
+    is_bot: bool,
+
+    /// The last time the player moved in milliseconds; `None` if they haven't moved yet
+    move_time: Option<u64>,
 }
 
 impl Player {
     const STARTING_POINTS: u64 = 15;
 
-    pub fn new(username: String, class: PlayerClass) -> Player {
+    /// Largest color a player can pick (`0xRRGGBB`)
+    pub const MAX_COLOR: u64 = 0xff_ffff;
+
+    /// Most characters a username can have
+    pub const MAX_USERNAME_LENGTH: usize = 20;
+
+    pub fn new(username: String, class: PlayerClass, color: Option<u64>) -> Player {
         Player {
             username,
             class,
+            color,
             points: IncrementalValue::new(Player::STARTING_POINTS),
-            move_time: 0,
+            is_bot: false,
+            move_time: None,
+        }
+    }
+
+    /// Creates a player controlled by the server.
+    pub fn new_bot(username: String, class: PlayerClass) -> Player {
+        Player {
+            is_bot: true,
+            ..Player::new(username, class, None)
+        }
+    }
+
+    pub fn username(&self) -> &str {
+        &self.username
+    }
+
+    /// Trims a username picked by a client; `None` if it's empty, too long or has control
+    /// characters.
+    pub fn clean_username(username: &str) -> Option<String> {
+        let username = username.trim();
+        let length = username.chars().count();
+        if length == 0
+            || length > Player::MAX_USERNAME_LENGTH
+            || username.chars().any(char::is_control)
+        {
+            return None;
         }
+        Some(username.to_string())
+    }
+
+    pub fn is_bot(&self) -> bool {
+        self.is_bot
     }
 
     pub fn player_class(&self) -> &PlayerClass {
@@ -327,11 +234,24 @@ impl Player {
     }
 
     pub fn ready_to_move(&self) -> bool {
-        self.ready_to_move_with_wait(self.class.move_wait)
+        self.ready_to_move_with_wait(self.class.move_wait())
     }
 
     pub fn ready_to_move_with_wait(&self, wait: u64) -> bool {
-        self.move_time + wait <= time_milliseconds()
+        match self.move_time {
+            Some(move_time) => move_time + wait <= time_milliseconds(),
+            None => true,
+        }
+    }
+
+    /// Milliseconds until the player can move again.
+    pub fn time_until_ready(&self) -> u64 {
+        match self.move_time {
+            Some(move_time) => {
+                (move_time + self.class.move_wait()).saturating_sub(time_milliseconds())
+            }
+            None => 0,
+        }
     }
 
     pub fn can_move_to(&self, relative_index: &MapIndex) -> bool {
@@ -340,7 +260,7 @@ impl Player {
     }
 
     pub fn moved(&mut self) {
-        self.move_time = time_milliseconds();
+        self.move_time = Some(time_milliseconds());
     }
 
     pub fn serialize(&self, init: bool) -> Value {
@@ -351,6 +271,10 @@ impl Player {
         if init {
             data.push(("username".into(), self.username.clone().into()));
             data.push(("class".into(), self.class.serialize()));
+            if let Some(color) = self.color {
+                data.push(("color".into(), color.into()));
+            }
+            data.push(("bot".into(), self.is_bot.into()));
         }
 
         // Update values
@@ -380,3 +304,22 @@ impl Player {
         self.points.updated()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_clean_username() {
+        assert_eq!(
+            Player::clean_username("  scrunch "),
+            Some("scrunch".to_string())
+        );
+        assert_eq!(Player::clean_username("   "), None);
+        assert_eq!(Player::clean_username("a\nb"), None);
+
+        let longest = "é".repeat(Player::MAX_USERNAME_LENGTH);
+        assert_eq!(Player::clean_username(&longest), Some(longest.clone()));
+        assert_eq!(Player::clean_username(&format!("{}e", longest)), None);
+    }
+}
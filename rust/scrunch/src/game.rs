@@ -1,19 +1,23 @@
 use rand;
 use rand::distributions::{IndependentSample, Range};
-use rand::Rng;
+use rmpv::Value;
 use std::collections::HashMap;
 use std::f64;
-use std::ops::AddAssign;
-use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use crate::entities::{Entity, EntityId, EntityKind, Player, PlayerClass};
+use crate::bot_planner::ThreatMap;
+use crate::bots::{Bot, BOT_CONFIG};
+use crate::entities::{Entity, EntityId, EntityKind, Player};
 use crate::game_map::{GameMap, IndexType, MapIndex};
-use crate::incremental_value::IncrementalValue;
-use crate::network::{ClientHandle, ClientId, SocketSender};
+use crate::network::{
+    ClientHandle, ClientId, Command, CommandReceiver, JoinError, JoinRequest, SocketSender,
+};
+use crate::player_classes::{class_with_id, random_class, serialize_classes};
+use crate::rounds::{serialize_standings, standings, Round, RoundEvent, ROUND_CONFIG};
+use crate::scheduler::TickScheduler;
+use crate::tiles::{EnterAction, Gap, PointOrb};
 use crate::utils::time_milliseconds;
-use crate::utils::Counter;
 
 #[derive(Debug)]
 pub enum GameError {
@@ -43,8 +47,6 @@ pub enum GameError {
     OutOfMap,
 }
 
-pub type GameReference = Arc<Mutex<Game>>;
-
 pub struct Game {
     /// List of connections within the game
     clients: HashMap<ClientId, ClientHandle>,
@@ -52,78 +54,98 @@ pub struct Game {
     /// Structure that holds the items inside the map
     map: GameMap,
 
-    /// Counter for generating client IDs
-    client_id: Counter<ClientId>,
+    /// Commands sent by the connections
+    commands: CommandReceiver,
+
+    /// The bots in the game
+    bots: Vec<Bot>,
 
-    /// Number of bots to maintain in the game
-    bot_count: usize,
+    /// The current round; `None` if the game never ends
+    round: Option<Round>,
 
-    /// List of entity IDs for the bots
-    bot_ids: Vec<EntityId>,
+    /// The last time the leaderboard was sent
+    leaderboard_time: u64,
+
+    /// Most time all of the bots can spend planning their moves in a tick
+    bot_planning_time: Duration,
 }
 
 impl Game {
-    pub fn new() -> GameReference {
+    /// Starts the game loop on its own thread, which owns the game.
+    pub fn start(tick_rate: u32, commands: CommandReceiver) {
         // Create a Game object
-        let game = Game {
+        let mut game = Game {
             clients: HashMap::new(),
             map: GameMap::new(),
-            client_id: Counter::new(0, 1),
-            bot_count: 50,
-            bot_ids: Vec::new(),
+            commands,
+            bots: Vec::new(),
+            round: if ROUND_CONFIG.enabled() {
+                Some(Round::new(&ROUND_CONFIG, time_milliseconds()))
+            } else {
+                None
+            },
+            leaderboard_time: 0,
+            bot_planning_time: Duration::from_secs(1) / tick_rate / Game::BOT_PLANNING_SHARE,
         };
 
-        // Create the game reference
-        let game_reference = Arc::new(Mutex::new(game));
-
         // Start event loop
-        {
-            let game = game_reference.clone();
-            thread::spawn(move || {
-                loop {
-                    // Perform the update; the lock will go out of scope after the block
-                    {
-                        let mut game = game.lock().unwrap();
-                        match game.update() {
-                            Ok(_) => {}
-                            Err(err) => println!("Game update error: {:?}", err),
-                        }
-                    }
-
-                    // TODO: Fix sleep time to be dynamic
-                    thread::sleep(Duration::from_millis(200));
-
-                    // TODO: Recover from panic here
+        thread::spawn(move || {
+            let mut scheduler = TickScheduler::new(tick_rate);
+            loop {
+                // Perform the update
+                let update_start = Instant::now();
+                match game.update() {
+                    Ok(_) => {}
+                    Err(err) => println!("Game update error: {:?}", err),
                 }
-            });
-        }
+                scheduler.record(update_start.elapsed());
 
-        game_reference
-    }
+                // Sleep for what's left of the tick
+                scheduler.wait();
 
-    /// Called when a new connection is created.
-    pub fn add_client(&mut self, client: ClientHandle) {
-        // Save the client
-        self.clients.insert(self.client_id.tick(), client);
+                // TODO: Recover from panic here
+            }
+        });
     }
 
     /// Called every tick.
     fn update(&mut self) -> Result<(), GameError> {
+        let now = time_milliseconds();
+
+        /* Update the round */
+        self.update_round(now);
+        let playing = match self.round {
+            Some(ref round) => round.is_playing(),
+            None => true,
+        };
+
         /* Update the size */
-        self.map.update_map_size();
+        let map_size = self.map.map_size();
+        let size_limit = self
+            .round
+            .as_mut()
+            .and_then(|round| round.map_size_limit(now, map_size));
+        self.map.set_size_limit(size_limit);
+        self.map.update_map_size(now);
+        self.map.enforce_border();
 
         // Update the bots
-        self.update_bots()?;
+        if playing {
+            let spawning = self.can_spawn(now);
+            self.update_bots(spawning)?;
+        }
 
         /* Map updates */
         self.update_players()?;
-        self.update_gaps()?;
+        self.update_tiles()?;
 
         /* Send client updates */
         // Send the updates
         for (_, client) in self.clients.iter_mut() {
+            client.track_points(&self.map);
             client.build_update_message(&self.map);
         }
+        self.update_leaderboard(now);
 
         // Remove the flags from all of the objects
         self.map.updated();
@@ -134,48 +156,39 @@ impl Game {
 
 impl Game {
     fn update_players(&mut self) -> Result<(), GameError> {
-        // Handle messages from connections
+        // Handle commands from connections; only the latest move from each client is kept, since
+        // older ones were made from positions the player isn't at anymore. New connections are
+        // added before and disconnections removed after the player commands, so the commands of a
+        // client that connected and left in the same tick still have a client to go to.
         let mut disconnections = Vec::new();
-        let mut wanted_player_moves = Vec::new();
-        for (id, client) in self.clients.iter_mut() {
-            // Handle leave message
-            if let Ok(_) = client.rx_leave.try_recv() {
-                disconnections.push(id.clone());
-                continue;
-            }
-
-            // Handle join message
-            if let Ok(username) = client.rx_join.try_recv() {
-                // Validate the IDs
-                client.validate_player_ids(&self.map);
-
-                // Make sure not already in the game
-                if client.player_id != None {
-                    continue;
+        for command in self.commands.connection_commands().into_iter() {
+            match command {
+                Command::Connect(id, client) => {
+                    self.clients.insert(id, client);
                 }
-
-                // Spawn the user
-                let player = Entity::new(
-                    self.map.spawn_position(),
-                    EntityKind::Player(Player::new(username, PlayerClass::random())),
-                );
-                let player_id = self.map.insert_entity(player)?;
-
-                // Update the client
-                client.player_id = Some(player_id);
-
-                // Send the join
-                client.send_join(player_id.clone());
+                Command::Leave(id) => disconnections.push(id),
+                Command::Join(..) => {}
             }
-
-            // Handle move message
-            if let Ok(index) = client.rx_move.try_recv() {
-                // Move the player
-                if let Some(ref player_id) = client.player_id {
-                    wanted_player_moves.push((player_id.clone(), index));
-                }
+        }
+        for command in self.commands.join_commands().into_iter() {
+            match command {
+                Command::Join(id, request) => self.join_client(&id, request)?,
+                Command::Connect(..) | Command::Leave(..) => {}
             }
         }
+        let mut wanted_client_moves = self.commands.moves();
+        for id in disconnections.iter() {
+            wanted_client_moves.remove(id);
+        }
+
+        // Find the players to move
+        let wanted_player_moves = wanted_client_moves
+            .into_iter()
+            .filter_map(|(id, index)| {
+                let player_id = self.clients.get(&id)?.player_id?;
+                Some((player_id, index))
+            })
+            .collect::<Vec<_>>();
 
         // Apply the moves and move them into an array of the moves that were actually executed
         for (id, index) in wanted_player_moves.into_iter() {
@@ -207,24 +220,92 @@ impl Game {
         Ok(())
     }
 
-    fn update_gaps(&mut self) -> Result<(), GameError> {
-        const GAP_LIFE: u64 = 7500;
+    fn join_client(&mut self, id: &ClientId, request: JoinRequest) -> Result<(), GameError> {
+        let can_spawn = self.can_spawn(time_milliseconds());
+        let client = match self.clients.get_mut(id) {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+
+        // Validate the IDs
+        client.validate_player_ids(&self.map);
+
+        // Make sure not already in the game
+        if client.player_id != None {
+            return Ok(());
+        }
+
+        // Wait for the next round
+        if !can_spawn {
+            client.send_join_failed(JoinError::BetweenRounds);
+            return Ok(());
+        }
+
+        let username = match Player::clean_username(&request.username) {
+            Some(username) => username,
+            None => {
+                client.send_join_failed(JoinError::InvalidUsername);
+                return Ok(());
+            }
+        };
+
+        // Find the class
+        let class = match request.class_id {
+            Some(ref class_id) => match class_with_id(class_id) {
+                Some(class) if class.is_unlocked(client.best_points) => class,
+                Some(_) => {
+                    client.send_join_failed(JoinError::LockedClass);
+                    return Ok(());
+                }
+                None => {
+                    client.send_join_failed(JoinError::UnknownClass);
+                    return Ok(());
+                }
+            },
+            None => random_class(client.best_points),
+        };
+
+        // Spawn the user
+        let index = match self.map.spawn_position() {
+            Some(index) => index,
+            None => {
+                client.send_join_failed(JoinError::NoRoom);
+                return Ok(());
+            }
+        };
+        let player = Entity::new(
+            index,
+            EntityKind::Player(Player::new(username, class.clone(), request.color)),
+        );
+        let player_id = self.map.insert_entity(player)?;
+
+        // Update the client
+        client.player_id = Some(player_id);
 
-        let current_time = time_milliseconds();
+        // Send the join along with the classes to pick from next time
+        client.send_join(player_id.clone(), serialize_classes(client.best_points));
 
-        // Find gaps to destroy
-        let mut wanted_destroys = Vec::new();
-        for (id, entity) in self.map.entities() {
-            if let &EntityKind::Gap { ref spawn_time, .. } = entity.kind() {
-                if spawn_time + GAP_LIFE <= current_time {
-                    wanted_destroys.push(id.clone());
+        Ok(())
+    }
+
+    fn update_tiles(&mut self) -> Result<(), GameError> {
+        let now = time_milliseconds();
+
+        // Tick the tiles and find the ones that are expired
+        let mut expired = Vec::new();
+        for (id, entity) in self.map.entities_mut() {
+            let spawn_time = entity.spawn_time();
+            if let EntityKind::Tile(tile) = entity.kind_mut() {
+                tile.on_tick(now);
+                if tile.is_expired(spawn_time, now) {
+                    expired.push(*id);
                 }
             }
         }
 
-        // Destroy the gaps
-        for id in wanted_destroys.into_iter() {
-            self.map.remove_entity_with_id(&id);
+        // Destroy the expired tiles
+        for id in expired.into_iter() {
+            self.map.remove_entity_with_id(&id)?;
         }
 
         Ok(())
@@ -262,94 +343,65 @@ impl Game {
             return Err(GameError::MissingEntity);
         };
 
-        // Determine what to squish
-        enum SquishAction {
-            None,
-            KillPlayer { id: EntityId },
-            AbsorbOrb { id: EntityId, points: u64 },
-        }
-        let squish_action =
-            if let Some((id_at_target, entity_at_target)) = self.map.entity_at(target) {
-                // Make sure it's not this player
-                match entity_at_target.kind() {
-                    &EntityKind::Gap { .. } => SquishAction::KillPlayer {
-                        id: player_id.clone(),
-                    },
-                    &EntityKind::Player(_) => SquishAction::KillPlayer {
-                        id: id_at_target.clone(),
-                    },
-                    &EntityKind::PointOrb { ref points } => SquishAction::AbsorbOrb {
-                        id: id_at_target.clone(),
-                        points: points.clone_value(),
-                    },
-                }
-            } else {
-                SquishAction::None
+        // Find out what happens to the player at the target; players there are squished to make
+        // room like a consumed tile
+        let target_entity = self.map.entity_at(target).map(|(id, entity)| {
+            let action = match entity.kind() {
+                EntityKind::Player(_) => EnterAction::Consume,
+                EntityKind::Tile(tile) => tile.enter_action(),
             };
+            (*id, action)
+        });
 
-        // Move the player
+        // Set player to moved
         let old_index = if let Some(entity) = self.map.entity_with_id_mut(player_id) {
-            // Set player to moved
             match entity.kind_mut() {
                 &mut EntityKind::Player(ref mut player) => player.moved(),
                 _ => return Err(GameError::InvalidEntityKind),
             }
 
             // Save the old index
-            let old_index = entity.index().clone();
-
-            // Move the player
-            entity.move_to(target);
-
-            old_index
+            entity.index().clone()
         } else {
             return Err(GameError::MissingEntity);
         };
 
-        // Handle the squish action
-        match squish_action {
-            SquishAction::None => {}
-            SquishAction::KillPlayer { id } => {
-                // Get the point count
-                let point_count = if let Some(entity) = self.map.entity_with_id(&id) {
-                    if let &EntityKind::Player(ref player) = entity.kind() {
-                        player.point_count().clone()
-                    } else {
-                        return Err(GameError::InvalidEntityKind);
+        // Handle what was at the target; whatever is squished is removed so the target is free
+        let mut squished_points = None;
+        if let Some((target_id, action)) = target_entity {
+            // Let the tile affect the player
+            self.map
+                .with_entity_pair_mut(&target_id, player_id, |target_entity, player_entity| {
+                    if let (EntityKind::Tile(tile), EntityKind::Player(player)) =
+                        (target_entity.kind_mut(), player_entity.kind_mut())
+                    {
+                        tile.on_enter(player);
                     }
-                } else {
-                    return Err(GameError::MissingEntity);
-                };
+                });
 
-                // Spawn the points
-                self.spawn_points(point_count, target)?;
-
-                // Remove the entity
-                self.map.remove_entity_with_id(&id)?;
+            // Remove whatever was used up or squished; squished players drop their points
+            let removed_id = match action {
+                EnterAction::Consume => target_id,
+                EnterAction::Squish => *player_id,
+            };
+            if let Some(EntityKind::Player(player)) = self.map.entity_kind_with_id(&removed_id) {
+                squished_points = Some(*player.point_count());
             }
-            SquishAction::AbsorbOrb { id, points } => {
-                // Give the points and destroy the orb
-                if let Some(&mut EntityKind::Player(ref mut player)) =
-                    self.map.entity_kind_with_id_mut(player_id)
-                {
-                    player.give_points(points);
-                } else {
-                    return Err(GameError::MissingEntity);
-                }
+            self.map.remove_entity_with_id(&removed_id)?;
+        }
 
-                // Destroy the orb
-                self.map.remove_entity_with_id(&id)?;
-            }
-        };
+        // Move the player, unless it was squished itself
+        if self.map.entity_with_id(player_id).is_some() {
+            self.map.move_to(player_id, target)?;
+        }
+
+        // Spawn the points of the squished player
+        if let Some(point_count) = squished_points {
+            self.spawn_points(point_count, target)?;
+        }
 
         // Spawn a gap where the player was
-        let gap = Entity::new(
-            old_index,
-            EntityKind::Gap {
-                spawner: player_id.clone(),
-                spawn_time: time_milliseconds(),
-            },
-        );
+        let gap = Entity::new(old_index, EntityKind::Tile(Box::new(Gap)));
         self.map.insert_entity(gap)?;
 
         Ok(())
@@ -377,12 +429,9 @@ impl Game {
 
             // Update or create an orb at the index
             match self.map.entity_kind_at_mut(&tmp_index) {
-                Some(&mut EntityKind::PointOrb { ref mut points }) => {
-                    points.get_mut().add_assign(POINT_CLUMP);
-                    Ok(())
-                }
-                Some(&mut EntityKind::Gap { .. }) => {
-                    // Points don't spawn if on gap
+                Some(&mut EntityKind::Tile(ref mut tile)) => {
+                    // Orbs take the points; points don't spawn on other tiles
+                    tile.add_points(POINT_CLUMP);
                     Ok(())
                 }
                 _ => Err(()),
@@ -391,9 +440,7 @@ impl Game {
                 // Create an orb and remove the map index from the result
                 let orb = Entity::new(
                     tmp_index.clone(),
-                    EntityKind::PointOrb {
-                        points: IncrementalValue::new(POINT_CLUMP),
-                    },
+                    EntityKind::Tile(Box::new(PointOrb::new(POINT_CLUMP))),
                 );
                 self.map.insert_entity(orb).map(|_| ())
             })?;
@@ -403,53 +450,163 @@ impl Game {
     }
 }
 
-/* Bots */
-lazy_static::lazy_static! {
-    static ref BOT_NAMES: Vec<&'static str> = {
-        vec![
-            "test name 1",
-            "test name 2",
-            "another bot name"
-        ]
-    };
+/* Rounds */
+impl Game {
+    /// Time between leaderboard updates in milliseconds
+    const LEADERBOARD_INTERVAL: u64 = 1000;
+
+    /// If players can spawn; they can't between rounds or while the map is shrinking.
+    fn can_spawn(&self, now: u64) -> bool {
+        match self.round {
+            Some(ref round) => round.is_playing() && !round.is_finale(now),
+            None => true,
+        }
+    }
+
+    fn update_round(&mut self, now: u64) {
+        let event = match self.round {
+            Some(ref mut round) => round.update(now),
+            None => None,
+        };
+        match event {
+            Some(RoundEvent::Ended) => self.end_round(),
+            Some(RoundEvent::Started) => self.leaderboard_time = 0,
+            None => {}
+        }
+    }
+
+    /// Sends the results and clears the map for the next round.
+    fn end_round(&mut self) {
+        let number = self.round.as_ref().map_or(0, |round| round.number());
+        let standings = standings(&self.map);
+        let winner = match standings.first() {
+            Some(&(id, username, points)) => {
+                Value::Array(vec![id.into(), username.into(), points.into()])
+            }
+            None => Value::Nil,
+        };
+        let results = Value::Map(vec![
+            ("round".into(), number.into()),
+            ("winner".into(), winner),
+            ("standings".into(), serialize_standings(&standings)),
+        ]);
+        for client in self.clients.values_mut() {
+            client.send_round_results(results.clone());
+            client.player_id = None;
+        }
+
+        // Everyone starts over in the next round
+        self.map.clear();
+        self.bots.clear();
+        self.leaderboard_time = 0;
+    }
+
+    /// Sends the leaderboard and the time left in the round every so often.
+    fn update_leaderboard(&mut self, now: u64) {
+        if now < self.leaderboard_time + Game::LEADERBOARD_INTERVAL {
+            return;
+        }
+        self.leaderboard_time = now;
+
+        let leaderboard = Value::Map(vec![
+            (
+                "round".into(),
+                self.round
+                    .as_ref()
+                    .map_or(Value::Nil, |round| round.serialize(now)),
+            ),
+            ("leaders".into(), serialize_standings(&standings(&self.map))),
+        ]);
+        for client in self.clients.values() {
+            client.send_leaderboard(leaderboard.clone());
+        }
+    }
 }
 
+/* Bots */
 impl Game {
-    fn update_bots(&mut self) -> Result<(), GameError> {
+    /// Part of the tick period the bots can spend planning their moves, as a divisor (20 ms at the
+    /// default 5 ticks per second)
+    const BOT_PLANNING_SHARE: u32 = 10;
+
+    /// Most time a single bot can spend planning its move
+    const BOT_MOVE_PLANNING_TIME: Duration = Duration::from_millis(2);
+
+    fn update_bots(&mut self, spawning: bool) -> Result<(), GameError> {
         // Find the bots still alive
-        let mut bot_ids = Vec::new();
-        for id in self.bot_ids.drain(..) {
-            if self.map.entity_with_id(&id).is_some() {
-                bot_ids.push(id);
+        let mut bots = Vec::new();
+        for bot in self.bots.drain(..) {
+            if self.map.entity_with_id(&bot.id).is_some() {
+                bots.push(bot);
+            }
+        }
+
+        // Make room for new players one bot at a time; only clients playing count, not the ones
+        // spectating or still picking a class
+        let map = &self.map;
+        let human_count = self
+            .clients
+            .values()
+            .filter(|client| {
+                client
+                    .player_id
+                    .is_some_and(|id| map.entity_with_id(&id).is_some())
+            })
+            .count();
+        let bot_count = BOT_CONFIG.bot_count(human_count);
+        if bots.len() > bot_count {
+            if let Some(bot) = bots.pop() {
+                self.map.remove_entity_with_id(&bot.id)?;
             }
         }
 
         // Generate the bots
-        for _ in bot_ids.len()..self.bot_count {
-            // Find a bot name
-            let username: String = rand::thread_rng().choose(&BOT_NAMES).unwrap().to_string();
-
-            // Create the entity
-            let mut entity = Entity::new(
-                self.map.spawn_position(),
-                EntityKind::Player(Player::new(username, PlayerClass::random())),
+        let spawn_count = if spawning { bot_count } else { 0 };
+        for _ in bots.len()..spawn_count {
+            let index = match self.map.spawn_position() {
+                Some(index) => index,
+                None => break,
+            };
+
+            // Create the entity; bots can play any class
+            let entity = Entity::new(
+                index,
+                EntityKind::Player(Player::new_bot(
+                    BOT_CONFIG.random_name(),
+                    random_class(u64::MAX).clone(),
+                )),
             );
-            let bot_id = self.map.insert_entity(entity)?;
-            bot_ids.push(bot_id);
+            let id = self.map.insert_entity(entity)?;
+            bots.push(Bot {
+                id,
+                difficulty: BOT_CONFIG.random_difficulty(),
+            });
         }
 
         // Update the bots
-        for id in bot_ids.iter() {
-            self.update_bot(id)?;
+        let threats = ThreatMap::build(&self.map);
+        let planning_deadline = Instant::now() + self.bot_planning_time;
+        for bot in bots.iter() {
+            let deadline = Instant::min(
+                Instant::now() + Game::BOT_MOVE_PLANNING_TIME,
+                planning_deadline,
+            );
+            self.update_bot(bot, &threats, deadline)?;
         }
 
-        // Save the bot IDs
-        self.bot_ids = bot_ids;
+        // Save the bots
+        self.bots = bots;
 
         Ok(())
     }
 
-    pub fn update_bot(&mut self, id: &EntityId) -> Result<(), GameError> {
+    pub fn update_bot(
+        &mut self,
+        bot: &Bot,
+        threats: &ThreatMap,
+        deadline: Instant,
+    ) -> Result<(), GameError> {
+        let id = &bot.id;
         let move_index = if let Some(entity) = self.map.entity_with_id(id) {
             if let &EntityKind::Player(ref player) = entity.kind() {
                 // Don't do anything if not ready to move
@@ -457,7 +614,14 @@ impl Game {
                     return Ok(());
                 }
 
-                if let Some(position) = self.find_bot_move_position(entity.index(), player) {
+                if let Some(position) = bot.difficulty.find_move(
+                    &self.map,
+                    threats,
+                    id,
+                    entity.index(),
+                    player,
+                    deadline,
+                ) {
                     position
                 } else {
                     // If we reach this point, the a good move position couldn't be found.
@@ -481,58 +645,4 @@ impl Game {
 
         Ok(())
     }
-
-    pub fn find_bot_move_position(
-        &self,
-        base_index: &MapIndex,
-        player: &Player,
-    ) -> Option<MapIndex> {
-        // How many times it'll choose a random position and see if it should move there
-        const MOVE_TRIES: usize = 5;
-
-        // Ranking for how good different move positions are; 0 = don't move there
-        const GAP_RANK: u8 = 0;
-        const EMPTY_RANK: u8 = 1;
-        const POINT_ORB_RANK: u8 = 2;
-        const PLAYER_RANK: u8 = 3;
-
-        let mut tmp_index = MapIndex::default();
-        let mut best_move = MapIndex::default();
-        let mut best_rank = 0; // See ranks above
-        for i in 0..MOVE_TRIES {
-            // Find the index
-            let rand_relative_index = rand::thread_rng()
-                .choose(&player.player_class().move_positions())
-                .unwrap();
-            tmp_index.clone_from(base_index);
-            tmp_index.add(rand_relative_index);
-
-            // Ensure within map
-            if !self.map.index_within_bounds(&tmp_index) {
-                continue;
-            }
-
-            // Rank the index
-            let rank = match self.map.entity_kind_at(&tmp_index) {
-                Some(e) => match e {
-                    &EntityKind::Player(_) => PLAYER_RANK,
-                    &EntityKind::Gap { .. } => GAP_RANK,
-                    &EntityKind::PointOrb { .. } => POINT_ORB_RANK,
-                },
-                None => EMPTY_RANK,
-            };
-            if rank > best_rank {
-                best_move.clone_from(&tmp_index);
-                best_rank = rank;
-            }
-        }
-
-        // Return the best move, if one was found (this makes sure it's not jumping on a gap & makes
-        // sure a position was actually chosen)
-        if best_rank > 0 {
-            Some(best_move)
-        } else {
-            None
-        }
-    }
 }
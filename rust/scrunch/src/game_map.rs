@@ -1,17 +1,20 @@
 use rand;
 use rand::distributions::{IndependentSample, Range};
 use rmpv::Value;
+use serde::Deserialize;
 use std::collections::HashMap;
 
 use crate::entities::{Entity, EntityId, EntityKind};
 use crate::game::GameError;
+use crate::map_config::{OutsideRule, MAP_CONFIG};
 use crate::utils::Counter;
 
 /// Identifies the number component on the map
 pub type IndexType = i64;
 
-/// Used to identify positions within the map.
-#[derive(PartialEq, Eq, Hash, Debug, Clone, Default)]
+/// Used to identify positions within the map. Deserialized from `[x, y]`.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Default, Deserialize)]
+#[serde(from = "(IndexType, IndexType)")]
 pub struct MapIndex {
     pub x: IndexType,
     pub y: IndexType,
@@ -32,6 +35,12 @@ impl MapIndex {
     }
 }
 
+impl From<(IndexType, IndexType)> for MapIndex {
+    fn from((x, y): (IndexType, IndexType)) -> MapIndex {
+        MapIndex::new(x, y)
+    }
+}
+
 impl MapIndex {
     // TODO: implement std::ops::*
     pub fn add(&mut self, index: &MapIndex) -> &mut Self {
@@ -61,11 +70,23 @@ pub struct GameMap {
     /// The entities in the map
     map: HashMap<EntityId, Entity>,
 
+    /// The entity at each index; there can only be one entity per index
+    occupancy: HashMap<MapIndex, EntityId>,
+
     /// Counter for the entity id
     entity_id: Counter<EntityId>,
 
     /// List of entities that have been destroyed within the last update.
     destroyed_entities: HashMap<EntityId, Entity>,
+
+    /// Size the map is growing or shrinking to
+    target_size: IndexType,
+
+    /// The last time the border moved in
+    shrink_time: u64,
+
+    /// Largest the map can be, no matter where the players are
+    size_limit: Option<IndexType>,
 }
 
 impl GameMap {
@@ -73,8 +94,12 @@ impl GameMap {
         GameMap {
             map_size: 0,
             map: HashMap::new(),
+            occupancy: HashMap::new(),
             entity_id: Counter::new(0, 1),
             destroyed_entities: HashMap::new(),
+            target_size: 0,
+            shrink_time: 0,
+            size_limit: None,
         }
     }
 
@@ -95,31 +120,57 @@ impl GameMap {
         self.map_size
     }
 
-    pub fn update_map_size(&mut self) {
-        const BASE_MAP_SIZE: IndexType = 4; // Minimum size for the map with 0 players
-        const UNITS_PER_PLAYER: IndexType = 1; // How much the map grows for each player
+    /// Size the map is growing or shrinking to
+    pub fn target_size(&self) -> IndexType {
+        self.target_size
+    }
 
+    /// Grows the map to fit the players right away, but only shrinks it one step at a time so
+    /// players have time to get away from the border.
+    pub fn update_map_size(&mut self, now: u64) {
         // Count the players
-        let player_count: IndexType = self.map.values().fold(0, |sum, e| match e.kind() {
-            &EntityKind::Player(_) => sum + 1,
-            _ => sum,
-        });
-
-        // Count the players and find the one furthest from the center
-        let mut furthest_player = -1;
-        for (_, entity) in self.entities().iter() {
-            if let &EntityKind::Player(_) = entity.kind() {
-                // Find the distance
-                let distance = IndexType::max(entity.index().x.abs(), entity.index().y.abs());
-                if distance > furthest_player {
-                    furthest_player = distance;
+        let (mut human_count, mut bot_count) = (0, 0);
+        for entity in self.map.values() {
+            if let EntityKind::Player(player) = entity.kind() {
+                if player.is_bot() {
+                    bot_count += 1;
+                } else {
+                    human_count += 1;
                 }
             }
         }
 
-        // Shrink map to the base size or the furthest player
-        let target_map_size = BASE_MAP_SIZE + player_count * UNITS_PER_PLAYER;
-        self.map_size = IndexType::max(target_map_size, furthest_player);
+        // Find the size to end up at, keeping within the limit
+        self.target_size = MAP_CONFIG.target_size(human_count, bot_count);
+        if let Some(size_limit) = self.size_limit {
+            self.target_size = IndexType::min(self.target_size, size_limit);
+        }
+
+        // Grow right away
+        if self.target_size >= self.map_size {
+            self.map_size = self.target_size;
+            self.shrink_time = now;
+            return;
+        }
+
+        // The limit shrinks on its own schedule, so it's never crossed
+        if let Some(size_limit) = self.size_limit {
+            if size_limit < self.map_size {
+                self.map_size = size_limit;
+                self.shrink_time = now;
+            }
+        }
+
+        // Move the border in a step at a time
+        let shrink_due = now >= self.shrink_time + MAP_CONFIG.shrink_interval;
+        if self.map_size > self.target_size && shrink_due {
+            self.map_size -= 1;
+            self.shrink_time = now;
+        }
+    }
+
+    pub fn set_size_limit(&mut self, size_limit: Option<IndexType>) {
+        self.size_limit = size_limit;
     }
 
     pub fn index_within_bounds(&self, index: &MapIndex) -> bool {
@@ -135,6 +186,11 @@ impl GameMap {
         &self.map
     }
 
+    /// Entities can be changed in place, but only moved through `move_to`.
+    pub fn entities_mut(&mut self) -> impl Iterator<Item = (&EntityId, &mut Entity)> {
+        self.map.iter_mut()
+    }
+
     pub fn destroyed_entities(&self) -> &HashMap<EntityId, Entity> {
         &self.destroyed_entities
     }
@@ -149,11 +205,12 @@ impl GameMap {
         }
 
         // Check if there's an entity there
-        if let Some(_) = self.entity_at(entity.index()) {
+        if self.occupancy.contains_key(entity.index()) {
             return Err(GameError::EntityAlreadyAtIndex);
         }
 
         // Add the entity
+        self.occupancy.insert(entity.index().clone(), id);
         self.map.insert(id, entity);
 
         Ok(id)
@@ -162,6 +219,7 @@ impl GameMap {
     pub fn remove_entity_with_id(&mut self, id: &EntityId) -> Result<(), GameError> {
         // Attempt to remove the item from the map and add to to the destroyed entities
         if let Some(entity) = self.map.remove(id) {
+            self.occupancy.remove(entity.index());
             self.destroyed_entities.insert(id.clone(), entity);
             Ok(())
         } else {
@@ -169,40 +227,68 @@ impl GameMap {
         }
     }
 
-    pub fn entity_at(&self, index: &MapIndex) -> Option<(&EntityId, &Entity)> {
-        // Check every entity to see if it matches the index
-        for (id, entity) in self.map.iter() {
-            if entity.index() == index {
-                return Some((id, &entity));
+    /// Handles the entities left outside the map after it shrank. Players are pushed back inside
+    /// or squished depending on the map config; everything else is removed.
+    pub fn enforce_border(&mut self) {
+        let outside = self
+            .map
+            .iter()
+            .filter(|(_, entity)| !self.index_within_bounds(entity.index()))
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        for id in outside.iter() {
+            let pushed_index = match self.map.get(id) {
+                Some(entity) if MAP_CONFIG.outside == OutsideRule::Push => match entity.kind() {
+                    EntityKind::Player(_) => self.closest_free_index(entity.index()),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match pushed_index {
+                Some(index) => silence!(self.move_to(id, &index)),
+                None => silence!(self.remove_entity_with_id(id)),
             }
         }
+    }
 
-        // Otherwise return none
-        None
+    /// Removes every entity in the map.
+    pub fn clear(&mut self) {
+        self.occupancy.clear();
+        self.destroyed_entities.extend(self.map.drain());
     }
 
-    pub fn entity_kind_at(&self, index: &MapIndex) -> Option<&EntityKind> {
-        // Check every entity to see if it matches the index
-        for (_, entity) in self.map.iter() {
-            if entity.index() == index {
-                return Some(entity.kind());
-            }
+    /// Moves an entity to an empty index. Entities must always be moved through the map so it
+    /// knows where they are.
+    pub fn move_to(&mut self, id: &EntityId, target: &MapIndex) -> Result<(), GameError> {
+        // Make sure the target is free
+        match self.occupancy.get(target) {
+            Some(id_at_target) if id_at_target == id => return Err(GameError::SameMovePosition),
+            Some(_) => return Err(GameError::EntityAlreadyAtIndex),
+            None => {}
         }
 
-        // Otherwise return none
-        None
+        // Move the entity
+        let entity = self.map.get_mut(id).ok_or(GameError::MissingEntity)?;
+        self.occupancy.remove(entity.index());
+        self.occupancy.insert(target.clone(), id.clone());
+        entity.move_to(target);
+
+        Ok(())
     }
 
-    pub fn entity_kind_at_mut(&mut self, index: &MapIndex) -> Option<&mut EntityKind> {
-        // Check every entity to see if it matches the index
-        for (_, entity) in self.map.iter_mut() {
-            if entity.index() == index {
-                return Some(entity.kind_mut());
-            }
-        }
+    pub fn entity_at(&self, index: &MapIndex) -> Option<(&EntityId, &Entity)> {
+        self.occupancy
+            .get(index)
+            .and_then(|id| self.map.get_key_value(id))
+    }
 
-        // Otherwise return none
-        None
+    pub fn entity_kind_at(&self, index: &MapIndex) -> Option<&EntityKind> {
+        self.entity_at(index).map(|(_, entity)| entity.kind())
+    }
+
+    pub fn entity_kind_at_mut(&mut self, index: &MapIndex) -> Option<&mut EntityKind> {
+        let id = self.occupancy.get(index)?;
+        self.map.get_mut(id).map(|entity| entity.kind_mut())
     }
 
     pub fn entity_with_id(&self, id: &EntityId) -> Option<&Entity> {
@@ -220,18 +306,190 @@ impl GameMap {
     pub fn entity_with_id_mut(&mut self, id: &EntityId) -> Option<&mut Entity> {
         self.map.get_mut(id)
     }
+
+    /// Calls the function with two different entities borrowed at once, such as a tile and the
+    /// player moving onto it; `None` if either is missing.
+    pub fn with_entity_pair_mut<F, R>(&mut self, a: &EntityId, b: &EntityId, f: F) -> Option<R>
+    where
+        F: FnOnce(&mut Entity, &mut Entity) -> R,
+    {
+        if a == b {
+            return None;
+        }
+
+        // Take the first entity out so the second can be borrowed too
+        let mut first = self.map.remove(a)?;
+        let result = self.map.get_mut(b).map(|second| f(&mut first, second));
+        self.map.insert(*a, first);
+        result
+    }
 }
 
 // Utils
 impl GameMap {
-    pub fn spawn_position(&self) -> MapIndex {
+    /// Finds a random empty index to spawn at; `None` if the map is too crowded to find one.
+    pub fn spawn_position(&self) -> Option<MapIndex> {
+        const SPAWN_TRIES: usize = 100;
+
         // Search for a valid index until found
-        loop {
+        for _ in 0..SPAWN_TRIES {
             let index = MapIndex::random(self.map_size);
             if self.entity_at(&index).is_none() {
-                return index;
+                return Some(index);
             }
         }
+        None
+    }
+
+    /// Finds the empty index inside the map closest to where the index is pushed to at the border;
+    /// `None` if there isn't one nearby.
+    fn closest_free_index(&self, index: &MapIndex) -> Option<MapIndex> {
+        const PUSH_RANGE: IndexType = 2; // How far from the border to look for room
+
+        let size = self.map_size;
+        let border_index = MapIndex::new(index.x.clamp(-size, size), index.y.clamp(-size, size));
+        (-PUSH_RANGE..=PUSH_RANGE)
+            .flat_map(|x| (-PUSH_RANGE..=PUSH_RANGE).map(move |y| MapIndex::new(x, y)))
+            .map(|offset| {
+                let mut candidate = border_index.clone();
+                candidate.add(&offset);
+                candidate
+            })
+            .filter(|candidate| {
+                self.index_within_bounds(candidate) && !self.occupancy.contains_key(candidate)
+            })
+            .min_by_key(|candidate| {
+                (candidate.x - border_index.x).abs() + (candidate.y - border_index.y).abs()
+            })
     }
 }
 
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::entities::Player;
+    use crate::player_classes::random_class;
+    use crate::tiles::PointOrb;
+
+    fn orb(x: IndexType, y: IndexType) -> Entity {
+        Entity::new(
+            MapIndex::new(x, y),
+            EntityKind::Tile(Box::new(PointOrb::new(1))),
+        )
+    }
+
+    fn player(x: IndexType, y: IndexType) -> Entity {
+        Entity::new(
+            MapIndex::new(x, y),
+            EntityKind::Player(Player::new_bot(
+                "bot".to_string(),
+                random_class(u64::MAX).clone(),
+            )),
+        )
+    }
+
+    /// The id of the entity at the index.
+    fn id_at(map: &GameMap, x: IndexType, y: IndexType) -> Option<EntityId> {
+        map.entity_at(&MapIndex::new(x, y)).map(|(id, _)| *id)
+    }
+
+    #[test]
+    fn test_insert_entity() {
+        let mut map = GameMap::new();
+        let id = map.insert_entity(orb(0, 0)).unwrap();
+        assert_eq!(id_at(&map, 0, 0), Some(id));
+
+        // Only one entity fits at each index
+        assert!(matches!(
+            map.insert_entity(orb(0, 0)),
+            Err(GameError::EntityAlreadyAtIndex)
+        ));
+        assert_eq!(map.entities().len(), 1);
+        assert_eq!(id_at(&map, 0, 0), Some(id));
+    }
+
+    #[test]
+    fn test_move_to() {
+        let mut map = GameMap::new();
+        let a = map.insert_entity(orb(0, 0)).unwrap();
+        let b = map.insert_entity(orb(1, 0)).unwrap();
+
+        assert!(matches!(
+            map.move_to(&a, &MapIndex::new(0, 0)),
+            Err(GameError::SameMovePosition)
+        ));
+        assert!(matches!(
+            map.move_to(&a, &MapIndex::new(1, 0)),
+            Err(GameError::EntityAlreadyAtIndex)
+        ));
+        assert_eq!(id_at(&map, 1, 0), Some(b));
+
+        // The old index is freed for the next entity
+        map.move_to(&a, &MapIndex::new(0, 1)).unwrap();
+        assert_eq!(
+            map.entity_with_id(&a).unwrap().index(),
+            &MapIndex::new(0, 1)
+        );
+        assert_eq!(id_at(&map, 0, 1), Some(a));
+        assert_eq!(id_at(&map, 0, 0), None);
+        map.move_to(&b, &MapIndex::new(0, 0)).unwrap();
+        assert_eq!(id_at(&map, 0, 0), Some(b));
+        assert_eq!(id_at(&map, 1, 0), None);
+    }
+
+    #[test]
+    fn test_remove_entity() {
+        let mut map = GameMap::new();
+        let a = map.insert_entity(orb(0, 0)).unwrap();
+        let b = map.insert_entity(orb(1, 0)).unwrap();
+
+        map.remove_entity_with_id(&a).unwrap();
+        assert_eq!(id_at(&map, 0, 0), None);
+        assert!(map.destroyed_entities().contains_key(&a));
+        assert!(matches!(
+            map.remove_entity_with_id(&a),
+            Err(GameError::InvalidDeletion)
+        ));
+        map.insert_entity(orb(0, 0)).unwrap();
+
+        // Clearing frees every index
+        map.clear();
+        assert!(map.entities().is_empty());
+        assert_eq!(id_at(&map, 0, 0), None);
+        assert_eq!(id_at(&map, 1, 0), None);
+        assert!(map.destroyed_entities().contains_key(&b));
+        map.insert_entity(orb(1, 0)).unwrap();
+    }
+
+    #[test]
+    fn test_enforce_border() {
+        let mut map = GameMap::new();
+        map.map_size = 3;
+        let tile = map.insert_entity(orb(3, 3)).unwrap();
+        let blocker = map.insert_entity(orb(-2, 0)).unwrap();
+        let player = map.insert_entity(player(-3, 0)).unwrap();
+
+        // Shrink the map so the tile and the player are left outside
+        map.map_size = 2;
+        map.enforce_border();
+        assert_eq!(id_at(&map, 3, 3), None);
+        assert!(map.destroyed_entities().contains_key(&tile));
+        assert_eq!(id_at(&map, -2, 0), Some(blocker));
+        assert_eq!(id_at(&map, -3, 0), None);
+
+        match MAP_CONFIG.outside {
+            // Pushed next to the border index, which is taken
+            OutsideRule::Push => {
+                let index = map.entity_with_id(&player).unwrap().index().clone();
+                assert!(map.index_within_bounds(&index));
+                assert_eq!((index.x + 2).abs() + index.y.abs(), 1);
+                assert_eq!(id_at(&map, index.x, index.y), Some(player));
+                assert_eq!(map.entities().len(), 2);
+            }
+            OutsideRule::Kill => {
+                assert!(map.destroyed_entities().contains_key(&player));
+                assert_eq!(map.entities().len(), 1);
+            }
+        }
+    }
+}
@@ -1,22 +1,36 @@
 #[macro_use]
 mod utils;
 
+mod bot_planner;
+mod bots;
 mod entities;
 mod game;
 mod game_map;
 mod incremental_value;
+mod map_config;
 mod network;
+mod player_classes;
 mod rivet;
+mod rounds;
+mod scheduler;
+mod tiles;
 
 fn main() {
+    // Load the classes up front so invalid classes are caught before anyone joins
+    lazy_static::initialize(&player_classes::PLAYER_CLASSES);
+    lazy_static::initialize(&bots::BOT_CONFIG);
+    lazy_static::initialize(&map_config::MAP_CONFIG);
+    lazy_static::initialize(&rounds::ROUND_CONFIG);
+
     // Start the game
-    let game = game::Game::new();
+    let (commands, command_receiver) = network::command_queue();
+    game::Game::start(scheduler::tick_rate(), command_receiver);
 
     rivet::lobby_ready().expect("lobby_ready");
 
-    let game = game.clone();
+    let mut client_id = utils::Counter::new(0, 1);
     ws::listen("0.0.0.0:3000", |out| {
-        network::Client::new(game.clone(), out)
+        network::Client::new(client_id.tick(), commands.clone(), out)
     })
     .unwrap();
 }
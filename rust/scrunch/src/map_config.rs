@@ -0,0 +1,173 @@
+use serde::Deserialize;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::fs;
+use std::io;
+
+use crate::game_map::IndexType;
+
+/// File the map config is loaded from
+const MAP_PATH: &str = "map.toml";
+
+lazy_static::lazy_static! {
+    pub static ref MAP_CONFIG: MapConfig = {
+        match load_map_config(MAP_PATH) {
+            Ok(config) => config,
+            Err(err) => panic!("Invalid map config in {}: {}", MAP_PATH, err),
+        }
+    };
+}
+
+/// Errors that occur when loading the map config
+#[derive(Debug)]
+pub enum MapConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+
+    /// The map needs at least one index around the center
+    NoBaseSize,
+
+    /// The map can't be capped below its base size
+    MaxBelowBase,
+
+    /// Growth has to be a finite number that isn't negative
+    InvalidGrowth,
+}
+
+impl Display for MapConfigError {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            MapConfigError::Io(err) => write!(f, "{}", err),
+            MapConfigError::Toml(err) => write!(f, "{}", err),
+            MapConfigError::NoBaseSize => write!(f, "base size is below 1"),
+            MapConfigError::MaxBelowBase => write!(f, "max size is below the base size"),
+            MapConfigError::InvalidGrowth => {
+                write!(f, "growth per player is negative or not finite")
+            }
+        }
+    }
+}
+
+/// What happens to players left outside the border when it moves in
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutsideRule {
+    /// Moved to the closest free index inside the border, or squished if there isn't one
+    Push,
+
+    /// Squished
+    Kill,
+}
+
+/// Controls how the map grows and shrinks with the players in it
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MapConfig {
+    /// Size of the map with no players
+    base_size: IndexType,
+
+    /// How much the map grows for each player
+    growth_per_player: f64,
+
+    /// If bots make the map grow like human players do
+    count_bots: bool,
+
+    /// Largest the map can grow to
+    max_size: IndexType,
+
+    /// Milliseconds between each step the border moves in while the map shrinks
+    pub shrink_interval: u64,
+
+    /// What happens to players left outside the border
+    pub outside: OutsideRule,
+}
+
+impl MapConfig {
+    /// Size the map should be with this many players and bots in it.
+    pub fn target_size(&self, human_count: usize, bot_count: usize) -> IndexType {
+        let player_count = if self.count_bots {
+            human_count + bot_count
+        } else {
+            human_count
+        };
+        let growth = (player_count as f64 * self.growth_per_player).round() as IndexType;
+        IndexType::min(self.base_size + growth, self.max_size)
+    }
+}
+
+/* Loading */
+pub fn load_map_config(path: &str) -> Result<MapConfig, MapConfigError> {
+    parse_map_config(&fs::read_to_string(path).map_err(MapConfigError::Io)?)
+}
+
+/// Parses and validates the map config.
+pub fn parse_map_config(source: &str) -> Result<MapConfig, MapConfigError> {
+    let config = toml::from_str::<MapConfig>(source).map_err(MapConfigError::Toml)?;
+
+    if config.base_size < 1 {
+        return Err(MapConfigError::NoBaseSize);
+    }
+    if config.max_size < config.base_size {
+        return Err(MapConfigError::MaxBelowBase);
+    }
+    if !config.growth_per_player.is_finite() || config.growth_per_player < 0. {
+        return Err(MapConfigError::InvalidGrowth);
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = "
+        base_size = 4
+        growth_per_player = 0.5
+        count_bots = false
+        max_size = 10
+        shrink_interval = 1000
+        outside = \"push\"
+    ";
+
+    #[test]
+    fn test_parse_map_config() {
+        let config = parse_map_config(CONFIG).unwrap();
+        assert_eq!(config.outside, OutsideRule::Push);
+
+        assert!(matches!(
+            parse_map_config(&CONFIG.replace("base_size = 4", "base_size = 0")),
+            Err(MapConfigError::NoBaseSize)
+        ));
+        assert!(matches!(
+            parse_map_config(&CONFIG.replace("max_size = 10", "max_size = 3")),
+            Err(MapConfigError::MaxBelowBase)
+        ));
+        assert!(matches!(
+            parse_map_config(&CONFIG.replace("0.5", "-1.0")),
+            Err(MapConfigError::InvalidGrowth)
+        ));
+        assert!(matches!(
+            parse_map_config(&CONFIG.replace("0.5", "nan")),
+            Err(MapConfigError::InvalidGrowth)
+        ));
+        assert!(matches!(
+            parse_map_config(&CONFIG.replace("\"push\"", "\"teleport\"")),
+            Err(MapConfigError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn test_target_size() {
+        let config = parse_map_config(CONFIG).unwrap();
+        assert_eq!(config.target_size(0, 0), 4);
+        assert_eq!(config.target_size(4, 0), 6);
+
+        // Bots only count when the config says so
+        assert_eq!(config.target_size(4, 6), 6);
+        let config = parse_map_config(&CONFIG.replace("false", "true")).unwrap();
+        assert_eq!(config.target_size(4, 6), 9);
+
+        // The map stops growing at the max size
+        assert_eq!(config.target_size(40, 0), 10);
+    }
+}
@@ -2,16 +2,19 @@ use rmpv::decode::read_value;
 use rmpv::encode::write_value;
 use rmpv::Value;
 use std::cell::Cell;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Display, Formatter, Result as FormatResult};
 use std::io::Cursor;
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Mutex;
-use ws::{CloseCode, Error, Handler, Handshake, Message, Result as WsResult, Sender as WsSender};
-
-use crate::entities::EntityId;
-use crate::game::GameReference;
+use std::sync::mpsc::{channel, sync_channel, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use ws::{
+    CloseCode, Error, ErrorKind, Handler, Handshake, Message, Result as WsResult,
+    Sender as WsSender,
+};
+
+use crate::entities::{EntityId, EntityKind, Player};
 use crate::game_map::{GameMap, IndexType, MapIndex};
+use crate::player_classes::serialize_classes;
 
 /// Errors that occur when processing messages
 #[derive(Debug)]
@@ -20,6 +23,13 @@ enum MessageError {
     MissingData,
     EventType,
     InvalidPlayerToken,
+    InvalidColor,
+
+    /// The game is too far behind on commands to take another one
+    QueueFull,
+
+    /// The game loop isn't running anymore
+    GameStopped,
 }
 
 impl Display for MessageError {
@@ -31,7 +41,145 @@ impl Display for MessageError {
 /// Type used to identify clients
 pub type ClientId = u64;
 
+/// Maximum number of joins waiting for the game loop
+const COMMAND_QUEUE_CAPACITY: usize = 1024;
+
+/// Messages from the connections to the game loop.
+#[derive(Debug)]
+pub enum Command {
+    /// A new connection was opened
+    Connect(ClientId, ClientHandle),
+
+    /// The client wants to spawn a player
+    Join(ClientId, JoinRequest),
+
+    /// The connection was closed
+    Leave(ClientId),
+}
+
+/// Moves waiting for the game loop; only the latest move from each client is kept
+type MoveSlots = Arc<Mutex<HashMap<ClientId, MapIndex>>>;
+
+/// Sends commands to the game loop. Connections and disconnections go through an unbounded lane so
+/// the socket thread never waits on the game; joins are bounded so a flood of messages can't build
+/// up. Moves replace the client's previous move, so one client sending a lot of them can't crowd
+/// out the others.
+#[derive(Clone)]
+pub struct CommandSender {
+    connections: Sender<Command>,
+    joins: SyncSender<Command>,
+    moves: MoveSlots,
+}
+
+impl CommandSender {
+    /// Registers a connection or its disconnection.
+    fn send_connection(&self, command: Command) {
+        // Only fails if the game loop stopped, in which case there's nothing to register with
+        self.connections.send(command).ok();
+    }
+
+    /// Sends a join without waiting for room in the queue.
+    fn try_send_join(&self, id: ClientId, request: JoinRequest) -> Result<(), MessageError> {
+        match self.joins.try_send(Command::Join(id, request)) {
+            Ok(_) => Ok(()),
+            Err(TrySendError::Full(_)) => Err(MessageError::QueueFull),
+            Err(TrySendError::Disconnected(_)) => Err(MessageError::GameStopped),
+        }
+    }
+
+    /// Replaces the client's waiting move.
+    fn send_move(&self, id: ClientId, index: MapIndex) {
+        lock_moves(&self.moves).insert(id, index);
+    }
+}
+
+pub struct CommandReceiver {
+    connections: Receiver<Command>,
+    joins: Receiver<Command>,
+    moves: MoveSlots,
+}
+
+impl CommandReceiver {
+    /// Takes the waiting connections and disconnections.
+    pub fn connection_commands(&self) -> Vec<Command> {
+        self.connections.try_iter().collect()
+    }
+
+    /// Takes the waiting joins.
+    pub fn join_commands(&self) -> Vec<Command> {
+        self.joins.try_iter().collect()
+    }
+
+    /// Takes the latest move from each client.
+    pub fn moves(&self) -> HashMap<ClientId, MapIndex> {
+        std::mem::take(&mut *lock_moves(&self.moves))
+    }
+}
+
+/// Locks the move slots; a panic while they were locked can't leave a half written move, so
+/// they're still used after one.
+fn lock_moves(moves: &MoveSlots) -> MutexGuard<'_, HashMap<ClientId, MapIndex>> {
+    moves.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Creates the queue the connections send commands to the game loop through.
+pub fn command_queue() -> (CommandSender, CommandReceiver) {
+    let (connections, connection_receiver) = channel();
+    let (joins, join_receiver) = sync_channel(COMMAND_QUEUE_CAPACITY);
+    let moves = MoveSlots::default();
+    (
+        CommandSender {
+            connections,
+            joins,
+            moves: moves.clone(),
+        },
+        CommandReceiver {
+            connections: connection_receiver,
+            joins: join_receiver,
+            moves,
+        },
+    )
+}
+
+/// Sent by the client to spawn a player
+#[derive(Debug)]
+pub struct JoinRequest {
+    pub username: String,
+
+    /// The class to play as; a random unlocked class if `None`
+    pub class_id: Option<String>,
+
+    /// The color to play as; the class's color if `None`
+    pub color: Option<u64>,
+}
+
+/// Reasons a join is turned down; sent back so the client can try again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinError {
+    /// Players can't spawn until the next round starts
+    BetweenRounds,
+    InvalidUsername,
+    UnknownClass,
+    LockedClass,
+
+    /// There's no free index to spawn at
+    NoRoom,
+}
+
+impl JoinError {
+    fn code(&self) -> &'static str {
+        match self {
+            JoinError::BetweenRounds => "betweenRounds",
+            JoinError::InvalidUsername => "invalidUsername",
+            JoinError::UnknownClass => "unknownClass",
+            JoinError::LockedClass => "lockedClass",
+            JoinError::NoRoom => "noRoom",
+        }
+    }
+}
+
 /// Used by the game to
+#[derive(Debug)]
 pub struct ClientHandle {
     out: WsSender,
 
@@ -41,9 +189,8 @@ pub struct ClientHandle {
     /// Index at which the player is spectating from; this is used for if the player dies and doesn't have a position
     pub player_id: Option<EntityId>,
 
-    pub rx_join: Receiver<String>,
-    pub rx_move: Receiver<MapIndex>,
-    pub rx_leave: Receiver<()>,
+    /// The most points the player had in a single life; used to unlock classes
+    pub best_points: u64,
 }
 
 impl SocketSender for ClientHandle {
@@ -64,6 +211,15 @@ impl ClientHandle {
         }
     }
 
+    /// Remembers the player's points so they keep the classes they unlocked after dying.
+    pub fn track_points(&mut self, map: &GameMap) {
+        if let Some(ref player_id) = self.player_id {
+            if let Some(EntityKind::Player(player)) = map.entity_kind_with_id(player_id) {
+                self.best_points = u64::max(self.best_points, *player.point_count());
+            }
+        }
+    }
+
     fn index_in_range(&self, base: &MapIndex, index: &MapIndex) -> bool {
         index.x <= base.x + ClientHandle::VIEW_RANGE
             && index.x >= base.x - ClientHandle::VIEW_RANGE
@@ -141,6 +297,7 @@ impl ClientHandle {
                 .map(|id| Value::from(id.clone()))
                 .collect::<Vec<Value>>()
                 .into(),
+            map.target_size().into(),
         ]);
         self.send_update(message);
 
@@ -161,52 +318,43 @@ impl ClientHandle {
 /// Handles incoming connections; one is created for every individual WebSocket connection created.
 /// This just forwards important messages to the connection itself.
 pub struct Client {
+    id: ClientId,
     out: WsSender,
     is_authed: Cell<bool>,
     is_open: bool,
     rivet_player_token: Mutex<Option<String>>,
 
-    tx_join: Sender<String>,
-    tx_move: Sender<MapIndex>,
-    tx_leave: Sender<()>,
+    commands: CommandSender,
 }
 
 impl Client {
-    pub fn new(game: GameReference, out: WsSender) -> Client {
-        // Create the channels
-        let (tx_join, rx_join) = channel();
-        let (tx_move, rx_move) = channel();
-        let (tx_leave, rx_leave) = channel();
-
+    pub fn new(id: ClientId, commands: CommandSender, out: WsSender) -> Client {
         // Register the connection with the game
-        {
-            let mut game = game.lock().unwrap();
-            game.add_client(ClientHandle {
+        commands.send_connection(Command::Connect(
+            id,
+            ClientHandle {
                 out: out.clone(),
                 watching_entities: HashSet::new(),
                 spectating_index: MapIndex::new(0, 0),
                 player_id: None,
-                rx_join,
-                rx_move,
-                rx_leave,
-            });
-        }
+                best_points: 0,
+            },
+        ));
 
         // Return the server
         Client {
+            id,
             out,
             is_authed: Cell::new(false),
             is_open: false,
             rivet_player_token: Mutex::new(None),
-            tx_join,
-            tx_move,
-            tx_leave,
+            commands,
         }
     }
 
     fn close(&mut self) {
         // Send leave message
-        self.tx_leave.send(()).unwrap();
+        self.commands.send_connection(Command::Leave(self.id));
 
         // Close the socket
         self.out.close(CloseCode::Empty).unwrap();
@@ -233,12 +381,20 @@ impl Handler for Client {
         // Set to open
         self.is_open = true;
 
+        // Let the client pick a class
+        self.send_classes(serialize_classes(0));
+
         Ok(())
     }
 
     fn on_message(&mut self, msg: Message) -> WsResult<()> {
         match self.parse_message(msg) {
             Ok(_) => Ok(()),
+
+            // Nothing can be done with the connection without a game, so close it
+            Err(MessageError::GameStopped) => {
+                Err(Error::new(ErrorKind::Internal, "the game loop stopped"))
+            }
             Err(err) => {
                 // TODO: Send error back, maybe with Err(...)
                 println!("Error: {}", err);
@@ -327,14 +483,25 @@ impl Client {
             return Ok(());
         }
 
-        // TODO: Use `data`
-        // Get the username
-        let username: String = unwrap_data!(data.as_str()).to_string();
+        // Read the join options
+        let data: &Vec<(Value, Value)> = unwrap_data!(data.as_map());
+        let username = unwrap_data!(map_value(data, "username").and_then(|v| v.as_str()));
+        let class_id = map_value(data, "classId").and_then(|v| v.as_str());
+        let color = match map_value(data, "color") {
+            Some(Value::Nil) | None => None,
+            Some(color) => match color.as_u64() {
+                Some(color) if color <= Player::MAX_COLOR => Some(color),
+                _ => return Err(MessageError::InvalidColor),
+            },
+        };
+        let request = JoinRequest {
+            username: username.to_string(),
+            class_id: class_id.map(|id| id.to_string()),
+            color,
+        };
 
         // Send the message
-        self.tx_join.send(username).unwrap();
-
-        Ok(())
+        self.commands.try_send_join(self.id, request)
     }
 
     fn move_player(&self, data: &Value) -> Result<(), MessageError> {
@@ -356,17 +523,27 @@ impl Client {
         };
 
         // Send the message
-        self.tx_move.send(index).unwrap();
-
+        self.commands.send_move(self.id, index);
         Ok(())
     }
 }
 
+/// Finds the value for a key in a MessagePack map.
+fn map_value<'a>(map: &'a [(Value, Value)], key: &str) -> Option<&'a Value> {
+    map.iter()
+        .find(|(k, _)| k.as_str() == Some(key))
+        .map(|(_, v)| v)
+}
+
 /* Socket sender */
 /// Declares the type of message
 pub enum MessageType {
     Join,
     Update,
+    Classes,
+    Leaderboard,
+    RoundResults,
+    JoinFailed,
 }
 
 impl MessageType {
@@ -374,6 +551,10 @@ impl MessageType {
         match self {
             &MessageType::Join => 0,
             &MessageType::Update => 1,
+            &MessageType::Classes => 2,
+            &MessageType::Leaderboard => 3,
+            &MessageType::RoundResults => 4,
+            &MessageType::JoinFailed => 5,
         }
     }
 }
@@ -394,12 +575,36 @@ pub trait SocketSender {
         self.socket_out().send(buf).unwrap();
     }
 
-    fn send_join(&self, player_id: EntityId) {
-        self.send_message(MessageType::Join, Value::from(player_id));
+    /// Sends the player the client controls and the classes to pick from when respawning.
+    fn send_join(&self, player_id: EntityId, classes: Value) {
+        self.send_message(
+            MessageType::Join,
+            Value::Array(vec![Value::from(player_id), classes]),
+        );
+    }
+
+    /// Tells the client why they couldn't join.
+    fn send_join_failed(&self, error: JoinError) {
+        self.send_message(MessageType::JoinFailed, error.code().into());
     }
 
     fn send_update(&self, message: Value) {
         // Generate the data
         self.send_message(MessageType::Update, message);
     }
+
+    /// Sends the classes players can pick from when joining.
+    fn send_classes(&self, classes: Value) {
+        self.send_message(MessageType::Classes, classes);
+    }
+
+    /// Sends the players with the most points and the state of the round.
+    fn send_leaderboard(&self, leaderboard: Value) {
+        self.send_message(MessageType::Leaderboard, leaderboard);
+    }
+
+    /// Sends the final standings once a round is over.
+    fn send_round_results(&self, results: Value) {
+        self.send_message(MessageType::RoundResults, results);
+    }
 }
@@ -0,0 +1,281 @@
+use rand::Rng;
+use rmpv::Value;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::fs;
+use std::io;
+
+use crate::game_map::MapIndex;
+
+/// File the player classes are loaded from
+const CLASSES_PATH: &str = "classes.toml";
+
+lazy_static::lazy_static! {
+    pub static ref PLAYER_CLASSES: Vec<PlayerClass> = {
+        match load_classes(CLASSES_PATH) {
+            Ok(classes) => classes,
+            Err(err) => panic!("Invalid player classes in {}: {}", CLASSES_PATH, err),
+        }
+    };
+}
+
+/// Errors that occur when loading the player classes
+#[derive(Debug)]
+pub enum ClassError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+
+    /// There has to be at least one class
+    NoClasses,
+
+    /// Every class needs a unique ID
+    DuplicateId(String),
+
+    /// The class can't move anywhere
+    NoMovePositions(String),
+
+    /// The class can move to where it already is
+    InvalidMovePosition(String),
+
+    /// New players need at least one class that doesn't have to be unlocked
+    NoStartingClass,
+}
+
+impl Display for ClassError {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            ClassError::Io(err) => write!(f, "{}", err),
+            ClassError::Toml(err) => write!(f, "{}", err),
+            ClassError::NoClasses => write!(f, "no classes"),
+            ClassError::DuplicateId(id) => write!(f, "duplicate class {}", id),
+            ClassError::NoMovePositions(id) => write!(f, "class {} has no move positions", id),
+            ClassError::InvalidMovePosition(id) => {
+                write!(f, "class {} can move to its own position", id)
+            }
+            ClassError::NoStartingClass => write!(f, "every class has to be unlocked"),
+        }
+    }
+}
+
+/// Rules a player has to meet before picking a class
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnlockRule {
+    /// Points the player has to reach in a single life
+    #[serde(default)]
+    points: u64,
+}
+
+impl UnlockRule {
+    pub fn is_met(&self, best_points: u64) -> bool {
+        best_points >= self.points
+    }
+}
+
+/// The class of the player
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlayerClass {
+    /// Identifies the class when joining
+    id: String,
+
+    /// Display name for the class
+    name: String,
+
+    /// Explains how the class moves
+    description: String,
+
+    /// The color for the class
+    color: u64,
+
+    /// Time in between moves, in milliseconds
+    move_wait: u64,
+
+    /// Positions relative to the player's base in which they can move
+    move_positions: Vec<MapIndex>,
+
+    /// Rules to unlock the class; available to everyone if `None`
+    unlock: Option<UnlockRule>,
+}
+
+impl PlayerClass {
+    pub fn move_wait(&self) -> u64 {
+        self.move_wait
+    }
+
+    pub fn move_positions(&self) -> &Vec<MapIndex> {
+        &self.move_positions
+    }
+
+    pub fn valid_move_position(&self, relative_index: &MapIndex) -> bool {
+        self.move_positions.contains(relative_index)
+    }
+
+    /// If a player with this best score can pick the class.
+    pub fn is_unlocked(&self, best_points: u64) -> bool {
+        match self.unlock {
+            Some(ref unlock) => unlock.is_met(best_points),
+            None => true,
+        }
+    }
+}
+
+impl PlayerClass {
+    pub fn serialize(&self) -> Value {
+        // Convert the move positions from a set to an array
+        let move_positions = self
+            .move_positions
+            .iter()
+            .map(|x| x.serialize())
+            .collect::<Vec<Value>>()
+            .into();
+
+        // Convert the data to a map
+        Value::Map(vec![
+            ("id".into(), self.id.clone().into()),
+            ("name".into(), self.name.clone().into()),
+            ("description".into(), self.description.clone().into()),
+            ("color".into(), self.color.into()),
+            ("movePositions".into(), move_positions),
+            ("moveWait".into(), self.move_wait.into()),
+            (
+                "unlockPoints".into(),
+                self.unlock
+                    .as_ref()
+                    .map_or(Value::Nil, |unlock| unlock.points.into()),
+            ),
+        ])
+    }
+}
+
+/// Serializes the classes a player can pick from and whether they unlocked each one.
+pub fn serialize_classes(best_points: u64) -> Value {
+    PLAYER_CLASSES
+        .iter()
+        .map(|class| {
+            let mut data = class.serialize();
+            if let Value::Map(ref mut fields) = data {
+                fields.push(("unlocked".into(), class.is_unlocked(best_points).into()));
+            }
+            data
+        })
+        .collect::<Vec<Value>>()
+        .into()
+}
+
+/* Loading */
+#[derive(Deserialize)]
+struct ClassesFile {
+    #[serde(rename = "class", default)]
+    classes: Vec<PlayerClass>,
+}
+
+pub fn load_classes(path: &str) -> Result<Vec<PlayerClass>, ClassError> {
+    parse_classes(&fs::read_to_string(path).map_err(ClassError::Io)?)
+}
+
+/// Parses and validates the player classes.
+pub fn parse_classes(source: &str) -> Result<Vec<PlayerClass>, ClassError> {
+    let classes = toml::from_str::<ClassesFile>(source)
+        .map_err(ClassError::Toml)?
+        .classes;
+
+    if classes.is_empty() {
+        return Err(ClassError::NoClasses);
+    }
+
+    let mut ids = HashSet::new();
+    for class in classes.iter() {
+        if !ids.insert(class.id.as_str()) {
+            return Err(ClassError::DuplicateId(class.id.clone()));
+        }
+        if class.move_positions.is_empty() {
+            return Err(ClassError::NoMovePositions(class.id.clone()));
+        }
+        if class.move_positions.contains(&MapIndex::new(0, 0)) {
+            return Err(ClassError::InvalidMovePosition(class.id.clone()));
+        }
+    }
+
+    if !classes.iter().any(|class| class.is_unlocked(0)) {
+        return Err(ClassError::NoStartingClass);
+    }
+
+    Ok(classes)
+}
+
+/* Lookup */
+pub fn class_with_id(id: &str) -> Option<&'static PlayerClass> {
+    PLAYER_CLASSES.iter().find(|class| class.id == id)
+}
+
+/// Picks a random class out of the ones a player with this best score has unlocked.
+pub fn random_class(best_points: u64) -> &'static PlayerClass {
+    let unlocked = PLAYER_CLASSES
+        .iter()
+        .filter(|class| class.is_unlocked(best_points))
+        .collect::<Vec<_>>();
+    rand::thread_rng()
+        .choose(&unlocked)
+        .expect("there is always a starting class")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn class(id: &str, move_positions: &str, unlock: &str) -> String {
+        format!(
+            "[[class]]
+            id = \"{}\"
+            name = \"Test\"
+            description = \"\"
+            color = 0xffffff
+            move_wait = 500
+            move_positions = {}
+            {}
+            ",
+            id, move_positions, unlock
+        )
+    }
+
+    #[test]
+    fn test_parse_classes() {
+        let rook = class("rook", "[[1, 0], [0, 1]]", "");
+        let locked = class("locked", "[[2, 0]]", "unlock = { points = 50 }");
+        let classes = parse_classes(&format!("{}{}", rook, locked)).unwrap();
+        assert_eq!(classes.len(), 2);
+        assert!(classes[0].is_unlocked(0));
+        assert!(!classes[1].is_unlocked(49));
+        assert!(classes[1].is_unlocked(50));
+        assert!(classes[0].valid_move_position(&MapIndex::new(0, 1)));
+        assert!(!classes[0].valid_move_position(&MapIndex::new(1, 1)));
+    }
+
+    #[test]
+    fn test_invalid_classes() {
+        let rook = class("rook", "[[1, 0]]", "");
+        assert!(matches!(parse_classes(""), Err(ClassError::NoClasses)));
+        assert!(matches!(
+            parse_classes(&format!("{}{}", rook, rook)),
+            Err(ClassError::DuplicateId(ref id)) if id == "rook"
+        ));
+        assert!(matches!(
+            parse_classes(&class("still", "[]", "")),
+            Err(ClassError::NoMovePositions(ref id)) if id == "still"
+        ));
+        assert!(matches!(
+            parse_classes(&class("still", "[[1, 0], [0, 0]]", "")),
+            Err(ClassError::InvalidMovePosition(ref id)) if id == "still"
+        ));
+        assert!(matches!(
+            parse_classes(&class("locked", "[[1, 0]]", "unlock = { points = 10 }")),
+            Err(ClassError::NoStartingClass)
+        ));
+        assert!(matches!(
+            parse_classes(&class("rook", "[[1, 0]]", "speed = 2")),
+            Err(ClassError::Toml(_))
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+use rmpv::Value;
+use serde::Deserialize;
+use std::fmt::{Display, Formatter, Result as FormatResult};
+use std::fs;
+use std::io;
+
+use crate::entities::{EntityId, EntityKind};
+use crate::game_map::{GameMap, IndexType};
+
+/// File the round config is loaded from
+const ROUNDS_PATH: &str = "rounds.toml";
+
+lazy_static::lazy_static! {
+    pub static ref ROUND_CONFIG: RoundConfig = {
+        match load_round_config(ROUNDS_PATH) {
+            Ok(config) => config,
+            Err(err) => panic!("Invalid round config in {}: {}", ROUNDS_PATH, err),
+        }
+    };
+}
+
+/// Errors that occur when loading the round config
+#[derive(Debug)]
+pub enum RoundConfigError {
+    Io(io::Error),
+    Toml(toml::de::Error),
+
+    /// Rounds need time to play in
+    NoDuration,
+
+    /// The finale has to fit in the round
+    FinaleTooLong,
+
+    /// The map can't shrink below one index around the center
+    NoFinaleSize,
+
+    /// The leaderboard needs at least one spot
+    NoLeaderboard,
+}
+
+impl Display for RoundConfigError {
+    fn fmt(&self, f: &mut Formatter) -> FormatResult {
+        match self {
+            RoundConfigError::Io(err) => write!(f, "{}", err),
+            RoundConfigError::Toml(err) => write!(f, "{}", err),
+            RoundConfigError::NoDuration => write!(f, "rounds have no duration"),
+            RoundConfigError::FinaleTooLong => write!(f, "finale is longer than the round"),
+            RoundConfigError::NoFinaleSize => write!(f, "finale size is below 1"),
+            RoundConfigError::NoLeaderboard => write!(f, "leaderboard has no spots"),
+        }
+    }
+}
+
+/// Controls how long rounds last and how many players the leaderboard shows
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoundConfig {
+    /// Plays in rounds if true; the game never ends otherwise
+    enabled: bool,
+
+    /// Length of the round in seconds, including the finale
+    duration: u64,
+
+    /// Seconds at the end of the round in which the map shrinks
+    finale: u64,
+
+    /// Size the map shrinks to by the end of the finale
+    finale_size: IndexType,
+
+    /// Seconds between rounds
+    intermission: u64,
+
+    /// Number of players on the leaderboard
+    pub leaderboard_size: usize,
+}
+
+impl RoundConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+}
+
+/* Loading */
+pub fn load_round_config(path: &str) -> Result<RoundConfig, RoundConfigError> {
+    parse_round_config(&fs::read_to_string(path).map_err(RoundConfigError::Io)?)
+}
+
+/// Parses and validates the round config.
+pub fn parse_round_config(source: &str) -> Result<RoundConfig, RoundConfigError> {
+    let config = toml::from_str::<RoundConfig>(source).map_err(RoundConfigError::Toml)?;
+
+    if config.duration == 0 {
+        return Err(RoundConfigError::NoDuration);
+    }
+    if config.finale > config.duration {
+        return Err(RoundConfigError::FinaleTooLong);
+    }
+    if config.finale_size < 1 {
+        return Err(RoundConfigError::NoFinaleSize);
+    }
+    if config.leaderboard_size == 0 {
+        return Err(RoundConfigError::NoLeaderboard);
+    }
+
+    Ok(config)
+}
+
+/* Rounds */
+/// Something that happened to the round during an update.
+#[derive(Debug, PartialEq)]
+pub enum RoundEvent {
+    /// Time ran out; the results should be sent and the map reset
+    Ended,
+
+    /// The intermission is over and players can spawn again
+    Started,
+}
+
+#[derive(Debug, PartialEq)]
+enum RoundPhase {
+    Playing,
+    Intermission,
+}
+
+/// Keeps track of the current round.
+#[derive(Debug)]
+pub struct Round {
+    config: &'static RoundConfig,
+
+    /// Counts up from 1
+    number: u64,
+
+    phase: RoundPhase,
+
+    /// When the phase ends in milliseconds
+    phase_end: u64,
+
+    /// Size of the map when the finale started
+    finale_start_size: Option<IndexType>,
+}
+
+impl Round {
+    pub fn new(config: &'static RoundConfig, now: u64) -> Round {
+        Round {
+            config,
+            number: 1,
+            phase: RoundPhase::Playing,
+            phase_end: now + config.duration * 1000,
+            finale_start_size: None,
+        }
+    }
+
+    pub fn number(&self) -> u64 {
+        self.number
+    }
+
+    /// If players can be in the map.
+    pub fn is_playing(&self) -> bool {
+        self.phase == RoundPhase::Playing
+    }
+
+    /// If the map is shrinking at the end of the round.
+    pub fn is_finale(&self, now: u64) -> bool {
+        let finale = self.config.finale * 1000;
+        self.is_playing() && finale > 0 && now + finale >= self.phase_end
+    }
+
+    /// Moves on to the next phase once the current one is over.
+    pub fn update(&mut self, now: u64) -> Option<RoundEvent> {
+        if now < self.phase_end {
+            return None;
+        }
+
+        match self.phase {
+            RoundPhase::Playing => {
+                self.phase = RoundPhase::Intermission;
+                self.phase_end = now + self.config.intermission * 1000;
+                self.finale_start_size = None;
+                Some(RoundEvent::Ended)
+            }
+            RoundPhase::Intermission => {
+                self.number += 1;
+                self.phase = RoundPhase::Playing;
+                self.phase_end = now + self.config.duration * 1000;
+                Some(RoundEvent::Started)
+            }
+        }
+    }
+
+    /// Largest size the map can be at this point of the finale; `None` before the finale. The
+    /// limit shrinks evenly from the size of the map when the finale starts.
+    pub fn map_size_limit(&mut self, now: u64, map_size: IndexType) -> Option<IndexType> {
+        if !self.is_finale(now) {
+            return None;
+        }
+
+        let start_size = *self.finale_start_size.get_or_insert(map_size);
+        let end_size = IndexType::min(self.config.finale_size, start_size);
+        let finale = self.config.finale * 1000;
+        let time_left = self.phase_end.saturating_sub(now);
+        let shrink = (start_size - end_size) as u64 * (finale - time_left) / finale;
+        Some(start_size - shrink as IndexType)
+    }
+
+    pub fn serialize(&self, now: u64) -> Value {
+        Value::Map(vec![
+            ("number".into(), self.number.into()),
+            ("playing".into(), self.is_playing().into()),
+            ("endsIn".into(), self.phase_end.saturating_sub(now).into()),
+        ])
+    }
+}
+
+/* Standings */
+/// The players in the map from the most to the fewest points.
+pub fn standings(map: &GameMap) -> Vec<(EntityId, &str, u64)> {
+    let mut standings = map
+        .entities()
+        .iter()
+        .filter_map(|(id, entity)| match entity.kind() {
+            EntityKind::Player(player) => Some((*id, player.username(), *player.point_count())),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    standings.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+    standings
+}
+
+/// Serializes the top of the standings as `[id, username, points]`.
+pub fn serialize_standings(standings: &[(EntityId, &str, u64)]) -> Value {
+    standings
+        .iter()
+        .take(ROUND_CONFIG.leaderboard_size)
+        .map(|&(id, username, points)| {
+            Value::Array(vec![id.into(), username.into(), points.into()])
+        })
+        .collect::<Vec<Value>>()
+        .into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CONFIG: &str = "
+        enabled = true
+        duration = 100
+        finale = 10
+        finale_size = 3
+        intermission = 5
+        leaderboard_size = 10
+    ";
+
+    fn config(source: &str) -> &'static RoundConfig {
+        Box::leak(Box::new(parse_round_config(source).unwrap()))
+    }
+
+    #[test]
+    fn test_parse_round_config() {
+        assert!(parse_round_config(CONFIG).is_ok());
+        assert!(matches!(
+            parse_round_config(&CONFIG.replace("duration = 100", "duration = 0")),
+            Err(RoundConfigError::NoDuration)
+        ));
+        assert!(matches!(
+            parse_round_config(&CONFIG.replace("finale = 10", "finale = 101")),
+            Err(RoundConfigError::FinaleTooLong)
+        ));
+        assert!(matches!(
+            parse_round_config(&CONFIG.replace("finale_size = 3", "finale_size = 0")),
+            Err(RoundConfigError::NoFinaleSize)
+        ));
+        assert!(matches!(
+            parse_round_config(&CONFIG.replace("finale_size = 3", "finale_size = -2")),
+            Err(RoundConfigError::NoFinaleSize)
+        ));
+        assert!(matches!(
+            parse_round_config(&CONFIG.replace("leaderboard_size = 10", "leaderboard_size = 0")),
+            Err(RoundConfigError::NoLeaderboard)
+        ));
+        assert!(matches!(
+            parse_round_config(&format!("{}\nunknown = 1", CONFIG)),
+            Err(RoundConfigError::Toml(_))
+        ));
+    }
+
+    #[test]
+    fn test_map_size_limit() {
+        let mut round = Round::new(config(CONFIG), 0);
+
+        // No limit before the finale
+        assert_eq!(round.map_size_limit(89_999, 23), None);
+
+        // Shrinks evenly from the size when the finale started
+        assert_eq!(round.map_size_limit(90_000, 23), Some(23));
+        assert_eq!(round.map_size_limit(95_000, 40), Some(13));
+        assert_eq!(round.map_size_limit(100_000, 13), Some(3));
+
+        // The map doesn't grow if it was already smaller than the finale size
+        let mut round = Round::new(config(CONFIG), 0);
+        assert_eq!(round.map_size_limit(90_000, 2), Some(2));
+        assert_eq!(round.map_size_limit(100_000, 2), Some(2));
+
+        // The limit starts over with the next round
+        let mut round = Round::new(config(CONFIG), 0);
+        round.map_size_limit(95_000, 23);
+        assert_eq!(round.update(100_000), Some(RoundEvent::Ended));
+        assert_eq!(round.map_size_limit(102_000, 40), None);
+        assert_eq!(round.update(105_000), Some(RoundEvent::Started));
+        assert_eq!(round.map_size_limit(195_000, 40), Some(40));
+    }
+}
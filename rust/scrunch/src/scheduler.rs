@@ -0,0 +1,162 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Ticks per second used when `TICK_RATE` isn't set (one tick every 200 ms)
+const DEFAULT_TICK_RATE: u32 = 5;
+
+/// Number of ticks between metric reports
+const REPORT_INTERVAL: u64 = 300;
+
+/// Reads the number of ticks per second from `TICK_RATE`.
+pub fn tick_rate() -> u32 {
+    match std::env::var("TICK_RATE") {
+        Ok(rate) => match rate.parse::<u32>() {
+            Ok(rate) if rate > 0 => rate,
+            _ => panic!("invalid TICK_RATE {:?}", rate),
+        },
+        Err(_) => DEFAULT_TICK_RATE,
+    }
+}
+
+/// Timing of the ticks since the last report.
+#[derive(Debug, Default)]
+pub struct TickMetrics {
+    /// Number of ticks run
+    ticks: u64,
+
+    /// Ticks that took longer than the tick period
+    overruns: u64,
+
+    /// Ticks that were dropped because the loop fell a full tick behind
+    skipped: u64,
+
+    /// Total and longest time spent updating the game
+    total_update: Duration,
+    max_update: Duration,
+}
+
+impl TickMetrics {
+    fn record(&mut self, update: Duration) {
+        self.ticks += 1;
+        self.total_update += update;
+        self.max_update = self.max_update.max(update);
+    }
+
+    fn report(&self) {
+        let ticks = self.ticks.max(1) as u32;
+        println!(
+            "Ticks: {} ({} overruns, {} skipped), update avg {:?} max {:?}",
+            self.ticks,
+            self.overruns,
+            self.skipped,
+            self.total_update / ticks,
+            self.max_update
+        );
+    }
+}
+
+/// Runs the game loop at a fixed rate. The time spent updating is taken out of the sleep, so ticks
+/// start on a steady schedule regardless of how long they take.
+pub struct TickScheduler {
+    /// Time between the start of each tick
+    period: Duration,
+
+    /// When the next tick is meant to start
+    next_tick: Instant,
+
+    metrics: TickMetrics,
+}
+
+impl TickScheduler {
+    pub fn new(tick_rate: u32) -> TickScheduler {
+        TickScheduler {
+            period: Duration::from_secs(1) / tick_rate,
+            next_tick: Instant::now(),
+            metrics: TickMetrics::default(),
+        }
+    }
+
+    /// Saves how long the update took.
+    pub fn record(&mut self, update: Duration) {
+        self.metrics.record(update);
+    }
+
+    /// Sleeps until the next tick is due.
+    pub fn wait(&mut self) {
+        if let Some(sleep) = self.advance(Instant::now()) {
+            thread::sleep(sleep);
+        }
+
+        if self.metrics.ticks >= REPORT_INTERVAL {
+            self.metrics.report();
+            self.metrics = TickMetrics::default();
+        }
+    }
+
+    /// Moves on to the next tick and returns how long to sleep until it's due, if it isn't already.
+    fn advance(&mut self, now: Instant) -> Option<Duration> {
+        self.next_tick += self.period;
+
+        if now < self.next_tick {
+            Some(self.next_tick - now)
+        } else {
+            self.metrics.overruns += 1;
+
+            // Drop the ticks that were missed instead of running them back to back
+            let behind = now - self.next_tick;
+            let missed = (behind.as_nanos() / self.period.as_nanos()) as u64;
+            if missed > 0 {
+                self.metrics.skipped += missed;
+                self.next_tick = now;
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const PERIOD: Duration = Duration::from_millis(200);
+
+    #[test]
+    fn test_advance() {
+        let start = Instant::now();
+        let mut scheduler = TickScheduler::new(5);
+        scheduler.next_tick = start;
+
+        // A quick update sleeps for the rest of the period
+        let sleep = scheduler.advance(start + Duration::from_millis(50));
+        assert_eq!(sleep, Some(Duration::from_millis(150)));
+
+        // A slow update runs the next tick right away, without skipping any
+        let sleep = scheduler.advance(start + Duration::from_millis(450));
+        assert_eq!(sleep, None);
+        assert_eq!(scheduler.next_tick, start + PERIOD * 2);
+        assert_eq!(scheduler.metrics.overruns, 1);
+        assert_eq!(scheduler.metrics.skipped, 0);
+
+        // The following tick goes back to the schedule
+        let sleep = scheduler.advance(start + Duration::from_millis(500));
+        assert_eq!(sleep, Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_skip() {
+        let start = Instant::now();
+        let mut scheduler = TickScheduler::new(5);
+        scheduler.next_tick = start;
+
+        // Falling more than a full tick behind drops the missed ticks
+        let sleep = scheduler.advance(start + Duration::from_millis(750));
+        assert_eq!(sleep, None);
+        assert_eq!(scheduler.metrics.overruns, 1);
+        assert_eq!(scheduler.metrics.skipped, 2);
+
+        // The schedule starts over from when the loop caught up
+        assert_eq!(scheduler.next_tick, start + Duration::from_millis(750));
+        let sleep = scheduler.advance(start + Duration::from_millis(800));
+        assert_eq!(sleep, Some(Duration::from_millis(150)));
+    }
+}
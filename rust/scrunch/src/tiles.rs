@@ -0,0 +1,167 @@
+use rmpv::Value;
+use std::fmt::Debug;
+use std::ops::AddAssign;
+
+use crate::entities::{KindFlag, Player};
+use crate::incremental_value::IncrementalValue;
+
+/*** Base ***/
+/// What happens to a player that moves onto a tile
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnterAction {
+    /// The tile is used up and removed to make room for the player
+    Consume,
+
+    /// The player is squished
+    Squish,
+}
+
+/// Behaviour of the entities in the map that aren't players. A new kind of tile implements this
+/// and adds its own `KindFlag` for the client.
+pub trait Tile: Debug + Send {
+    fn kind_flag(&self) -> KindFlag;
+
+    /// What happens to a player that moves onto the tile. Bots check this when planning moves.
+    fn enter_action(&self) -> EnterAction;
+
+    /// Called when a player moves onto the tile, before the enter action is carried out.
+    fn on_enter(&mut self, _player: &mut Player) {}
+
+    /// Called every tick.
+    fn on_tick(&mut self, _now: u64) {}
+
+    /// Milliseconds the tile lasts after it spawns; `None` if it stays until something removes it.
+    fn lifetime(&self) -> Option<u64> {
+        None
+    }
+
+    /// If the tile has outlived its lifetime and should be removed.
+    fn is_expired(&self, spawn_time: u64, now: u64) -> bool {
+        self.lifetime()
+            .is_some_and(|lifetime| spawn_time + lifetime <= now)
+    }
+
+    /// Points a player gets for moving onto the tile; bots go after tiles worth points.
+    fn points(&self) -> u64 {
+        0
+    }
+
+    /// Adds points dropped by a squished player; false if the tile can't hold points.
+    fn add_points(&mut self, _points: u64) -> bool {
+        false
+    }
+
+    fn serialize(&self, _init: bool) -> Value {
+        Value::Nil
+    }
+
+    /// Called when checking to see if the tile needs to be send to the client
+    fn needs_update(&self) -> bool {
+        false
+    }
+
+    /// Called after an update occurred
+    fn updated(&mut self) {}
+}
+
+/*** Gap ***/
+/// Left behind by a player when it moves; squishes players that jump in.
+#[derive(Debug)]
+pub struct Gap;
+
+impl Gap {
+    /// Milliseconds before the gap fills back in
+    const LIFETIME: u64 = 7500;
+}
+
+impl Tile for Gap {
+    fn kind_flag(&self) -> KindFlag {
+        KindFlag::Gap
+    }
+
+    fn enter_action(&self) -> EnterAction {
+        EnterAction::Squish
+    }
+
+    fn lifetime(&self) -> Option<u64> {
+        Some(Gap::LIFETIME)
+    }
+}
+
+/*** Point orb ***/
+/// Gives its points to the player that takes it.
+#[derive(Debug)]
+pub struct PointOrb {
+    points: IncrementalValue<u64>,
+}
+
+impl PointOrb {
+    pub fn new(points: u64) -> PointOrb {
+        PointOrb {
+            points: IncrementalValue::new(points),
+        }
+    }
+}
+
+impl Tile for PointOrb {
+    fn kind_flag(&self) -> KindFlag {
+        KindFlag::PointOrb
+    }
+
+    fn enter_action(&self) -> EnterAction {
+        EnterAction::Consume
+    }
+
+    fn on_enter(&mut self, player: &mut Player) {
+        player.give_points(*self.points.get());
+    }
+
+    fn points(&self) -> u64 {
+        *self.points.get()
+    }
+
+    fn add_points(&mut self, points: u64) -> bool {
+        self.points.get_mut().add_assign(points);
+        true
+    }
+
+    fn serialize(&self, _init: bool) -> Value {
+        Value::Array(vec![Value::from(*self.points.get())])
+    }
+
+    fn needs_update(&self) -> bool {
+        self.points.is_changed()
+    }
+
+    fn updated(&mut self) {
+        self.points.updated()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::player_classes::random_class;
+
+    #[test]
+    fn test_point_orb() {
+        let mut player = Player::new_bot("bot".to_string(), random_class(u64::MAX).clone());
+        let points = *player.point_count();
+
+        // Dropped points pile up in the orb, and the player taking it gets all of them
+        let mut orb = PointOrb::new(4);
+        assert!(orb.add_points(4));
+        assert_eq!(orb.points(), 8);
+        orb.on_enter(&mut player);
+        assert_eq!(*player.point_count(), points + 8);
+        assert!(!orb.is_expired(0, u64::MAX));
+    }
+
+    #[test]
+    fn test_gap_lifetime() {
+        let mut gap = Gap;
+        assert!(!gap.add_points(4));
+        assert!(!gap.is_expired(1000, 1000 + Gap::LIFETIME - 1));
+        assert!(gap.is_expired(1000, 1000 + Gap::LIFETIME));
+    }
+}
@@ -1,8 +1,5 @@
 use rand;
-use std::{
-    ops::AddAssign,
-    time::{SystemTime, UNIX_EPOCH},
-};
+use std::{ops::AddAssign, time::Instant};
 
 /*** Counter ***/
 /// Used to count items.
@@ -79,12 +76,15 @@ macro_rules! silence {
 }
 
 /*** Time utils ***/
+lazy_static::lazy_static! {
+    /// When the clock started; read on first use
+    static ref CLOCK_START: Instant = Instant::now();
+}
+
+/// Milliseconds on a monotonic clock. Unlike the system time it never jumps backwards, so it's
+/// only meaningful when compared with other values from this function.
 pub fn time_milliseconds() -> u64 {
-    let start = SystemTime::now();
-    let since_the_epoch = start
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards");
-    return since_the_epoch.as_secs() * 1000 + since_the_epoch.subsec_nanos() as u64 / 1_000_000;
+    CLOCK_START.elapsed().as_millis() as u64
 }
 
 /*** Collection utils ***/